@@ -0,0 +1,209 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::meta::ParseNestedMeta;
+use syn::{parse_macro_input, token, Attribute, Data, DeriveInput, Error, Expr, Fields, GenericArgument, LitStr, PathArguments, Token, Type};
+
+// Derives `png_db::HasSchema`, mapping each serialized field to a png-db type:
+// strings, integers, floats and booleans map directly, `Option<T>` becomes nullable,
+// sequences become `array`, maps become `object` and anything else `any`. Fields
+// without `Option` or `#[serde(default)]` are required. `#[png_db(type = "...")]`
+// overrides the inferred type and `#[png_db(skip)]` leaves a field out.
+#[proc_macro_derive(HasSchema, attributes(png_db))]
+pub fn derive_has_schema(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new_spanned(&input.ident, "HasSchema can only be derived for structs"));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(Error::new_spanned(&input.ident, "HasSchema requires a struct with named fields"));
+    };
+
+    let container = Attrs::parse(&input.attrs)?;
+    let mut entries = Vec::new();
+
+    for field in &fields.named {
+        let attrs = Attrs::parse(&field.attrs)?;
+        if attrs.skip {
+            continue;
+        }
+        if attrs.flatten {
+            return Err(Error::new_spanned(field, "png-db cannot infer columns for #[serde(flatten)] fields; add #[png_db(skip)]"));
+        }
+
+        let ident = field.ident.as_ref().map(ToString::to_string).unwrap_or_default();
+        let ident = ident.strip_prefix("r#").unwrap_or(&ident);
+        let name = match attrs.rename {
+            Some(name) => name,
+            None => rename(ident, container.rename_all.as_deref(), field)?,
+        };
+        let field_type = match attrs.field_type {
+            Some(field_type) => field_type,
+            None => infer_type(&field.ty, attrs.default || container.default),
+        };
+
+        entries.push(quote! { (#name.to_string(), #field_type.to_string()) });
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::png_db::HasSchema for #ident #ty_generics #where_clause {
+            fn schema() -> ::png_db::Schema {
+                ::png_db::Schema::new(::std::collections::HashMap::from([#(#entries),*]))
+            }
+        }
+    })
+}
+
+// The serde and png_db attributes that affect the generated schema
+#[derive(Default)]
+struct Attrs {
+    rename: Option<String>,
+    rename_all: Option<String>,
+    field_type: Option<String>,
+    default: bool,
+    skip: bool,
+    flatten: bool,
+}
+
+impl Attrs {
+    fn parse(attributes: &[Attribute]) -> syn::Result<Self> {
+        let mut attrs = Attrs::default();
+
+        for attribute in attributes {
+            if attribute.path().is_ident("serde") {
+                attribute.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("rename") && meta.input.peek(Token![=]) {
+                        attrs.rename = Some(meta.value()?.parse::<LitStr>()?.value());
+                    } else if meta.path.is_ident("rename_all") && meta.input.peek(Token![=]) {
+                        attrs.rename_all = Some(meta.value()?.parse::<LitStr>()?.value());
+                    } else if meta.path.is_ident("default") {
+                        attrs.default = true;
+                        skip_meta(&meta)?;
+                    } else if meta.path.is_ident("skip") || meta.path.is_ident("skip_serializing") {
+                        attrs.skip = true;
+                    } else if meta.path.is_ident("flatten") {
+                        attrs.flatten = true;
+                    } else {
+                        skip_meta(&meta)?;
+                    }
+                    Ok(())
+                })?;
+            } else if attribute.path().is_ident("png_db") {
+                attribute.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("type") {
+                        attrs.field_type = Some(meta.value()?.parse::<LitStr>()?.value());
+                    } else if meta.path.is_ident("skip") {
+                        attrs.skip = true;
+                    } else {
+                        return Err(meta.error("expected `type = \"...\"` or `skip`"));
+                    }
+                    Ok(())
+                })?;
+            }
+        }
+
+        Ok(attrs)
+    }
+}
+
+// Consumes the value of a serde option that does not affect the schema
+fn skip_meta(meta: &ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(Token![=]) {
+        meta.value()?.parse::<Expr>()?;
+    } else if meta.input.peek(token::Paren) {
+        meta.parse_nested_meta(|nested| skip_meta(&nested))?;
+    }
+    Ok(())
+}
+
+// Applies a serde `rename_all` rule to a snake_case field name
+fn rename(field: &str, rule: Option<&str>, span: &syn::Field) -> syn::Result<String> {
+    let capitalize = |word: &str| {
+        let mut chars = word.chars();
+        chars.next().map(|first| first.to_uppercase().chain(chars).collect::<String>()).unwrap_or_default()
+    };
+
+    Ok(match rule {
+        None | Some("snake_case") => field.to_string(),
+        Some("lowercase") => field.to_lowercase(),
+        Some("UPPERCASE") => field.to_uppercase(),
+        Some("PascalCase") => field.split('_').map(capitalize).collect(),
+        Some("camelCase") => {
+            let pascal: String = field.split('_').map(capitalize).collect();
+            let mut chars = pascal.chars();
+            chars.next().map(|first| first.to_lowercase().chain(chars).collect()).unwrap_or_default()
+        }
+        Some("SCREAMING_SNAKE_CASE") => field.to_uppercase(),
+        Some("kebab-case") => field.replace('_', "-"),
+        Some("SCREAMING-KEBAB-CASE") => field.to_uppercase().replace('_', "-"),
+        Some(other) => return Err(Error::new_spanned(span, format!("unsupported rename_all rule '{}'", other))),
+    })
+}
+
+fn infer_type(ty: &Type, has_default: bool) -> String {
+    match option_inner(ty) {
+        Some(inner) => format!("{}?", base_type(inner)),
+        None if has_default => base_type(ty).to_string(),
+        None => format!("{}!", base_type(ty)),
+    }
+}
+
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Option" {
+        return None;
+    }
+    first_type_argument(&segment.arguments)
+}
+
+fn first_type_argument(arguments: &PathArguments) -> Option<&Type> {
+    let PathArguments::AngleBracketed(arguments) = arguments else {
+        return None;
+    };
+    arguments.args.iter().find_map(|arg| match arg {
+        GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    })
+}
+
+fn base_type(ty: &Type) -> &'static str {
+    match ty {
+        Type::Reference(reference) => base_type(&reference.elem),
+        Type::Paren(paren) => base_type(&paren.elem),
+        Type::Group(group) => base_type(&group.elem),
+        Type::Array(_) | Type::Slice(_) => "array",
+        Type::Tuple(tuple) if tuple.elems.is_empty() => "null",
+        Type::Tuple(_) => "array",
+        Type::Path(path) => {
+            let Some(segment) = path.path.segments.last() else {
+                return "any";
+            };
+            match segment.ident.to_string().as_str() {
+                "String" | "str" | "char" => "string",
+                "i8" | "i16" | "i32" | "i64" | "i128" | "isize" | "u8" | "u16" | "u32" | "u64" | "u128" | "usize" => "integer",
+                "f32" | "f64" => "float",
+                "bool" => "boolean",
+                "Vec" | "VecDeque" | "LinkedList" | "HashSet" | "BTreeSet" => "array",
+                "HashMap" | "BTreeMap" | "Map" => "object",
+                "Box" | "Rc" | "Arc" | "Cow" | "Option" => {
+                    first_type_argument(&segment.arguments).map(base_type).unwrap_or("any")
+                }
+                _ => "any",
+            }
+        }
+        _ => "any",
+    }
+}
@@ -0,0 +1,178 @@
+use crate::format::{Metadata, METADATA_KEYWORD};
+use crate::{itxt_chunk, metadata_chunk, DataRow, PngDbError, Result, METADATA_TEXT_LEN};
+use flate2::read::ZlibDecoder;
+use png::text_metadata::EncodableTextChunk;
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+// The IEND chunk every PNG ends with: no data, then its CRC
+const IEND: [u8; 12] = [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82];
+const SIGNATURE_LEN: u64 = 8;
+// Chunk length, type and CRC around the data
+const CHUNK_OVERHEAD: u64 = 12;
+// Larger iTXt chunks are rows, not the metadata, and are skipped unread
+const MAX_METADATA_CHUNK_LEN: u32 = 4096;
+
+// The metadata chunk of a saved file, where appends rewrite it
+pub(crate) struct MetadataChunk {
+    offset: u64,
+    len: u64,
+    pub metadata: Metadata,
+}
+
+// What appending a row needs to know about a file, read without decoding its rows
+pub(crate) struct Summary {
+    pub width: u32,
+    pub height: u32,
+    pub found: MetadataChunk,
+    pub schema: Option<String>,
+    pub config: Option<String>,
+    // The highest `seq` among the rows in each occupied cell
+    pub cells: HashMap<(u32, u32), u32>,
+}
+
+// Finds the metadata chunk among the chunks ahead of the image data. `None` if
+// it has no room to be rewritten in place: missing, compressed as saves did
+// before appends existed, or not padded, as by other writers.
+pub(crate) fn find_metadata<S: Read + Seek>(stream: &mut S) -> Result<Option<MetadataChunk>> {
+    let mut found = None;
+    walk(stream, |stream, kind, offset, data_len| {
+        if &kind == b"IDAT" {
+            return Ok(false);
+        }
+        if &kind != b"iTXt" || data_len > MAX_METADATA_CHUNK_LEN {
+            return Ok(true);
+        }
+        let data = read_data(stream, data_len as usize)?;
+        match itxt_fields(&data) {
+            Some((METADATA_KEYWORD, false, text)) => {
+                if text.len() >= METADATA_TEXT_LEN {
+                    let metadata = serde_json::from_slice(text)?;
+                    found = Some(MetadataChunk { offset, len: u64::from(data_len) + CHUNK_OVERHEAD, metadata });
+                }
+                Ok(false)
+            }
+            Some((METADATA_KEYWORD, true, _)) => Ok(false),
+            _ => Ok(true),
+        }
+    })?;
+    Ok(found)
+}
+
+// Reads the image size, the metadata, schema and config chunks and the keywords
+// of the rows, skipping everything else. `None` where `find_metadata` is.
+pub(crate) fn summarize<S: Read + Seek>(stream: &mut S) -> Result<Option<Summary>> {
+    let Some(found) = find_metadata(stream)? else {
+        return Ok(None);
+    };
+    let mut summary = Summary { width: 0, height: 0, found, schema: None, config: None, cells: HashMap::new() };
+    walk(stream, |stream, kind, _, data_len| {
+        if &kind == b"IHDR" {
+            let data = read_data(stream, 8)?;
+            summary.width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+            summary.height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        } else if &kind == b"iTXt" {
+            // A keyword is at most 79 bytes, then a NUL
+            let mut data = read_data(stream, (data_len as usize).min(80))?;
+            let keyword = String::from_utf8_lossy(data.split(|&byte| byte == 0).next().unwrap_or_default()).into_owned();
+            if keyword == "schema" || keyword == "config" {
+                data.extend(read_data(stream, data_len as usize - data.len())?);
+                let text = match itxt_fields(&data) {
+                    Some((_, compressed, text)) => decode_text(compressed, text)?,
+                    None => return Err(PngDbError::DatabaseError(format!("Damaged {} chunk", keyword))),
+                };
+                if keyword == "schema" {
+                    summary.schema = Some(text);
+                } else {
+                    summary.config = Some(text);
+                }
+            } else if let Some((x, y, seq)) = DataRow::parse_keyword(&keyword) {
+                let highest = summary.cells.entry((x, y)).or_insert(seq);
+                *highest = (*highest).max(seq);
+            }
+        }
+        Ok(true)
+    })?;
+    Ok(Some(summary))
+}
+
+// Hands the type, offset and data length of each chunk up to IEND to `visit`,
+// with the stream at the chunk's data, until `visit` returns false
+fn walk<S, F>(stream: &mut S, mut visit: F) -> Result<()>
+where
+    S: Read + Seek,
+    F: FnMut(&mut S, [u8; 4], u64, u32) -> Result<bool>,
+{
+    let mut offset = SIGNATURE_LEN;
+    loop {
+        stream.seek(SeekFrom::Start(offset))?;
+        let header = read_data(stream, 8)?;
+        let data_len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let kind = [header[4], header[5], header[6], header[7]];
+        if &kind == b"IEND" || !visit(stream, kind, offset, data_len)? {
+            return Ok(());
+        }
+        offset += u64::from(data_len) + CHUNK_OVERHEAD;
+    }
+}
+
+fn read_data<S: Read>(stream: &mut S, len: usize) -> Result<Vec<u8>> {
+    let mut data = vec![0u8; len];
+    stream.read_exact(&mut data)?;
+    Ok(data)
+}
+
+// Splits iTXt data into its keyword, whether the text is compressed, and the
+// text. The keyword, compression flag and method come first, then a language
+// tag and translated keyword, each NUL-terminated.
+fn itxt_fields(data: &[u8]) -> Option<(&str, bool, &[u8])> {
+    let (keyword, rest) = data.split_at(data.iter().position(|&byte| byte == 0)?);
+    // `rest` starts at the keyword's NUL
+    let (&[_, flag, _method], rest) = rest.split_first_chunk::<3>()?;
+    let mut fields = rest.splitn(3, |&byte| byte == 0);
+    let (_language, _translated, text) = (fields.next()?, fields.next()?, fields.next()?);
+    Some((std::str::from_utf8(keyword).ok()?, flag != 0, text))
+}
+
+fn decode_text(compressed: bool, text: &[u8]) -> Result<String> {
+    let mut decoded = String::new();
+    if compressed {
+        ZlibDecoder::new(text).read_to_string(&mut decoded)?;
+    } else {
+        decoded = String::from_utf8_lossy(text).into_owned();
+    }
+    Ok(decoded)
+}
+
+// Writes `rows` as (keyword, text) chunks ahead of IEND, then rewrites the
+// metadata chunk with `metadata`. Nothing is written if the file does not end
+// with IEND or `metadata` does not fit the chunk.
+pub(crate) fn append_rows<S: Read + Write + Seek>(
+    stream: &mut S,
+    found: &MetadataChunk,
+    metadata: &Metadata,
+    rows: &[(String, String)],
+) -> Result<()> {
+    let mut rewritten = Vec::new();
+    metadata_chunk(serde_json::to_string(metadata)?).encode(&mut rewritten)?;
+    if rewritten.len() as u64 != found.len {
+        return Err(PngDbError::DatabaseError("Metadata no longer fits its chunk; save the file in full".to_string()));
+    }
+
+    let end = stream.seek(SeekFrom::End(-(IEND.len() as i64)))?;
+    let mut trailer = [0u8; 12];
+    stream.read_exact(&mut trailer)?;
+    if trailer != IEND {
+        return Err(PngDbError::DatabaseError("File does not end with an IEND chunk".to_string()));
+    }
+
+    stream.seek(SeekFrom::Start(end))?;
+    for (keyword, text) in rows {
+        itxt_chunk(keyword, text.clone()).encode(stream)?;
+    }
+    stream.write_all(&IEND)?;
+    stream.seek(SeekFrom::Start(found.offset))?;
+    stream.write_all(&rewritten)?;
+    stream.flush()?;
+    Ok(())
+}
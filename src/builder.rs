@@ -0,0 +1,576 @@
+use crate::expr::{ArithOp, Expr};
+use crate::path::FieldPath;
+use crate::spatial::Shape;
+use crate::query::{
+    aggregate_name, AggregateFunc, ComparisonOp, Condition, Nearest, OrderBy, OrderKey, Projection, Query, Select,
+    SelectItem,
+};
+use serde_json::Value;
+use std::fmt;
+
+// The left-hand side of a condition being built, e.g. `Query::field("age")` in
+// `Query::field("age").gte(30)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operand {
+    target: Target,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Target {
+    Field(FieldPath),
+    Aggregate { func: AggregateFunc, column: Option<FieldPath> },
+    Any(FieldPath),
+    Expr(Expr),
+}
+
+impl Operand {
+    pub fn eq(self, value: impl Into<Value>) -> Query {
+        self.compare(ComparisonOp::Equal, value.into())
+    }
+
+    pub fn ne(self, value: impl Into<Value>) -> Query {
+        self.compare(ComparisonOp::NotEqual, value.into())
+    }
+
+    pub fn gt(self, value: impl Into<Value>) -> Query {
+        self.compare(ComparisonOp::GreaterThan, value.into())
+    }
+
+    pub fn lt(self, value: impl Into<Value>) -> Query {
+        self.compare(ComparisonOp::LessThan, value.into())
+    }
+
+    pub fn gte(self, value: impl Into<Value>) -> Query {
+        self.compare(ComparisonOp::GreaterThanOrEqual, value.into())
+    }
+
+    pub fn lte(self, value: impl Into<Value>) -> Query {
+        self.compare(ComparisonOp::LessThanOrEqual, value.into())
+    }
+
+    pub fn like(self, pattern: &str) -> Query {
+        self.compare(ComparisonOp::Like, pattern.into())
+    }
+
+    pub fn ilike(self, pattern: &str) -> Query {
+        self.compare(ComparisonOp::ILike, pattern.into())
+    }
+
+    // `~`, true if the regular expression matches anywhere in the value
+    pub fn matches(self, regex: &str) -> Query {
+        self.compare(ComparisonOp::Regex, regex.into())
+    }
+
+    pub fn is_in<V: Into<Value>>(self, values: impl IntoIterator<Item = V>) -> Query {
+        let values = values.into_iter().map(Into::into).collect();
+        Query::Condition(self.predicate(|field| Condition::In { field, values }))
+    }
+
+    pub fn between(self, low: impl Into<Value>, high: impl Into<Value>) -> Query {
+        let (low, high) = (low.into(), high.into());
+        Query::Condition(self.predicate(|field| Condition::Between { field, low, high }))
+    }
+
+    pub fn is_null(self) -> Query {
+        Query::Condition(self.predicate(|field| Condition::IsNull { field, negated: false }))
+    }
+
+    pub fn is_not_null(self) -> Query {
+        Query::Condition(self.predicate(|field| Condition::IsNull { field, negated: true }))
+    }
+
+    pub fn is_missing(self) -> Query {
+        Query::Condition(self.predicate(|field| Condition::IsMissing { field, negated: false }))
+    }
+
+    pub fn is_not_missing(self) -> Query {
+        Query::Condition(self.predicate(|field| Condition::IsMissing { field, negated: true }))
+    }
+
+    // An array element equal to `value`, or a substring of a string
+    pub fn contains(self, value: impl Into<Value>) -> Query {
+        let value = value.into();
+        Query::Condition(self.predicate(|field| Condition::Contains { field, value }))
+    }
+
+    pub fn has_key(self, key: &str) -> Query {
+        Query::Condition(self.predicate(|field| Condition::HasKey { field, key: key.to_string() }))
+    }
+
+    // `@>`: the value structurally contains `json`
+    pub fn contains_json(self, json: Value) -> Query {
+        Query::Condition(self.predicate(|field| Condition::JsonContains { field, value: json }))
+    }
+
+    // `<@`: the value is structurally contained in `json`
+    pub fn contained_in_json(self, json: Value) -> Query {
+        Query::Condition(self.predicate(|field| Condition::JsonContainedBy { field, value: json }))
+    }
+
+    pub(crate) fn is_coordinate(&self) -> bool {
+        matches!(&self.target, Target::Field(field) if field.is_coordinate())
+    }
+
+    pub fn compare(self, op: ComparisonOp, value: Value) -> Query {
+        Query::Condition(self.condition(op, value))
+    }
+
+    pub(crate) fn condition(self, op: ComparisonOp, value: Value) -> Condition {
+        match self.target {
+            Target::Aggregate { func, column } => Condition::Aggregate { func, column, op, value },
+            // Same as the parser: x and y compare against the row's coordinates
+            Target::Field(field) if field.is_coordinate() && !op.is_pattern() && value.as_u64().is_some_and(|v| v <= u32::MAX as u64) => {
+                Condition::Coordinate { field: field.to_string(), op, value: value.as_u64().unwrap_or(0) as u32 }
+            }
+            _ => self.predicate(|field| Condition::JsonField { field, op, value }),
+        }
+    }
+
+    // Builds a condition on the operand's value. ANY wraps a condition on each
+    // element, expressions one on their result, and aggregates are named by their
+    // call, which HAVING resolves against the select list.
+    pub(crate) fn predicate(self, condition: impl FnOnce(FieldPath) -> Condition) -> Condition {
+        match self.target {
+            Target::Field(field) => condition(field),
+            Target::Aggregate { func, column } => condition(FieldPath::key(&aggregate_name(&func, column.as_ref()))),
+            Target::Any(field) => Condition::Any { field, condition: Box::new(condition(FieldPath::root())) },
+            Target::Expr(expr) => Condition::Computed { expr, condition: Box::new(condition(FieldPath::root())) },
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.target {
+            Target::Field(path) => write!(f, "{}", path),
+            Target::Aggregate { func, column } => write!(f, "{}", aggregate_name(func, column.as_ref())),
+            Target::Any(path) => write!(f, "ANY({})", path),
+            Target::Expr(expr) => write!(f, "{}", expr),
+        }
+    }
+}
+
+impl Query {
+    pub fn field(path: impl Into<FieldPath>) -> Operand {
+        Operand { target: Target::Field(path.into()) }
+    }
+
+    // An aggregate such as `COUNT(*)` or `AVG(age)`, for HAVING clauses
+    pub fn aggregate(func: AggregateFunc, column: Option<FieldPath>) -> Operand {
+        Operand { target: Target::Aggregate { func, column } }
+    }
+
+    // Each element of an array field: `Query::any("tags").eq("rust")` matches rows
+    // with at least one such tag
+    pub fn any(path: impl Into<FieldPath>) -> Operand {
+        Operand { target: Target::Any(path.into()) }
+    }
+
+    // The length of an array, object or string field
+    pub fn length(path: impl Into<FieldPath>) -> Operand {
+        Query::expr(Expr::call("length", vec![Expr::field(path)]))
+    }
+
+    // A computed value: `Query::expr(Expr::field("price") * Expr::field("qty")).gt(100)`
+    pub fn expr(expr: Expr) -> Operand {
+        match expr {
+            Expr::Field(path) => Query::field(path),
+            expr => Operand { target: Target::Expr(expr) },
+        }
+    }
+
+    // Rows whose cell lies in `shape`, e.g. `Query::within(Shape::radius(10.0, 10.0, 5.0))`
+    pub fn within(shape: Shape) -> Query {
+        Query::Condition(Condition::Within(shape))
+    }
+
+    pub fn and(self, other: Query) -> Query {
+        Query::And(Box::new(self), Box::new(other))
+    }
+
+    pub fn or(self, other: Query) -> Query {
+        Query::Or(Box::new(self), Box::new(other))
+    }
+
+    // The query as text accepted by `parse_query`
+    pub fn to_where_clause(&self) -> String {
+        format!("WHERE {}", self)
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            Query::Or(..) => 0,
+            Query::And(..) => 1,
+            Query::Not(_) => 2,
+            Query::Condition(_) | Query::Group(_) => 3,
+        }
+    }
+
+    // Writes `self`, parenthesized if it binds looser than its context
+    fn fmt_operand(&self, f: &mut fmt::Formatter<'_>, context: u8) -> fmt::Result {
+        if self.precedence() < context {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+impl std::ops::Not for Query {
+    type Output = Query;
+
+    fn not(self) -> Query {
+        Query::Not(Box::new(self))
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Query::Condition(condition) => write!(f, "{}", condition),
+            Query::And(left, right) => {
+                left.fmt_operand(f, 1)?;
+                write!(f, " AND ")?;
+                right.fmt_operand(f, 2)
+            }
+            Query::Or(left, right) => {
+                left.fmt_operand(f, 0)?;
+                write!(f, " OR ")?;
+                right.fmt_operand(f, 1)
+            }
+            Query::Not(inner) => {
+                write!(f, "NOT ")?;
+                inner.fmt_operand(f, 2)
+            }
+            Query::Group(inner) => write!(f, "({})", inner),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Coordinate { field, .. } => write_predicate(f, field, self),
+            Condition::Aggregate { func, column, .. } => write_predicate(f, &aggregate_name(func, column.as_ref()), self),
+            Condition::Parameter { operand, .. } => write_predicate(f, operand, self),
+            Condition::Any { field, condition } => write_predicate(f, &format!("ANY({})", field), condition),
+            Condition::Computed { expr, condition } => write_predicate(f, expr, condition),
+            Condition::Within(shape) => write!(f, "WITHIN {}", shape),
+            _ => write_predicate(f, &self.field().unwrap_or_else(FieldPath::root), self),
+        }
+    }
+}
+
+// Writes `lhs` followed by the rest of `condition`: its operator and operands
+fn write_predicate(f: &mut fmt::Formatter<'_>, lhs: &dyn fmt::Display, condition: &Condition) -> fmt::Result {
+    let not = |negated: bool| if negated { "NOT " } else { "" };
+    match condition {
+        Condition::Coordinate { op, value, .. } => write!(f, "{} {} {}", lhs, op, value),
+        Condition::JsonField { op, value, .. } | Condition::Aggregate { op, value, .. } => write!(f, "{} {} {}", lhs, op, value),
+        Condition::Parameter { op, param, .. } => write!(f, "{} {} {}", lhs, op, param),
+        Condition::In { values, .. } => write!(f, "{} IN ({})", lhs, join(values)),
+        Condition::Between { low, high, .. } => write!(f, "{} BETWEEN {} AND {}", lhs, low, high),
+        Condition::IsNull { negated, .. } => write!(f, "{} IS {}NULL", lhs, not(*negated)),
+        Condition::IsMissing { negated, .. } => write!(f, "{} IS {}MISSING", lhs, not(*negated)),
+        Condition::Contains { value, .. } => write!(f, "{} CONTAINS {}", lhs, value),
+        Condition::HasKey { key, .. } => write!(f, "{} HAS KEY {}", lhs, Value::String(key.clone())),
+        // The JSON operand is written as a string literal, as the parser expects
+        Condition::JsonContains { value, .. } => write!(f, "{} @> {}", lhs, Value::String(value.to_string())),
+        Condition::JsonContainedBy { value, .. } => write!(f, "{} <@ {}", lhs, Value::String(value.to_string())),
+        Condition::Any { .. } | Condition::Computed { .. } | Condition::Within(_) => write!(f, "{}", condition),
+    }
+}
+
+impl fmt::Display for ComparisonOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            ComparisonOp::Equal => "=",
+            ComparisonOp::NotEqual => "!=",
+            ComparisonOp::GreaterThan => ">",
+            ComparisonOp::LessThan => "<",
+            ComparisonOp::GreaterThanOrEqual => ">=",
+            ComparisonOp::LessThanOrEqual => "<=",
+            ComparisonOp::Like => "LIKE",
+            ComparisonOp::ILike => "ILIKE",
+            ComparisonOp::Regex => "~",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+impl Expr {
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Binary { op: ArithOp::Add | ArithOp::Subtract, .. } => 0,
+            Expr::Binary { .. } => 1,
+            Expr::Negate(_) => 2,
+            Expr::Field(_) | Expr::Literal(_) | Expr::Call { .. } => 3,
+        }
+    }
+
+    // Writes `self`, parenthesized if it binds looser than its context
+    fn fmt_operand(&self, f: &mut fmt::Formatter<'_>, context: u8) -> fmt::Result {
+        if self.precedence() < context {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Field(path) => write!(f, "{}", path),
+            // Double quotes would name a field, so strings are single-quoted
+            Expr::Literal(Value::String(text)) => write!(f, "'{}'", text.replace('\\', "\\\\").replace('\'', "\\'")),
+            Expr::Literal(value) => write!(f, "{}", value),
+            Expr::Negate(inner) => {
+                write!(f, "-")?;
+                inner.fmt_operand(f, 3)
+            }
+            Expr::Binary { op, left, right } => {
+                let context = if matches!(op, ArithOp::Add | ArithOp::Subtract) { 0 } else { 1 };
+                left.fmt_operand(f, context)?;
+                write!(f, " {} ", op)?;
+                right.fmt_operand(f, context + 1)
+            }
+            Expr::Call { name, args } => write!(f, "{}({})", name.to_uppercase(), join(args)),
+        }
+    }
+}
+
+impl fmt::Display for ArithOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            ArithOp::Add => "+",
+            ArithOp::Subtract => "-",
+            ArithOp::Multiply => "*",
+            ArithOp::Divide => "/",
+            ArithOp::Remainder => "%",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+macro_rules! arith_ops {
+    ($($trait:ident $method:ident $op:ident),*) => {$(
+        impl std::ops::$trait for Expr {
+            type Output = Expr;
+
+            fn $method(self, right: Expr) -> Expr {
+                Expr::Binary { op: ArithOp::$op, left: Box::new(self), right: Box::new(right) }
+            }
+        }
+    )*};
+}
+
+arith_ops!(Add add Add, Sub sub Subtract, Mul mul Multiply, Div div Divide, Rem rem Remainder);
+
+impl std::ops::Neg for Expr {
+    type Output = Expr;
+
+    fn neg(self) -> Expr {
+        Expr::Negate(Box::new(self))
+    }
+}
+
+impl Select {
+    // `SELECT *` over every row; narrow it down with the methods below
+    pub fn new() -> Self {
+        Select {
+            projection: Projection::All,
+            filter: None,
+            group_by: Vec::new(),
+            having: None,
+            order_by: Vec::new(),
+            limit: None,
+            offset: None,
+            nearest: None,
+        }
+    }
+
+    pub fn column(self, path: impl Into<FieldPath>) -> Self {
+        self.item(SelectItem::Column { path: path.into(), alias: None })
+    }
+
+    pub fn column_as(self, path: impl Into<FieldPath>, alias: &str) -> Self {
+        self.item(SelectItem::Column { path: path.into(), alias: Some(alias.to_string()) })
+    }
+
+    pub fn aggregate(self, func: AggregateFunc, column: Option<FieldPath>) -> Self {
+        self.item(SelectItem::Aggregate { func, column, alias: None })
+    }
+
+    pub fn aggregate_as(self, func: AggregateFunc, column: Option<FieldPath>, alias: &str) -> Self {
+        self.item(SelectItem::Aggregate { func, column, alias: Some(alias.to_string()) })
+    }
+
+    // A computed column, e.g. `Select::new().expr(Expr::call("upper", vec![Expr::field("name")]))`
+    pub fn expr(self, expr: Expr) -> Self {
+        self.item(match expr {
+            Expr::Field(path) => SelectItem::Column { path, alias: None },
+            expr => SelectItem::Expr { expr, alias: None },
+        })
+    }
+
+    pub fn expr_as(self, expr: Expr, alias: &str) -> Self {
+        let alias = Some(alias.to_string());
+        self.item(match expr {
+            Expr::Field(path) => SelectItem::Column { path, alias },
+            expr => SelectItem::Expr { expr, alias },
+        })
+    }
+
+    fn item(mut self, item: SelectItem) -> Self {
+        match &mut self.projection {
+            Projection::Items(items) => items.push(item),
+            Projection::All => self.projection = Projection::Items(vec![item]),
+        }
+        self
+    }
+
+    // Adds a WHERE condition, ANDed with any earlier one
+    pub fn filter(mut self, query: Query) -> Self {
+        self.filter = Some(match self.filter.take() {
+            Some(existing) => existing.and(query),
+            None => query,
+        });
+        self
+    }
+
+    pub fn group_by(mut self, path: impl Into<FieldPath>) -> Self {
+        self.group_by.push(path.into());
+        self
+    }
+
+    pub fn having(mut self, query: Query) -> Self {
+        self.having = Some(match self.having.take() {
+            Some(existing) => existing.and(query),
+            None => query,
+        });
+        self
+    }
+
+    pub fn order_by(mut self, path: impl Into<FieldPath>) -> Self {
+        self.order_by.push(OrderBy { key: OrderKey::Field(path.into()), descending: false });
+        self
+    }
+
+    pub fn order_by_desc(mut self, path: impl Into<FieldPath>) -> Self {
+        self.order_by.push(OrderBy { key: OrderKey::Field(path.into()), descending: true });
+        self
+    }
+
+    pub fn order_by_expr(mut self, expr: Expr) -> Self {
+        self.order_by.push(OrderBy { key: order_key(expr), descending: false });
+        self
+    }
+
+    pub fn order_by_expr_desc(mut self, expr: Expr) -> Self {
+        self.order_by.push(OrderBy { key: order_key(expr), descending: true });
+        self
+    }
+
+    // Keeps the `k` matching rows closest to (x, y); see `PngDatabase::nearest`
+    pub fn nearest(mut self, k: usize, x: f64, y: f64) -> Self {
+        self.nearest = Some(Nearest { k, x, y });
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+}
+
+impl Default for Select {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for Select {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SELECT ")?;
+        match &self.projection {
+            Projection::All => write!(f, "*")?,
+            Projection::Items(items) => write!(f, "{}", join(items))?,
+        }
+        if let Some(filter) = &self.filter {
+            write!(f, " WHERE {}", filter)?;
+        }
+        if let Some(Nearest { k, x, y }) = &self.nearest {
+            write!(f, " NEAREST {} TO ({}, {})", k, x, y)?;
+        }
+        if !self.group_by.is_empty() {
+            write!(f, " GROUP BY {}", join(&self.group_by))?;
+        }
+        if let Some(having) = &self.having {
+            write!(f, " HAVING {}", having)?;
+        }
+        if !self.order_by.is_empty() {
+            write!(f, " ORDER BY {}", join(&self.order_by))?;
+        }
+        if let Some(limit) = self.limit {
+            write!(f, " LIMIT {}", limit)?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, " OFFSET {}", offset)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SelectItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let alias = match self {
+            SelectItem::Column { path, alias } => {
+                write!(f, "{}", path)?;
+                alias
+            }
+            SelectItem::Aggregate { func, column, alias } => {
+                write!(f, "{}", aggregate_name(func, column.as_ref()))?;
+                alias
+            }
+            SelectItem::Expr { expr, alias } => {
+                write!(f, "{}", expr)?;
+                alias
+            }
+        };
+        match alias {
+            Some(alias) => write!(f, " AS {}", alias),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for OrderBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.key {
+            OrderKey::Field(path) => write!(f, "{}", path)?,
+            OrderKey::Expr(expr) => write!(f, "{}", expr)?,
+            OrderKey::Aggregate { func, column } => write!(f, "{}", aggregate_name(func, column.as_ref()))?,
+        }
+        if self.descending {
+            write!(f, " DESC")?;
+        }
+        Ok(())
+    }
+}
+
+fn order_key(expr: Expr) -> OrderKey {
+    match expr {
+        Expr::Field(path) => OrderKey::Field(path),
+        expr => OrderKey::Expr(expr),
+    }
+}
+
+fn join<T: fmt::Display>(items: &[T]) -> String {
+    items.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+}
@@ -0,0 +1,122 @@
+use crate::functions::FunctionRegistry;
+use crate::path::FieldPath;
+use crate::schema::describe;
+use crate::{DataRow, PngDbError, Result};
+use serde_json::{Number, Value};
+
+// A computed value such as `x + y`, `age * 12` or `lower(name)`, usable on the
+// left of a condition, in the select list and in ORDER BY
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Field(FieldPath),
+    Literal(Value),
+    Negate(Box<Expr>),
+    Binary { op: ArithOp, left: Box<Expr>, right: Box<Expr> },
+    // A scalar function from the database's `FunctionRegistry`; `name` is lowercase
+    Call { name: String, args: Vec<Expr> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+impl Expr {
+    pub fn field(path: impl Into<FieldPath>) -> Self {
+        Expr::Field(path.into())
+    }
+
+    pub fn literal(value: impl Into<Value>) -> Self {
+        Expr::Literal(value.into())
+    }
+
+    pub fn call(name: &str, args: Vec<Expr>) -> Self {
+        Expr::Call { name: name.to_lowercase(), args }
+    }
+
+    // Every field the expression reads
+    pub(crate) fn fields(&self) -> Vec<&FieldPath> {
+        match self {
+            Expr::Field(path) => vec![path],
+            Expr::Literal(_) => Vec::new(),
+            Expr::Negate(inner) => inner.fields(),
+            Expr::Binary { left, right, .. } => left.fields().into_iter().chain(right.fields()).collect(),
+            Expr::Call { args, .. } => args.iter().flat_map(Expr::fields).collect(),
+        }
+    }
+
+    // Missing fields evaluate to null, which arithmetic and the built-in
+    // functions pass through
+    pub(crate) fn eval(&self, row: &DataRow, functions: &FunctionRegistry) -> Result<Value> {
+        match self {
+            Expr::Field(path) => Ok(match path.as_key() {
+                Some("x") => Value::from(row.x),
+                Some("y") => Value::from(row.y),
+                _ => path.resolve(&row.data).cloned().unwrap_or(Value::Null),
+            }),
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Negate(inner) => arithmetic(ArithOp::Subtract, Value::from(0), inner.eval(row, functions)?),
+            Expr::Binary { op, left, right } => arithmetic(*op, left.eval(row, functions)?, right.eval(row, functions)?),
+            Expr::Call { name, args } => {
+                let args = args.iter().map(|arg| arg.eval(row, functions)).collect::<Result<Vec<_>>>()?;
+                functions.call(name, &args)
+            }
+        }
+    }
+}
+
+// Integer arithmetic stays integral where the result fits; `/` gives a float
+// unless the division is exact
+fn arithmetic(op: ArithOp, left: Value, right: Value) -> Result<Value> {
+    if left.is_null() || right.is_null() {
+        return Ok(Value::Null);
+    }
+    let (Value::Number(l), Value::Number(r)) = (&left, &right) else {
+        return Err(PngDbError::QueryError(format!(
+            "Cannot apply {} to {} and {}", op, describe(&left), describe(&right)
+        )));
+    };
+
+    let divides = |by: f64| if by == 0.0 { Err(PngDbError::QueryError("Division by zero".to_string())) } else { Ok(()) };
+
+    if let (Some(l), Some(r)) = (l.as_i64(), r.as_i64()) {
+        let result = match op {
+            ArithOp::Add => l.checked_add(r),
+            ArithOp::Subtract => l.checked_sub(r),
+            ArithOp::Multiply => l.checked_mul(r),
+            ArithOp::Divide => {
+                divides(r as f64)?;
+                l.checked_rem(r).filter(|rem| *rem == 0).and_then(|_| l.checked_div(r))
+            }
+            ArithOp::Remainder => {
+                divides(r as f64)?;
+                l.checked_rem(r)
+            }
+        };
+        if let Some(result) = result {
+            return Ok(Value::from(result));
+        }
+    }
+
+    let (l, r) = (l.as_f64().unwrap_or(0.0), r.as_f64().unwrap_or(0.0));
+    let result = match op {
+        ArithOp::Add => l + r,
+        ArithOp::Subtract => l - r,
+        ArithOp::Multiply => l * r,
+        ArithOp::Divide => {
+            divides(r)?;
+            l / r
+        }
+        ArithOp::Remainder => {
+            divides(r)?;
+            l % r
+        }
+    };
+    Number::from_f64(result)
+        .map(Value::Number)
+        .ok_or_else(|| PngDbError::QueryError(format!("Arithmetic overflow in {}", op)))
+}
@@ -0,0 +1,158 @@
+use crate::{PngDbError, Result};
+use serde::{Deserialize, Serialize};
+
+// Keyword of the chunk holding `Metadata`, written before every other png-db chunk
+pub const METADATA_KEYWORD: &str = "pngdb";
+// Keyword of the optional chunk holding a `SpatialIndex`. Readers that do not
+// know it skip it, so it is not a feature and does not change the format version.
+pub const SPATIAL_INDEX_KEYWORD: &str = "spatial_index";
+// Keyword of the optional chunk holding the `FieldIndex`es, which is not a
+// feature either
+pub const FIELD_INDEXES_KEYWORD: &str = "field_indexes";
+
+// Layout history:
+//   1 - `schema` and `row_x_y` chunks without a metadata chunk (zTXt, later iTXt)
+//   2 - adds the `pngdb` metadata chunk and a `config` chunk; rows use iTXt
+pub const FORMAT_VERSION: u32 = 2;
+const LEGACY_FORMAT_VERSION: u32 = 1;
+
+// Optional parts of the layout a reader has to understand to load the file correctly
+pub const FEATURE_ITXT: &str = "itxt";
+pub const FEATURE_CELL_POLICY: &str = "cell_policy";
+// Rows keyed `row_x_y_seq`, which readers without cell policies would skip
+pub const FEATURE_ROW_SEQ: &str = "row_seq";
+// Rows appended after the image data, which readers that stop there would miss
+pub const FEATURE_APPENDED_ROWS: &str = "appended_rows";
+const SUPPORTED_FEATURES: &[&str] = &[FEATURE_ITXT, FEATURE_CELL_POLICY, FEATURE_ROW_SEQ, FEATURE_APPENDED_ROWS];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    pub format_version: u32,
+    // png-db versions that first wrote and last rewrote the file
+    pub created_by: String,
+    pub modified_by: String,
+    // Seconds since the Unix epoch
+    pub created_at: u64,
+    pub modified_at: u64,
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+impl Metadata {
+    pub fn new() -> Self {
+        let now = unix_time();
+        Self {
+            format_version: FORMAT_VERSION,
+            created_by: creator(),
+            modified_by: creator(),
+            created_at: now,
+            modified_at: now,
+            features: Vec::new(),
+        }
+    }
+
+    // Stand-in for files written before the metadata chunk existed. Their creation
+    // time is unknown, so it is taken to be the moment they were first loaded.
+    pub(crate) fn legacy() -> Self {
+        let now = unix_time();
+        Self {
+            format_version: LEGACY_FORMAT_VERSION,
+            created_by: "unknown".to_string(),
+            modified_by: "unknown".to_string(),
+            created_at: now,
+            modified_at: now,
+            features: Vec::new(),
+        }
+    }
+
+    pub fn is_current(&self) -> bool {
+        self.format_version == FORMAT_VERSION
+    }
+
+    // Rejects files this build cannot read without losing data
+    pub(crate) fn check_supported(&self) -> Result<()> {
+        if self.format_version > FORMAT_VERSION || self.format_version < LEGACY_FORMAT_VERSION {
+            return Err(PngDbError::DatabaseError(format!(
+                "File uses png-db format v{} (written by {}), but {} only reads up to v{}",
+                self.format_version, self.modified_by, creator(), FORMAT_VERSION
+            )));
+        }
+        if let Some(feature) = self.features.iter().find(|f| !SUPPORTED_FEATURES.contains(&f.as_str())) {
+            return Err(PngDbError::DatabaseError(format!(
+                "File requires feature '{}' (written by {}), which {} does not support",
+                feature, self.modified_by, creator()
+            )));
+        }
+        Ok(())
+    }
+
+    // Whether rows were appended since the last full save. Appends leave the
+    // stored indexes as they were, and an upsert can change a row without
+    // changing the row count or the save stamp the indexes are checked against.
+    pub(crate) fn has_appended_rows(&self) -> bool {
+        self.features.iter().any(|feature| feature == FEATURE_APPENDED_ROWS)
+    }
+
+    // Metadata for a save by this build: current format, fresh modification stamp
+    pub(crate) fn for_save(&self, features: Vec<String>) -> Self {
+        Self {
+            format_version: FORMAT_VERSION,
+            created_by: self.created_by.clone(),
+            modified_by: creator(),
+            created_at: self.created_at,
+            modified_at: unix_time(),
+            features,
+        }
+    }
+}
+
+impl Default for Metadata {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn creator() -> String {
+    format!("png-db {}", env!("CARGO_PKG_VERSION"))
+}
+
+// `SystemTime::now` panics on wasm32, where the browser clock is used instead
+#[cfg(not(target_arch = "wasm32"))]
+fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+fn unix_time() -> u64 {
+    (js_sys::Date::now() / 1000.0) as u64
+}
+
+#[cfg(all(target_arch = "wasm32", not(feature = "wasm")))]
+fn unix_time() -> u64 {
+    0
+}
+
+// Formats Unix seconds as `YYYY-MM-DD HH:MM:SS UTC`
+pub fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let time = secs % 86_400;
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year, month, day, time / 3600, time % 3600 / 60, time % 60
+    )
+}
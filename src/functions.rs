@@ -0,0 +1,203 @@
+use crate::schema::describe;
+use crate::{PngDbError, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+pub type ScalarFunction = dyn Fn(&[Value]) -> Result<Value> + Send + Sync;
+
+// Scalar functions callable from queries, e.g. `lower(name)`. Names are
+// case-insensitive. `new` starts with the built-in functions; applications add
+// their own with `register`, which also replaces a function of the same name.
+#[derive(Clone)]
+pub struct FunctionRegistry {
+    functions: HashMap<String, Arc<ScalarFunction>>,
+}
+
+impl FunctionRegistry {
+    pub fn new() -> Self {
+        let mut registry = Self::empty();
+        registry.register("lower", lower);
+        registry.register("upper", upper);
+        registry.register("length", length);
+        registry.register("abs", abs);
+        registry.register("round", round);
+        registry.register("coalesce", coalesce);
+        registry.register("substr", substr);
+        registry.register("distance", distance);
+        registry
+    }
+
+    // A registry without the built-in functions
+    pub fn empty() -> Self {
+        Self { functions: HashMap::new() }
+    }
+
+    pub fn register<F>(&mut self, name: &str, function: F)
+    where
+        F: Fn(&[Value]) -> Result<Value> + Send + Sync + 'static,
+    {
+        self.functions.insert(name.to_lowercase(), Arc::new(function));
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.functions.contains_key(&name.to_lowercase())
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.functions.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+
+    pub fn call(&self, name: &str, args: &[Value]) -> Result<Value> {
+        let function = self.functions.get(&name.to_lowercase())
+            .ok_or_else(|| PngDbError::QueryError(format!("Unknown function '{}'", name)))?;
+        function(args)
+    }
+}
+
+impl Default for FunctionRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for FunctionRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.names()).finish()
+    }
+}
+
+// Checks the argument count of a built-in function
+fn expect_args(name: &str, args: &[Value], min: usize, max: usize) -> Result<()> {
+    if args.len() < min || args.len() > max {
+        let expected = if min == max { min.to_string() } else { format!("{} to {}", min, max) };
+        return Err(PngDbError::QueryError(format!("{}() takes {} argument(s) but got {}", name, expected, args.len())));
+    }
+    Ok(())
+}
+
+fn type_error(name: &str, expected: &str, value: &Value) -> PngDbError {
+    PngDbError::QueryError(format!("{}() expects {} but got {} {}", name, expected, describe(value), value))
+}
+
+fn number(name: &str, value: &Value) -> Result<f64> {
+    value.as_f64().ok_or_else(|| type_error(name, "a number", value))
+}
+
+fn float(name: &str, value: f64) -> Result<Value> {
+    serde_json::Number::from_f64(value)
+        .map(Value::Number)
+        .ok_or_else(|| PngDbError::QueryError(format!("{}() produced a non-finite number", name)))
+}
+
+// Built-ins return null for a null argument, as SQL functions do
+
+fn lower(args: &[Value]) -> Result<Value> {
+    expect_args("lower", args, 1, 1)?;
+    match &args[0] {
+        Value::Null => Ok(Value::Null),
+        Value::String(text) => Ok(Value::String(text.to_lowercase())),
+        other => Err(type_error("lower", "a string", other)),
+    }
+}
+
+fn upper(args: &[Value]) -> Result<Value> {
+    expect_args("upper", args, 1, 1)?;
+    match &args[0] {
+        Value::Null => Ok(Value::Null),
+        Value::String(text) => Ok(Value::String(text.to_uppercase())),
+        other => Err(type_error("upper", "a string", other)),
+    }
+}
+
+// Characters of a string, elements of an array or keys of an object; null for
+// anything else, so rows with a missing or scalar field simply do not match
+fn length(args: &[Value]) -> Result<Value> {
+    expect_args("length", args, 1, 1)?;
+    Ok(match &args[0] {
+        Value::String(text) => Value::from(text.chars().count()),
+        Value::Array(elements) => Value::from(elements.len()),
+        Value::Object(object) => Value::from(object.len()),
+        _ => Value::Null,
+    })
+}
+
+fn abs(args: &[Value]) -> Result<Value> {
+    expect_args("abs", args, 1, 1)?;
+    match &args[0] {
+        Value::Null => Ok(Value::Null),
+        Value::Number(n) => match n.as_i64().and_then(i64::checked_abs) {
+            Some(n) => Ok(Value::from(n)),
+            None => float("abs", number("abs", &args[0])?.abs()),
+        },
+        other => Err(type_error("abs", "a number", other)),
+    }
+}
+
+// `round(n)` rounds half away from zero to an integer; `round(n, digits)` keeps
+// that many decimal places
+fn round(args: &[Value]) -> Result<Value> {
+    expect_args("round", args, 1, 2)?;
+    if args[0].is_null() {
+        return Ok(Value::Null);
+    }
+    let value = number("round", &args[0])?;
+    let digits = match args.get(1) {
+        Some(digits) => digits.as_i64().ok_or_else(|| type_error("round", "an integer number of digits", digits))?,
+        None => 0,
+    };
+
+    if digits <= 0 {
+        let scale = 10f64.powi(-digits.max(-308) as i32);
+        let rounded = (value / scale).round() * scale;
+        if rounded.abs() < i64::MAX as f64 {
+            return Ok(Value::from(rounded as i64));
+        }
+        return float("round", rounded);
+    }
+    let scale = 10f64.powi(digits.min(308) as i32);
+    float("round", (value * scale).round() / scale)
+}
+
+fn coalesce(args: &[Value]) -> Result<Value> {
+    expect_args("coalesce", args, 1, usize::MAX)?;
+    Ok(args.iter().find(|value| !value.is_null()).cloned().unwrap_or(Value::Null))
+}
+
+// `substr(text, start[, count])` with a 1-based `start`, counting characters
+fn substr(args: &[Value]) -> Result<Value> {
+    expect_args("substr", args, 2, 3)?;
+    let text = match &args[0] {
+        Value::Null => return Ok(Value::Null),
+        Value::String(text) => text,
+        other => return Err(type_error("substr", "a string", other)),
+    };
+    let start = args[1].as_i64().ok_or_else(|| type_error("substr", "an integer start", &args[1]))?;
+    let count = match args.get(2) {
+        Some(count) => match count.as_u64() {
+            Some(count) => count as usize,
+            None => return Err(type_error("substr", "a non-negative count", count)),
+        },
+        None => usize::MAX,
+    };
+
+    let skip = usize::try_from(start.max(1) - 1).unwrap_or(usize::MAX);
+    // A start before 1 still counts towards `count`, as in SQL; `abs_diff` keeps
+    // the distance from 1 in range even for the most negative start
+    let before = usize::try_from(start.min(1).abs_diff(1)).unwrap_or(usize::MAX);
+    let count = count.saturating_sub(before);
+    Ok(Value::String(text.chars().skip(skip).take(count).collect()))
+}
+
+// Euclidean distance between (x1, y1) and (x2, y2)
+fn distance(args: &[Value]) -> Result<Value> {
+    expect_args("distance", args, 4, 4)?;
+    if args.iter().any(Value::is_null) {
+        return Ok(Value::Null);
+    }
+    let [x1, y1, x2, y2] = [&args[0], &args[1], &args[2], &args[3]].map(|value| number("distance", value));
+    float("distance", (x2? - x1?).hypot(y2? - y1?))
+}
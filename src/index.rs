@@ -0,0 +1,464 @@
+use crate::format::Metadata;
+use crate::path::FieldPath;
+use crate::query::parse_field_path;
+use crate::{DataRow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Bound;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexKind {
+    // Ordered keys: serves =, IN, <, <=, >, >= and BETWEEN
+    #[default]
+    BTree,
+    // Serves = and IN only
+    Hash,
+}
+
+impl std::str::FromStr for IndexKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "btree" => Ok(IndexKind::BTree),
+            "hash" => Ok(IndexKind::Hash),
+            _ => Err(format!("Unknown index kind '{}', expected btree or hash", s)),
+        }
+    }
+}
+
+impl fmt::Display for IndexKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IndexKind::BTree => write!(f, "btree"),
+            IndexKind::Hash => write!(f, "hash"),
+        }
+    }
+}
+
+// The value of an indexed field as a key, ordered like ORDER BY. Arrays and
+// objects share one key: they never equal a scalar, and are only looked up so
+// that ordering them fails as it would in a full scan.
+#[derive(Debug, Clone)]
+enum Key {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Composite,
+}
+
+impl Key {
+    fn of(value: &Value, kind: IndexKind) -> Self {
+        match value {
+            Value::Null => Key::Null,
+            Value::Bool(b) => Key::Bool(*b),
+            Value::Number(n) => {
+                let n = n.as_f64().unwrap_or(0.0);
+                match kind {
+                    IndexKind::BTree => Key::number(n),
+                    IndexKind::Hash => Key::number(bucket(n)),
+                }
+            }
+            Value::String(s) => Key::String(s.clone()),
+            Value::Array(_) | Value::Object(_) => Key::Composite,
+        }
+    }
+
+    // -0 and 0 are the same key
+    fn number(n: f64) -> Self {
+        Key::Number(n + 0.0)
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Key::Null => 0,
+            Key::Bool(_) => 1,
+            Key::Number(_) => 2,
+            Key::String(_) => 3,
+            Key::Composite => 4,
+        }
+    }
+}
+
+// Numbers are equal in queries when they differ by less than `f64::EPSILON`, so
+// a hash index files them under `n / EPSILON` rounded down, and a lookup also
+// tries the buckets on either side. EPSILON is a power of two, so the division
+// is exact.
+fn bucket(n: f64) -> f64 {
+    (n / f64::EPSILON).floor()
+}
+
+impl Ord for Key {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Key::Bool(l), Key::Bool(r)) => l.cmp(r),
+            (Key::Number(l), Key::Number(r)) => l.total_cmp(r),
+            (Key::String(l), Key::String(r)) => l.cmp(r),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Key {}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rank().hash(state);
+        match self {
+            Key::Bool(b) => b.hash(state),
+            Key::Number(n) => n.to_bits().hash(state),
+            Key::String(s) => s.hash(state),
+            Key::Null | Key::Composite => {}
+        }
+    }
+}
+
+// Row indices by key, in ascending order within each key
+#[derive(Debug, Clone)]
+enum Entries {
+    BTree(BTreeMap<Key, Vec<usize>>),
+    Hash(HashMap<Key, Vec<usize>>),
+}
+
+impl Entries {
+    fn new(kind: IndexKind) -> Self {
+        match kind {
+            IndexKind::BTree => Entries::BTree(BTreeMap::new()),
+            IndexKind::Hash => Entries::Hash(HashMap::new()),
+        }
+    }
+
+    fn get(&self, key: &Key) -> Option<&Vec<usize>> {
+        match self {
+            Entries::BTree(map) => map.get(key),
+            Entries::Hash(map) => map.get(key),
+        }
+    }
+
+    fn get_or_default(&mut self, key: Key) -> &mut Vec<usize> {
+        match self {
+            Entries::BTree(map) => map.entry(key).or_default(),
+            Entries::Hash(map) => map.entry(key).or_default(),
+        }
+    }
+
+    fn remove(&mut self, key: &Key, row: usize) {
+        let rows = match self {
+            Entries::BTree(map) => map.get_mut(key),
+            Entries::Hash(map) => map.get_mut(key),
+        };
+        let Some(rows) = rows else {
+            return;
+        };
+        if let Ok(position) = rows.binary_search(&row) {
+            rows.remove(position);
+        }
+        if rows.is_empty() {
+            match self {
+                Entries::BTree(map) => map.remove(key),
+                Entries::Hash(map) => map.remove(key),
+            };
+        }
+    }
+
+    fn groups(&self) -> Box<dyn Iterator<Item = &Vec<usize>> + '_> {
+        match self {
+            Entries::BTree(map) => Box::new(map.values()),
+            Entries::Hash(map) => Box::new(map.values()),
+        }
+    }
+
+    fn retain_groups<F: FnMut(&mut Vec<usize>) -> bool>(&mut self, mut f: F) {
+        match self {
+            Entries::BTree(map) => map.retain(|_, rows| f(rows)),
+            Entries::Hash(map) => map.retain(|_, rows| f(rows)),
+        }
+    }
+}
+
+// A secondary index over the values of one field, created with
+// `CREATE INDEX ON field`. Rows without the field are not in it. Like the
+// spatial index it refers to rows by position, so `PngDatabase` updates it
+// on every insert, update and delete.
+#[derive(Debug, Clone)]
+pub struct FieldIndex {
+    field: FieldPath,
+    kind: IndexKind,
+    entries: Entries,
+    // How many rows of the database it was kept up to date with
+    covered: usize,
+}
+
+impl FieldIndex {
+    pub fn build(field: FieldPath, kind: IndexKind, rows: &[DataRow]) -> Self {
+        let mut index = Self { field, kind, entries: Entries::new(kind), covered: 0 };
+        for (row, data) in rows.iter().enumerate() {
+            index.insert_row(row, &data.data);
+        }
+        index
+    }
+
+    pub fn field(&self) -> &FieldPath {
+        &self.field
+    }
+
+    pub fn kind(&self) -> IndexKind {
+        self.kind
+    }
+
+    // Number of rows that have the field
+    pub fn len(&self) -> usize {
+        self.entries.groups().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Number of distinct keys; for a hash index, numbers within `f64::EPSILON`
+    // of each other may share one
+    pub fn distinct(&self) -> usize {
+        self.entries.groups().count()
+    }
+
+    // The smallest and largest numbers in a B-tree index; `None` for a hash
+    // index or without numbers
+    pub fn number_range(&self) -> Option<(f64, f64)> {
+        let Entries::BTree(map) = &self.entries else {
+            return None;
+        };
+        let mut numbers = map.range(Key::Number(f64::NEG_INFINITY)..Key::String(String::new())).filter_map(|(key, _)| match key {
+            Key::Number(n) => Some(*n),
+            _ => None,
+        });
+        let min = numbers.next()?;
+        Some((min, numbers.next_back().unwrap_or(min)))
+    }
+
+    pub(crate) fn covers(&self, rows: usize) -> bool {
+        self.covered == rows
+    }
+
+    // Indices of the rows whose field may equal `value`, in storage order; every
+    // row that does is among them. `None` for arrays and objects, which the
+    // index cannot look up.
+    pub fn rows_equal(&self, value: &Value) -> Option<Vec<usize>> {
+        let mut found = Vec::new();
+        match (value, &self.entries) {
+            (Value::Array(_) | Value::Object(_), _) => return None,
+            (Value::Number(n), Entries::BTree(map)) => {
+                let n = n.as_f64().unwrap_or(0.0);
+                let range = (Bound::Included(Key::number(n - f64::EPSILON)), Bound::Included(Key::number(n + f64::EPSILON)));
+                found.extend(map.range(range).flat_map(|(_, rows)| rows));
+            }
+            (Value::Number(n), Entries::Hash(_)) => {
+                let bucket = bucket(n.as_f64().unwrap_or(0.0));
+                let mut keys = vec![Key::number(bucket - 1.0), Key::number(bucket), Key::number(bucket + 1.0)];
+                keys.dedup();
+                for key in keys {
+                    found.extend(self.entries.get(&key).into_iter().flatten());
+                }
+            }
+            _ => found.extend(self.entries.get(&Key::of(value, self.kind)).into_iter().flatten()),
+        }
+        found.sort_unstable();
+        Some(found)
+    }
+
+    // Indices of the rows whose field may lie between `low` and `high`, in
+    // storage order. Rows holding a value of another type are included, since
+    // comparing them is an error the query has to report. `None` for a hash
+    // index, or bounds that are not both numbers or both strings.
+    pub fn rows_in_range(&self, low: Bound<&Value>, high: Bound<&Value>) -> Option<Vec<usize>> {
+        let Entries::BTree(map) = &self.entries else {
+            return None;
+        };
+        let (start, end) = match [low, high].iter().find_map(|bound| match bound {
+            Bound::Included(value) | Bound::Excluded(value) => Some(*value),
+            Bound::Unbounded => None,
+        })? {
+            Value::Number(_) => (Key::Number(f64::NEG_INFINITY), Key::String(String::new())),
+            Value::String(_) => (Key::String(String::new()), Key::Composite),
+            _ => return None,
+        };
+        let key = |bound: Bound<&Value>, unbounded: Bound<Key>| -> Option<Bound<Key>> {
+            match bound {
+                Bound::Included(value) if Key::of(value, self.kind).rank() == start.rank() => Some(Bound::Included(Key::of(value, self.kind))),
+                Bound::Excluded(value) if Key::of(value, self.kind).rank() == start.rank() => Some(Bound::Excluded(Key::of(value, self.kind))),
+                Bound::Unbounded => Some(unbounded),
+                _ => None,
+            }
+        };
+        let low = key(low, Bound::Included(start.clone()))?;
+        let high = key(high, Bound::Excluded(end.clone()))?;
+
+        let mut found: Vec<usize> = map.range(..start).chain(map.range(end..)).flat_map(|(_, rows)| rows).copied().collect();
+        // `BTreeMap::range` panics on a range that ends before it starts
+        let empty = match (&low, &high) {
+            (Bound::Included(l), Bound::Included(h)) => l > h,
+            (Bound::Included(l) | Bound::Excluded(l), Bound::Included(h) | Bound::Excluded(h)) => l >= h,
+            _ => false,
+        };
+        if !empty {
+            found.extend(map.range((low, high)).flat_map(|(_, rows)| rows));
+        }
+        found.sort_unstable();
+        Some(found)
+    }
+
+    // Adds the row at position `row`, which must be the next one
+    pub(crate) fn insert_row(&mut self, row: usize, data: &Value) {
+        if let Some(value) = self.field.resolve(data) {
+            let rows = self.entries.get_or_default(Key::of(value, self.kind));
+            let position = rows.partition_point(|&other| other < row);
+            rows.insert(position, row);
+        }
+        self.covered += 1;
+    }
+
+    pub(crate) fn update_row(&mut self, row: usize, old: &Value, new: &Value) {
+        let (old, new) = (self.field.resolve(old), self.field.resolve(new));
+        if old == new {
+            return;
+        }
+        if let Some(value) = old {
+            self.entries.remove(&Key::of(value, self.kind), row);
+        }
+        if let Some(value) = new {
+            let rows = self.entries.get_or_default(Key::of(value, self.kind));
+            let position = rows.partition_point(|&other| other < row);
+            rows.insert(position, row);
+        }
+    }
+
+    // Drops the rows flagged in `deleted` and renumbers the rest to match
+    pub(crate) fn remove_rows(&mut self, deleted: &[bool]) {
+        let mut positions = Vec::with_capacity(deleted.len());
+        let mut next = 0;
+        for &gone in deleted {
+            positions.push(if gone { None } else { Some(next) });
+            next += usize::from(!gone);
+        }
+        self.entries.retain_groups(|rows| {
+            *rows = rows.iter().filter_map(|&row| positions.get(row).copied().flatten()).collect();
+            !rows.is_empty()
+        });
+        self.covered = next;
+    }
+}
+
+// Sorted row indices in both `a` and `b`
+pub(crate) fn intersect(a: &[usize], b: &[usize]) -> Vec<usize> {
+    let (mut i, mut j, mut both) = (0, 0, Vec::new());
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            Ordering::Less => i += 1,
+            Ordering::Greater => j += 1,
+            Ordering::Equal => {
+                both.push(a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    both
+}
+
+// Sorted row indices in `a` or `b`
+pub(crate) fn union(a: &[usize], b: &[usize]) -> Vec<usize> {
+    let mut either: Vec<usize> = a.iter().chain(b).copied().collect();
+    either.sort_unstable();
+    either.dedup();
+    either
+}
+
+// The `field_indexes` chunk. Keys are not stored: each group of rows shares
+// one, which is read back from its first row. `written_by` and `written_at`
+// repeat the metadata of the save, as for the spatial index, so that groups
+// kept by a writer that did not update them are rebuilt instead.
+#[derive(Serialize, Deserialize)]
+struct StoredIndexes {
+    written_by: String,
+    written_at: u64,
+    rows: usize,
+    indexes: Vec<StoredIndex>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredIndex {
+    field: String,
+    kind: IndexKind,
+    groups: Vec<Vec<usize>>,
+}
+
+pub(crate) fn to_chunk(indexes: &[FieldIndex], metadata: &Metadata, rows: &[DataRow]) -> Result<String> {
+    let indexes = indexes.iter().map(|index| {
+        let fresh;
+        let index = if index.covers(rows.len()) {
+            index
+        } else {
+            fresh = FieldIndex::build(index.field.clone(), index.kind, rows);
+            &fresh
+        };
+        StoredIndex { field: index.field.to_string(), kind: index.kind, groups: index.entries.groups().cloned().collect() }
+    });
+    let stored = StoredIndexes {
+        written_by: metadata.modified_by.clone(),
+        written_at: metadata.modified_at,
+        rows: rows.len(),
+        indexes: indexes.collect(),
+    };
+    Ok(serde_json::to_string(&stored)?)
+}
+
+// The indexes defined in the chunk. Their stored rows are used if they were
+// written along with the current metadata, no rows were appended since, and
+// they are consistent with `rows`; otherwise the indexes are rebuilt. A chunk that cannot be read defines none.
+pub(crate) fn from_chunk(text: &str, metadata: &Metadata, rows: &[DataRow]) -> Vec<FieldIndex> {
+    let Ok(stored) = serde_json::from_str::<StoredIndexes>(text) else {
+        return Vec::new();
+    };
+    let current = stored.written_by == metadata.modified_by
+        && stored.written_at == metadata.modified_at
+        && stored.rows == rows.len()
+        && !metadata.has_appended_rows();
+
+    stored.indexes.into_iter()
+        .filter_map(|index| {
+            let field = parse_field_path(&index.field).ok()?;
+            let restored = if current { restore(&field, index.kind, index.groups, rows) } else { None };
+            Some(restored.unwrap_or_else(|| FieldIndex::build(field, index.kind, rows)))
+        })
+        .collect()
+}
+
+fn restore(field: &FieldPath, kind: IndexKind, groups: Vec<Vec<usize>>, rows: &[DataRow]) -> Option<FieldIndex> {
+    let mut entries = Entries::new(kind);
+    let mut seen = HashSet::new();
+    for group in groups {
+        let key = Key::of(field.resolve(&rows.get(*group.first()?)?.data)?, kind);
+        if entries.get(&key).is_some() || !group.iter().all(|&row| row < rows.len() && seen.insert(row)) || !group.is_sorted() {
+            return None;
+        }
+        *entries.get_or_default(key) = group;
+    }
+    Some(FieldIndex { field: field.clone(), kind, entries, covered: rows.len() })
+}
@@ -1,24 +1,62 @@
-#[cfg(feature = "cli")]
-use color_eyre::Result;
-
-#[cfg(not(feature = "cli"))]
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
-
+mod append;
+pub mod builder;
+pub mod expr;
+pub mod format;
+pub mod functions;
+pub mod index;
+pub mod path;
+pub mod planner;
+pub mod prepared;
+pub mod query;
+pub mod schema;
+pub mod spatial;
 #[cfg(feature = "wasm")]
 pub mod web;
+
+pub use builder::Operand;
+pub use expr::{ArithOp, Expr};
+pub use format::{Metadata, FORMAT_VERSION};
+pub use functions::FunctionRegistry;
+use format::{
+    FEATURE_APPENDED_ROWS, FEATURE_CELL_POLICY, FEATURE_ITXT, FEATURE_ROW_SEQ, FIELD_INDEXES_KEYWORD, METADATA_KEYWORD,
+    SPATIAL_INDEX_KEYWORD,
+};
+pub use index::{FieldIndex, IndexKind};
+pub use path::{FieldPath, PathSegment};
+pub use planner::{Access, ColumnStats, Plan};
+pub use prepared::PreparedQuery;
+pub use query::{
+    parse_field_path, parse_index_statement, parse_query, parse_select, parse_shape, AggregateFunc, ComparisonOp, Condition,
+    IndexStatement, Nearest, OrderBy, OrderKey, Param, Projection, Query, ResultSet, Select, SelectItem,
+};
+use query::{matches_query, parse_explain_for, parse_query_for, parse_select_for, KnownNames};
+pub use schema::{ConversionRule, FieldKind, FieldType, HasSchema, Schema, Violation};
+pub use spatial::{Shape, SpatialIndex};
+#[cfg(feature = "derive")]
+pub use png_db_derive::HasSchema;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use png::{Decoder, Encoder, ColorType, BitDepth};
+use png::text_metadata::ITXtChunk;
 use serde_json::Value;
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::sync::OnceLock;
 #[cfg(not(target_arch = "wasm32"))]
-use std::fs::File;
 use std::io::{BufReader, BufWriter};
 use thiserror::Error;
 
+pub type Result<T> = std::result::Result<T, PngDbError>;
+
 #[derive(Error, Debug)]
 pub enum PngDbError {
     #[error("PNG format error: {0}")]
     PngError(#[from] png::DecodingError),
+    #[error("PNG encoding error: {0}")]
+    EncodingError(#[from] png::EncodingError),
     #[error("JSON parsing error: {0}")]
     JsonError(#[from] serde_json::Error),
     #[error("IO error: {0}")]
@@ -27,25 +65,122 @@ pub enum PngDbError {
     DatabaseError(String),
     #[error("Query error: {0}")]
     QueryError(String),
+    #[error("Schema error: {0}")]
+    SchemaError(String),
+    #[error("Coordinates ({x}, {y}) out of bounds for a {width}x{height} database")]
+    OutOfBounds { x: u32, y: u32, width: u32, height: u32 },
+    #[error("Cell ({x}, {y}) is already occupied")]
+    CellOccupied { x: u32, y: u32 },
+    // `row` is the cell of the offending row when the data was already stored
+    #[error("Schema violation{}: {}", row.map(|(x, y)| format!(" at ({}, {})", x, y)).unwrap_or_default(),
+        violations.iter().map(Violation::to_string).collect::<Vec<_>>().join("; "))]
+    SchemaViolation { row: Option<(u32, u32)>, violations: Vec<Violation> },
+    // `position` is the character offset into `query`; the message shows the
+    // offending line with a caret under that position
+    #[error("{}", query::describe_parse_error(message, *position, query))]
+    ParseError { message: String, position: usize, query: String },
+    #[error("Cannot store {type_name} as row data: {source}")]
+    SerializeError { type_name: &'static str, source: serde_json::Error },
+    #[error("Cannot read {} as {type_name}: {source}", row.map(|(x, y)| format!("row at ({}, {})", x, y)).unwrap_or_else(|| "result row".to_string()))]
+    DeserializeError { type_name: &'static str, row: Option<(u32, u32)>, source: serde_json::Error },
+}
+
+// How `insert` treats a cell that already holds a row
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CellPolicy {
+    // Reject the insert
+    #[default]
+    Unique,
+    // Replace the existing row's data
+    Upsert,
+    // Keep every row, numbered by a per-cell sequence
+    Multi,
+}
+
+impl std::str::FromStr for CellPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "unique" => Ok(CellPolicy::Unique),
+            "upsert" => Ok(CellPolicy::Upsert),
+            "multi" => Ok(CellPolicy::Multi),
+            _ => Err(format!("Unknown cell policy '{}', expected unique, upsert or multi", s)),
+        }
+    }
 }
 
+impl std::fmt::Display for CellPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            CellPolicy::Unique => "unique",
+            CellPolicy::Upsert => "upsert",
+            CellPolicy::Multi => "multi",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Database-wide settings stored in the "config" chunk
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Schema {
-    pub fields: HashMap<String, String>,
+pub(crate) struct Config {
+    #[serde(default)]
+    pub cell_policy: CellPolicy,
+    #[serde(default)]
+    pub persist_spatial_index: bool,
 }
 
 #[derive(Debug, Clone)]
 pub struct DataRow {
     pub x: u32,
     pub y: u32,
+    // Position of the row within its cell; always 0 unless the cell policy is `Multi`
+    pub seq: u32,
     pub data: Value,
 }
 
+impl DataRow {
+    pub(crate) fn keyword(&self) -> String {
+        if self.seq == 0 {
+            format!("row_{}_{}", self.x, self.y)
+        } else {
+            format!("row_{}_{}_{}", self.x, self.y, self.seq)
+        }
+    }
+
+    // Parses a `row_x_y` or `row_x_y_seq` chunk keyword
+    pub(crate) fn parse_keyword(keyword: &str) -> Option<(u32, u32, u32)> {
+        let parts: Vec<&str> = keyword.strip_prefix("row_")?.split('_').collect();
+        match parts.as_slice() {
+            [x, y] => Some((x.parse().ok()?, y.parse().ok()?, 0)),
+            [x, y, seq] => Some((x.parse().ok()?, y.parse().ok()?, seq.parse().ok()?)),
+            _ => None,
+        }
+    }
+}
+
 pub struct PngDatabase {
     pub width: u32,
     pub height: u32,
     pub schema: Schema,
+    pub cell_policy: CellPolicy,
+    pub metadata: Metadata,
     pub rows: Vec<DataRow>,
+    // Whether saves include the spatial index, so that loads can skip building it
+    pub persist_spatial_index: bool,
+    // Not stored in the file; each process registers its own functions
+    functions: FunctionRegistry,
+    // Built at load time or on first use, and dropped whenever rows are added
+    // or removed
+    spatial: OnceLock<SpatialIndex>,
+    // Created with `CREATE INDEX`, stored in the file and kept up to date by
+    // every insert, update and delete
+    indexes: Vec<FieldIndex>,
+    // Whether rows, the schema or the indexes changed since the database was
+    // loaded, saved or appended to, which appends alone cannot write. Changes
+    // made directly through the public fields are not tracked.
+    unsaved: bool,
 }
 
 impl PngDatabase {
@@ -54,13 +189,143 @@ impl PngDatabase {
             width,
             height,
             schema,
+            cell_policy: CellPolicy::default(),
+            metadata: Metadata::new(),
             rows: Vec::new(),
+            persist_spatial_index: false,
+            functions: FunctionRegistry::new(),
+            spatial: OnceLock::new(),
+            indexes: Vec::new(),
+            unsaved: false,
         }
     }
 
+    // Assembles a database from decoded chunks. Files without a config chunk
+    // predate cell policies and load as `Multi` so that no row is lost.
+    pub(crate) fn from_chunks(
+        width: u32,
+        height: u32,
+        schema: Schema,
+        config: Option<Config>,
+        chunks: Vec<DataRow>,
+    ) -> Result<Self> {
+        let mut db = Self::new(width, height, schema);
+        db.cell_policy = config.as_ref().map(|c| c.cell_policy).unwrap_or(CellPolicy::Multi);
+        db.persist_spatial_index = config.is_some_and(|c| c.persist_spatial_index);
+
+        let mut cells: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
+        for mut row in chunks {
+            let occupants = cells.entry((row.x, row.y)).or_default();
+            match db.cell_policy {
+                CellPolicy::Unique if !occupants.is_empty() => {
+                    return Err(PngDbError::DatabaseError(
+                        format!("Duplicate rows at ({}, {}) in a database with unique cells", row.x, row.y)
+                    ));
+                }
+                CellPolicy::Upsert if !occupants.is_empty() => {
+                    db.rows[occupants[0]].data = row.data;
+                    continue;
+                }
+                CellPolicy::Multi if occupants.iter().any(|&i| db.rows[i].seq == row.seq) => {
+                    row.seq = occupants.iter().map(|&i| db.rows[i].seq).max().unwrap_or(0) + 1;
+                }
+                CellPolicy::Unique | CellPolicy::Upsert => row.seq = 0,
+                CellPolicy::Multi => {}
+            }
+            occupants.push(db.rows.len());
+            db.rows.push(row);
+        }
+
+        Ok(db)
+    }
+
+    // Decodes the (keyword, text) chunks of a file, dispatching on the format version
+    // recorded in its metadata chunk. Files without one are format 1.
+    fn from_text_chunks(width: u32, height: u32, chunks: Vec<(String, String)>) -> Result<Self> {
+        let metadata = match chunks.iter().find(|(keyword, _)| keyword == METADATA_KEYWORD) {
+            Some((_, text)) => serde_json::from_str::<Metadata>(text)?,
+            None => Metadata::legacy(),
+        };
+        metadata.check_supported()?;
+
+        let mut schema = Schema::new(HashMap::new());
+        let mut config = None;
+        let mut spatial_index = None;
+        let mut field_indexes = None;
+        let mut rows = Vec::new();
+
+        for (keyword, text) in chunks {
+            if keyword == "schema" {
+                schema = serde_json::from_str(&text)?;
+            } else if keyword == "config" {
+                config = Some(serde_json::from_str(&text)?);
+            } else if keyword == SPATIAL_INDEX_KEYWORD {
+                spatial_index = Some(text);
+            } else if keyword == FIELD_INDEXES_KEYWORD {
+                field_indexes = Some(text);
+            } else if let Some((x, y, seq)) = DataRow::parse_keyword(&keyword) {
+                let row_data: Value = serde_json::from_str(&text)?;
+                rows.push(DataRow { x, y, seq, data: row_data });
+            }
+        }
+
+        // Format 1 files without a config chunk predate cell policies; from format 2
+        // on the config chunk is always written, so a missing one means the default
+        if metadata.format_version >= 2 && config.is_none() {
+            config = Some(Config { cell_policy: CellPolicy::default(), persist_spatial_index: false });
+        }
+
+        let mut db = Self::from_chunks(width, height, schema, config, rows)?;
+        db.metadata = metadata;
+
+        // A stored index is only trusted if it still describes the rows as loaded
+        let index = spatial_index
+            .and_then(|text| SpatialIndex::from_chunk(&text, &db.metadata, &db.rows))
+            .unwrap_or_else(|| SpatialIndex::build(&db.rows));
+        let _ = db.spatial.set(index);
+        if let Some(text) = field_indexes {
+            db.indexes = index::from_chunk(&text, &db.metadata, &db.rows);
+        }
+        Ok(db)
+    }
+
+    // The metadata for a save and the (keyword, text) chunks written for this
+    // database, metadata first
+    fn text_chunks(&self) -> Result<(Metadata, Vec<(String, String)>)> {
+        let metadata = self.metadata.for_save(self.features());
+        let mut chunks = vec![
+            (METADATA_KEYWORD.to_string(), serde_json::to_string(&metadata)?),
+            ("schema".to_string(), serde_json::to_string(&self.schema)?),
+            ("config".to_string(), serde_json::to_string(&self.config())?),
+        ];
+        for row in &self.rows {
+            chunks.push((row.keyword(), serde_json::to_string(&row.data)?));
+        }
+        if self.persist_spatial_index {
+            chunks.push((SPATIAL_INDEX_KEYWORD.to_string(), self.spatial_index().to_chunk(&metadata)?));
+        }
+        if !self.indexes.is_empty() {
+            chunks.push((FIELD_INDEXES_KEYWORD.to_string(), index::to_chunk(&self.indexes, &metadata, &self.rows)?));
+        }
+        Ok((metadata, chunks))
+    }
+
+    // Format features the rows rely on
+    fn features(&self) -> Vec<String> {
+        let mut features = vec![FEATURE_ITXT.to_string(), FEATURE_CELL_POLICY.to_string()];
+        if self.rows.iter().any(|row| row.seq > 0) {
+            features.push(FEATURE_ROW_SEQ.to_string());
+        }
+        features
+    }
+
+    pub(crate) fn config(&self) -> Config {
+        Config { cell_policy: self.cell_policy, persist_spatial_index: self.persist_spatial_index }
+    }
+
     #[cfg(not(target_arch = "wasm32"))]
     pub fn create_empty_png(width: u32, height: u32, schema: Schema, filename: &str) -> Result<Self> {
-        let db = Self::new(width, height, schema);
+        let mut db = Self::new(width, height, schema);
         db.save_to_png(filename)?;
         Ok(db)
     }
@@ -68,264 +333,695 @@ impl PngDatabase {
     #[cfg(not(target_arch = "wasm32"))]
     pub fn load_from_png(filename: &str) -> Result<Self> {
         let file = File::open(filename)?;
-        let reader = BufReader::new(file);
+        Self::from_reader(BufReader::new(file))
+    }
+
+    // Decodes a database from any PNG byte stream: a file, an in-memory buffer, a socket...
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
         let decoder = Decoder::new(reader);
-        let reader = decoder.read_info()?;
+        let mut reader = decoder.read_info()?;
+
+        // Appended rows come after the image data, so only then is it read through
+        if has_appended_rows(reader.info()) {
+            reader.finish()?;
+        }
 
         let info = reader.info();
         let width = info.width;
         let height = info.height;
 
-        let mut schema = Schema { fields: HashMap::new() };
-        let mut rows = Vec::new();
+        Self::from_text_chunks(width, height, read_text_chunks(info)?)
+    }
+
+    pub fn insert(&mut self, x: u32, y: u32, data: Value) -> Result<()> {
+        if x >= self.width || y >= self.height {
+            return Err(PngDbError::OutOfBounds { x, y, width: self.width, height: self.height });
+        }
+        self.schema.validate(&data)?;
 
-        // Read zTXt chunks
-        for chunk in reader.info().compressed_latin1_text.iter() {
-            if chunk.keyword == "schema" {
-                let decompressed_text = chunk.get_text()?;
-                schema = serde_json::from_str(&decompressed_text)?;
-            } else if chunk.keyword.starts_with("row_") {
-                let decompressed_text = chunk.get_text()?;
-                let row_data: Value = serde_json::from_str(&decompressed_text)?;
-                
-                // Extract coordinates from keyword (row_x_y format)
-                let coords: Vec<&str> = chunk.keyword.split('_').collect();
-                if coords.len() == 3 {
-                    let x = coords[1].parse::<u32>().unwrap_or(0);
-                    let y = coords[2].parse::<u32>().unwrap_or(0);
-                    
-                    rows.push(DataRow {
-                        x,
-                        y,
-                        data: row_data,
-                    });
+        let mut occupants = self.rows.iter().enumerate().filter(|(_, row)| row.x == x && row.y == y);
+        let seq = match self.cell_policy {
+            CellPolicy::Unique => {
+                if occupants.next().is_some() {
+                    return Err(PngDbError::CellOccupied { x, y });
                 }
+                0
             }
-        }
+            CellPolicy::Upsert => match occupants.next() {
+                Some((index, _)) => {
+                    self.replace_data(index, data);
+                    return Ok(());
+                }
+                None => 0,
+            },
+            CellPolicy::Multi => occupants.map(|(_, row)| row.seq + 1).max().unwrap_or(0),
+        };
 
-        Ok(Self {
-            width,
-            height,
-            schema,
-            rows,
-        })
+        self.rows.push(DataRow { x, y, seq, data });
+        let index = self.rows.len() - 1;
+        for field_index in &mut self.indexes {
+            field_index.insert_row(index, &self.rows[index].data);
+        }
+        self.spatial.take();
+        self.unsaved = true;
+        Ok(())
     }
 
-    pub fn insert(&mut self, x: u32, y: u32, data: Value) -> Result<()> {
-        if x >= self.width || y >= self.height {
-            return Err(PngDbError::DatabaseError(
-                format!("Coordinates ({}, {}) out of bounds", x, y)
-            ).into());
+    // Inserts a row as `insert` does and writes it to `stream`, which must hold
+    // this database as loaded, saved or last appended to, without rewriting the
+    // file: the row goes in a chunk just before IEND and only the metadata chunk
+    // is rewritten, in place. Fails without inserting anything if the stream was
+    // saved by a png-db without appends or has changed since, or if the
+    // database has other changes that were not saved.
+    pub fn append_to<S: Read + Write + Seek>(&mut self, stream: &mut S, x: u32, y: u32, data: Value) -> Result<()> {
+        let found = append::find_metadata(stream)?.ok_or_else(|| {
+            PngDbError::DatabaseError("File has no room to record appended rows; save it in full first".to_string())
+        })?;
+        if found.metadata != self.metadata {
+            return Err(PngDbError::DatabaseError("File has changed since the database was loaded".to_string()));
+        }
+        if self.unsaved {
+            return Err(PngDbError::DatabaseError("Database has changes that were not saved; save it in full first".to_string()));
         }
+        self.insert(x, y, data)?;
 
-        self.rows.push(DataRow { x, y, data });
+        // The row now in the cell: the new one, or the one an upsert replaced
+        // the data of. Loads apply appended chunks in order, as inserts.
+        let row = self.rows.iter()
+            .filter(|row| row.x == x && row.y == y)
+            .max_by_key(|row| row.seq)
+            .expect("cell has a row after an insert");
+        let chunk = (row.keyword(), serde_json::to_string(&row.data)?);
+
+        let mut features = self.features();
+        features.push(FEATURE_APPENDED_ROWS.to_string());
+        let metadata = self.metadata.for_save(features);
+        append::append_rows(stream, &found, &metadata, &[chunk])?;
+        self.metadata = metadata;
+        self.unsaved = false;
         Ok(())
     }
 
+    // `append_to` for a file. The file is saved in full instead if it was saved
+    // by a png-db without appends, which makes room for the next ones, or if the
+    // database has other changes to write.
     #[cfg(not(target_arch = "wasm32"))]
-    pub fn save_to_png(&self, filename: &str) -> Result<()> {
+    pub fn append_to_png(&mut self, filename: &str, x: u32, y: u32, data: Value) -> Result<()> {
+        let mut file = OpenOptions::new().read(true).write(true).open(filename)?;
+        match append::find_metadata(&mut file)? {
+            Some(found) if found.metadata != self.metadata => {
+                return Err(PngDbError::DatabaseError("File has changed since the database was loaded".to_string()));
+            }
+            Some(_) if !self.unsaved => return self.append_to(&mut file, x, y, data),
+            _ => {}
+        }
+        drop(file);
+
+        self.insert(x, y, data)?;
+        self.save_to_png(filename)
+    }
+
+    // Inserts a row into the database saved in `stream` without loading it:
+    // only the chunk headers, the keywords of the rows and the metadata, schema
+    // and config chunks are read, then the row is appended as `append_to` does.
+    // Fails without writing anything if the stream has no room to record
+    // appended rows.
+    pub fn append_row<S: Read + Write + Seek>(stream: &mut S, x: u32, y: u32, data: Value) -> Result<()> {
+        let summary = append::summarize(stream)?.ok_or_else(|| {
+            PngDbError::DatabaseError("File has no room to record appended rows; save it in full first".to_string())
+        })?;
+        let metadata = &summary.found.metadata;
+        metadata.check_supported()?;
+
+        if x >= summary.width || y >= summary.height {
+            return Err(PngDbError::OutOfBounds { x, y, width: summary.width, height: summary.height });
+        }
+        let schema = match &summary.schema {
+            Some(text) => serde_json::from_str(text)?,
+            None => Schema::new(HashMap::new()),
+        };
+        schema.validate(&data)?;
+        let config = match &summary.config {
+            Some(text) => serde_json::from_str(text)?,
+            None => Config { cell_policy: CellPolicy::default(), persist_spatial_index: false },
+        };
+
+        // Loads apply appended chunks in order, as inserts: an upsert's row
+        // replaces the data of the one in its cell
+        let seq = match (config.cell_policy, summary.cells.get(&(x, y))) {
+            (CellPolicy::Unique, Some(_)) => return Err(PngDbError::CellOccupied { x, y }),
+            (CellPolicy::Multi, Some(highest)) => highest + 1,
+            _ => 0,
+        };
+        let row = DataRow { x, y, seq, data };
+
+        let mut needed = vec![FEATURE_APPENDED_ROWS];
+        if seq > 0 {
+            needed.push(FEATURE_ROW_SEQ);
+        }
+        let mut features = metadata.features.clone();
+        for feature in needed {
+            if !features.iter().any(|f| f == feature) {
+                features.push(feature.to_string());
+            }
+        }
+        let updated = metadata.for_save(features);
+        append::append_rows(stream, &summary.found, &updated, &[(row.keyword(), serde_json::to_string(&row.data)?)])
+    }
+
+    // `append_row` for a file. Files saved by a png-db without appends are
+    // loaded and saved in full this once, which makes room for the next appends.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn append_row_to_png(filename: &str, x: u32, y: u32, data: Value) -> Result<()> {
+        let mut file = OpenOptions::new().read(true).write(true).open(filename)?;
+        if append::find_metadata(&mut file)?.is_some() {
+            return Self::append_row(&mut file, x, y, data);
+        }
+        drop(file);
+
+        let mut db = Self::load_from_png(filename)?;
+        db.insert(x, y, data)?;
+        db.save_to_png(filename)
+    }
+
+    // Replaces the data of the row at `index`, keeping the field indexes in step
+    fn replace_data(&mut self, index: usize, data: Value) {
+        let old = std::mem::replace(&mut self.rows[index].data, data);
+        for field_index in &mut self.indexes {
+            field_index.update_row(index, &old, &self.rows[index].data);
+        }
+        self.unsaved = true;
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save_to_png(&mut self, filename: &str) -> Result<()> {
         let file = File::create(filename)?;
-        let w = &mut BufWriter::new(file);
-        
+        let mut w = BufWriter::new(file);
+        self.to_writer(&mut w)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    // Encodes the database as a PNG into any byte sink, and takes on the
+    // metadata written, so that appends can follow
+    pub fn to_writer<W: Write>(&mut self, w: W) -> Result<()> {
         let mut encoder = Encoder::new(w, self.width, self.height);
         encoder.set_color(ColorType::Rgb);
         encoder.set_depth(BitDepth::Eight);
         
-        // Add schema as zTXt chunk
-        let schema_json = serde_json::to_string(&self.schema)?;
-        encoder.add_ztxt_chunk("schema".to_string(), schema_json)?;
-        
-        // Add each row as a zTXt chunk
-        for row in &self.rows {
-            let row_json = serde_json::to_string(&row.data)?;
-            let keyword = format!("row_{}_{}", row.x, row.y);
-            encoder.add_ztxt_chunk(keyword, row_json)?;
-        }
-        
         let mut writer = encoder.write_header()?;
+
+        // Metadata, schema, config and rows go in compressed UTF-8 iTXt chunks ahead of the image data,
+        // except for the metadata, which appends rewrite in place
+        let (metadata, chunks) = self.text_chunks()?;
+        for (keyword, text) in chunks {
+            if keyword == METADATA_KEYWORD {
+                writer.write_text_chunk(&metadata_chunk(text))?;
+            } else {
+                writer.write_text_chunk(&itxt_chunk(&keyword, text))?;
+            }
+        }
         
         // Create a simple RGB image with black pixels
         let image_data = vec![0u8; (self.width * self.height * 3) as usize];
         writer.write_image_data(&image_data)?;
         writer.finish()?;
+        self.metadata = metadata;
+        self.unsaved = false;
         
         Ok(())
     }
 
-    pub fn query(&self, query_str: &str) -> Result<Vec<&DataRow>> {
-        let query = parse_query(query_str)?;
-        let mut results = Vec::new();
+    pub fn update(&mut self, where_clause: &str, patch: Value) -> Result<usize> {
+        let query = parse_query_for(where_clause, Some(self.known_names()))?;
+        self.update_matching(&query, patch)
+    }
 
-        for row in &self.rows {
-            if matches_query(row, &query)? {
-                results.push(row);
+    pub fn update_matching(&mut self, query: &Query, patch: Value) -> Result<usize> {
+        let mut patched = Vec::new();
+
+        // Validate every patched row before touching any of them
+        for index in self.matching_rows(Some(query))? {
+            let row = &self.rows[index];
+            let mut data = row.data.clone();
+            merge_patch(&mut data, &patch);
+            self.schema.validate(&data).map_err(|e| row_error(row, e))?;
+            patched.push((index, data));
+        }
+
+        let updated = patched.len();
+        for (index, data) in patched {
+            self.replace_data(index, data);
+        }
+
+        Ok(updated)
+    }
+
+    pub fn delete(&mut self, where_clause: &str) -> Result<usize> {
+        let query = parse_query_for(where_clause, Some(self.known_names()))?;
+        self.delete_matching(&query)
+    }
+
+    pub fn delete_matching(&mut self, query: &Query) -> Result<usize> {
+        let mut to_delete = vec![false; self.rows.len()];
+        for index in self.matching_rows(Some(query))? {
+            to_delete[index] = true;
+        }
+
+        let before = self.rows.len();
+        let mut flags = to_delete.iter();
+        self.rows.retain(|_| !flags.next().copied().unwrap_or(false));
+        for field_index in &mut self.indexes {
+            field_index.remove_rows(&to_delete);
+        }
+        self.spatial.take();
+        self.unsaved |= self.rows.len() < before;
+        Ok(before - self.rows.len())
+    }
+
+    pub fn add_column(&mut self, name: &str, field_type: &str, default: Value) -> Result<usize> {
+        if self.schema.fields.contains_key(name) {
+            return Err(PngDbError::SchemaError(format!("Column '{}' already exists", name)));
+        }
+        let parsed = parse_field_type(name, field_type)?;
+        if !default.is_null() && !parsed.accepts(&default) {
+            return Err(PngDbError::SchemaError(
+                format!("Default value {} does not match type {} of column '{}'", default, parsed, name)
+            ));
+        }
+
+        let mut schema = self.schema.clone();
+        schema.fields.insert(name.to_string(), field_type.to_string());
+
+        self.evolve(schema, |object| {
+            if object.contains_key(name) || default.is_null() {
+                return Ok(false);
             }
+            object.insert(name.to_string(), default.clone());
+            Ok(true)
+        })
+    }
+
+    pub fn drop_column(&mut self, name: &str) -> Result<usize> {
+        let mut schema = self.schema.clone();
+        if schema.fields.remove(name).is_none() {
+            return Err(PngDbError::SchemaError(format!("Column '{}' does not exist", name)));
         }
 
-        Ok(results)
+        self.evolve(schema, |object| Ok(object.remove(name).is_some()))
     }
-}
 
-#[derive(Debug)]
-pub struct Query {
-    pub conditions: Vec<Condition>,
-}
+    pub fn rename_column(&mut self, from: &str, to: &str) -> Result<usize> {
+        if self.schema.fields.contains_key(to) {
+            return Err(PngDbError::SchemaError(format!("Column '{}' already exists", to)));
+        }
+        let mut schema = self.schema.clone();
+        let field_type = schema.fields.remove(from)
+            .ok_or_else(|| PngDbError::SchemaError(format!("Column '{}' does not exist", from)))?;
+        schema.fields.insert(to.to_string(), field_type);
 
-#[derive(Debug)]
-pub enum Condition {
-    Coordinate { field: String, op: ComparisonOp, value: u32 },
-    JsonField { field: String, op: ComparisonOp, value: Value },
-}
+        self.evolve(schema, |object| {
+            if object.contains_key(to) {
+                return Err(Violation::new(to, "is already present").into());
+            }
+            match object.remove(from) {
+                Some(value) => {
+                    object.insert(to.to_string(), value);
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        })
+    }
 
-#[derive(Debug)]
-pub enum ComparisonOp {
-    Equal,
-    NotEqual,
-    GreaterThan,
-    LessThan,
-    GreaterThanOrEqual,
-    LessThanOrEqual,
-}
+    pub fn change_column_type(&mut self, name: &str, field_type: &str, rule: ConversionRule) -> Result<usize> {
+        if !self.schema.fields.contains_key(name) {
+            return Err(PngDbError::SchemaError(format!("Column '{}' does not exist", name)));
+        }
+        let parsed = parse_field_type(name, field_type)?;
 
-pub fn parse_query(query_str: &str) -> Result<Query> {
-    // Simple parser for WHERE clauses
-    let query_str = query_str.trim();
-    
-    if !query_str.to_lowercase().starts_with("where") {
-        return Err(PngDbError::QueryError("Query must start with WHERE".to_string()).into());
-    }
-    
-    let conditions_str = &query_str[5..].trim(); // Remove "WHERE"
-    let condition_parts: Vec<&str> = conditions_str.split(" AND ").collect();
-    
-    let mut conditions = Vec::new();
-    
-    for part in condition_parts {
-        let condition = parse_condition(part.trim())?;
-        conditions.push(condition);
-    }
-    
-    Ok(Query { conditions })
-}
+        let mut schema = self.schema.clone();
+        schema.fields.insert(name.to_string(), field_type.to_string());
 
-fn parse_condition(condition_str: &str) -> Result<Condition> {
-    let operators = [">=", "<=", "!=", "=", ">", "<"];
-    
-    for op_str in &operators {
-        if let Some(pos) = condition_str.find(op_str) {
-            let field = condition_str[..pos].trim();
-            let value_str = condition_str[pos + op_str.len()..].trim();
-            
-            let op = match *op_str {
-                "=" => ComparisonOp::Equal,
-                "!=" => ComparisonOp::NotEqual,
-                ">" => ComparisonOp::GreaterThan,
-                "<" => ComparisonOp::LessThan,
-                ">=" => ComparisonOp::GreaterThanOrEqual,
-                "<=" => ComparisonOp::LessThanOrEqual,
-                _ => unreachable!(),
+        self.evolve(schema, |object| {
+            let Some(value) = object.get(name) else {
+                return Ok(false);
             };
-            
-            // Check if it's a coordinate field
-            if field == "x" || field == "y" {
-                let value = value_str.parse::<u32>()
-                    .map_err(|_| PngDbError::QueryError(format!("Invalid coordinate value: {}", value_str)))?;
-                return Ok(Condition::Coordinate {
-                    field: field.to_string(),
-                    op,
-                    value,
-                });
+            if parsed.accepts(value) {
+                return Ok(false);
             }
-            
-            // Parse JSON value
-            let value = if value_str.starts_with('"') && value_str.ends_with('"') {
-                Value::String(value_str[1..value_str.len()-1].to_string())
-            } else if let Ok(num) = value_str.parse::<i64>() {
-                Value::Number(serde_json::Number::from(num))
-            } else if let Ok(float) = value_str.parse::<f64>() {
-                Value::Number(serde_json::Number::from_f64(float).unwrap())
-            } else if value_str == "true" {
-                Value::Bool(true)
-            } else if value_str == "false" {
-                Value::Bool(false)
+
+            match (parsed.convert(value), &rule) {
+                (Some(converted), _) => {
+                    object.insert(name.to_string(), converted);
+                }
+                (None, ConversionRule::Fail) => {
+                    return Err(Violation::new(name, format!("cannot be converted from {} to {}", value, parsed)).into());
+                }
+                (None, ConversionRule::SetNull) => {
+                    object.insert(name.to_string(), Value::Null);
+                }
+                (None, ConversionRule::Remove) => {
+                    object.remove(name);
+                }
+                (None, ConversionRule::Default(default)) => {
+                    object.insert(name.to_string(), default.clone());
+                }
+            }
+            Ok(true)
+        })
+    }
+
+    // Rewrites every object row with `rewrite` and installs `schema` as the next
+    // schema version. Nothing changes unless every row, rewritten or not, is
+    // valid under the new schema.
+    fn evolve<F>(&mut self, mut schema: Schema, mut rewrite: F) -> Result<usize>
+    where
+        F: FnMut(&mut serde_json::Map<String, Value>) -> Result<bool>,
+    {
+        let mut rewritten = Vec::new();
+
+        for (index, row) in self.rows.iter().enumerate() {
+            // Rows that are not objects have no columns to rewrite, but must still fit
+            let Value::Object(object) = &row.data else {
+                schema.validate(&row.data).map_err(|e| row_error(row, e))?;
+                continue;
+            };
+            let mut object = object.clone();
+            if rewrite(&mut object).map_err(|e| row_error(row, e))? {
+                let data = Value::Object(object);
+                schema.validate(&data).map_err(|e| row_error(row, e))?;
+                rewritten.push((index, data));
             } else {
-                Value::String(value_str.to_string())
+                schema.validate(&row.data).map_err(|e| row_error(row, e))?;
+            }
+        }
+
+        schema.version = self.schema.version + 1;
+        self.schema = schema;
+        self.unsaved = true;
+
+        let count = rewritten.len();
+        for (index, data) in rewritten {
+            self.replace_data(index, data);
+        }
+
+        Ok(count)
+    }
+
+    pub fn query(&self, query_str: &str) -> Result<ResultSet> {
+        let select = parse_select_for(query_str, Some(self.known_names()))?;
+        self.select(&select)
+    }
+
+    // The `k` rows closest to (x, y) that match `filter`, nearest first; rows at
+    // the same distance come in storage order. Only rows near the point are
+    // visited, through the spatial index.
+    pub fn nearest(&self, x: f64, y: f64, k: usize, filter: Option<&Query>) -> Result<Vec<&DataRow>> {
+        let index = self.spatial_index();
+        let mut found = Vec::with_capacity(k.min(self.rows.len()));
+        for row in index.nearest(x, y).map(|index| &self.rows[index]) {
+            if found.len() == k {
+                break;
+            }
+            let keep = match filter {
+                Some(filter) => matches_query(row, filter, &self.functions)?,
+                None => true,
             };
-            
-            return Ok(Condition::JsonField {
-                field: field.to_string(),
-                op,
-                value,
-            });
+            if keep {
+                found.push(row);
+            }
         }
+        Ok(found)
     }
-    
-    Err(PngDbError::QueryError(format!("Invalid condition: {}", condition_str)).into())
-}
 
-fn matches_query(row: &DataRow, query: &Query) -> Result<bool> {
-    for condition in &query.conditions {
-        if !matches_condition(row, condition)? {
-            return Ok(false);
+    // The spatial index over `rows`. One cached before rows were pushed or
+    // removed directly is not used; a fresh one is built instead.
+    pub fn spatial_index(&self) -> Cow<'_, SpatialIndex> {
+        match self.spatial.get() {
+            Some(index) if index.len() == self.rows.len() => Cow::Borrowed(index),
+            Some(_) => Cow::Owned(SpatialIndex::build(&self.rows)),
+            None => Cow::Borrowed(self.spatial.get_or_init(|| SpatialIndex::build(&self.rows))),
         }
     }
-    Ok(true)
-}
 
-fn matches_condition(row: &DataRow, condition: &Condition) -> Result<bool> {
-    match condition {
-        Condition::Coordinate { field, op, value } => {
-            let coord_value = if field == "x" { row.x } else { row.y };
-            Ok(compare_numbers(coord_value as i64, *value as i64, op))
+    // Creates an index on `field`, used by =, IN and, for a B-tree, range
+    // conditions on it
+    pub fn create_index(&mut self, field: FieldPath, kind: IndexKind) -> Result<()> {
+        if field.is_coordinate() {
+            return Err(PngDbError::DatabaseError(format!("{} is a coordinate, covered by the spatial index", field)));
+        }
+        if self.indexes.iter().any(|index| index.field() == &field) {
+            return Err(PngDbError::DatabaseError(format!("Index on '{}' already exists", field)));
+        }
+        self.indexes.push(FieldIndex::build(field, kind, &self.rows));
+        self.unsaved = true;
+        Ok(())
+    }
+
+    pub fn drop_index(&mut self, field: &FieldPath) -> Result<()> {
+        let before = self.indexes.len();
+        self.indexes.retain(|index| index.field() != field);
+        if self.indexes.len() == before {
+            return Err(PngDbError::DatabaseError(format!("No index on '{}'", field)));
+        }
+        self.unsaved = true;
+        Ok(())
+    }
+
+    // Runs `CREATE INDEX ON field [USING BTREE | HASH]` or `DROP INDEX ON field`
+    pub fn execute_index_statement(&mut self, statement: &str) -> Result<()> {
+        match parse_index_statement(statement)? {
+            IndexStatement::Create { field, kind } => self.create_index(field, kind),
+            IndexStatement::Drop { field } => self.drop_index(&field),
         }
-        Condition::JsonField { field, op, value } => {
-            if let Some(field_value) = row.data.get(field) {
-                compare_json_values(field_value, value, op)
+    }
+
+    pub fn indexes(&self) -> &[FieldIndex] {
+        &self.indexes
+    }
+
+    // The index on `field`, unless rows were pushed or removed directly since it
+    // was last updated
+    pub(crate) fn field_index(&self, field: &FieldPath) -> Option<&FieldIndex> {
+        self.indexes.iter().find(|index| index.field() == field && index.covers(self.rows.len()))
+    }
+
+    // Statistics the planner uses for `field`: x and y, and indexed fields
+    pub fn column_stats(&self, field: &FieldPath) -> Option<ColumnStats> {
+        if field.is_coordinate() {
+            let (min, max) = match (field.as_key(), self.spatial_index().extent()) {
+                (Some("x"), Some((min_x, _, max_x, _))) => (Some(min_x as f64), Some(max_x as f64)),
+                (_, Some((_, min_y, _, max_y))) => (Some(min_y as f64), Some(max_y as f64)),
+                (_, None) => (None, None),
+            };
+            return Some(ColumnStats { rows: self.rows.len(), distinct: None, min, max });
+        }
+        let index = self.field_index(field)?;
+        let range = index.number_range();
+        Some(ColumnStats {
+            rows: index.len(),
+            distinct: Some(index.distinct()),
+            min: range.map(|(min, _)| min),
+            max: range.map(|(_, max)| max),
+        })
+    }
+
+    // How `select` would find its rows
+    pub fn plan(&self, select: &Select) -> Plan {
+        planner::plan_select(self, select)
+    }
+
+    // The plan for a query, written with or without a leading EXPLAIN
+    pub fn explain(&self, query_str: &str) -> Result<Plan> {
+        let select = parse_explain_for(query_str, Some(self.known_names()))?;
+        Ok(self.plan(&select))
+    }
+
+    // Rows whose cell lies in `shape`, in storage order
+    pub fn within(&self, shape: &Shape) -> Vec<&DataRow> {
+        let (min_x, min_y, max_x, max_y) = shape.bounds();
+        let mut candidates = self.spatial_index().within_bounds(min_x, min_y, max_x, max_y);
+        candidates.sort_unstable();
+        candidates.into_iter()
+            .map(|index| &self.rows[index])
+            .filter(|row| shape.contains(row.x as f64, row.y as f64))
+            .collect()
+    }
+
+    // Indices of the rows matching `filter`, in storage order. Only the rows
+    // found by the access path the planner picks are tested: e.g. the spatial
+    // index for WITHIN or `x > 10`, or a field index for `department = "Sales"`.
+    pub(crate) fn matching_rows(&self, filter: Option<&Query>) -> Result<Vec<usize>> {
+        let Some(filter) = filter else {
+            return Ok((0..self.rows.len()).collect());
+        };
+        let candidates = match planner::plan_filter(self, Some(filter)).access {
+            Access::CoordinateIndex { bounds: (min_x, min_y, max_x, max_y) }
+            | Access::SpatialIndex { bounds: (min_x, min_y, max_x, max_y) } => {
+                let mut candidates = self.spatial_index().within_bounds(min_x, min_y, max_x, max_y);
+                candidates.sort_unstable();
+                candidates
+            }
+            Access::FieldIndex { .. } => query::indexed_rows(filter, &|field| self.field_index(field))
+                .unwrap_or_else(|| (0..self.rows.len()).collect()),
+            Access::FullScan | Access::Nearest { .. } => (0..self.rows.len()).collect(),
+        };
+
+        let mut matched = Vec::new();
+        for index in candidates {
+            if matches_query(&self.rows[index], filter, &self.functions)? {
+                matched.push(index);
+            }
+        }
+        Ok(matched)
+    }
+
+    // Runs a parsed or built statement, e.g. `Select::new().filter(Query::field("age").gt(30))`
+    pub fn select(&self, select: &Select) -> Result<ResultSet> {
+        query::execute_select(select, self)
+    }
+
+    // Top-level fields declared in the schema or present in any row, plus x and
+    // y, and the registered functions. Query text naming a field close to a
+    // declared one, but not one of these, is rejected as a likely typo, as is a
+    // call to an unregistered function.
+    fn known_names(&self) -> KnownNames {
+        let mut declared: BTreeSet<&str> = self.schema.fields.keys().map(String::as_str).collect();
+        declared.extend(["x", "y"]);
+        let mut fields = declared.clone();
+        for row in &self.rows {
+            if let Value::Object(map) = &row.data {
+                fields.extend(map.keys().map(String::as_str));
+            }
+        }
+        KnownNames {
+            fields: fields.into_iter().map(str::to_string).collect(),
+            declared: declared.into_iter().map(str::to_string).collect(),
+            functions: self.functions.names().into_iter().map(str::to_string).collect(),
+        }
+    }
+
+    // Makes `name(...)` callable from this database's queries, replacing any
+    // function of the same name, built-ins included
+    pub fn register_function<F>(&mut self, name: &str, function: F)
+    where
+        F: Fn(&[Value]) -> Result<Value> + Send + Sync + 'static,
+    {
+        self.functions.register(name, function);
+    }
+
+    pub fn functions(&self) -> &FunctionRegistry {
+        &self.functions
+    }
+
+    pub fn execute(&self, prepared: &PreparedQuery, params: &[Value]) -> Result<ResultSet> {
+        self.select(&prepared.bind(params)?)
+    }
+
+    pub fn execute_named(&self, prepared: &PreparedQuery, params: &serde_json::Map<String, Value>) -> Result<ResultSet> {
+        self.select(&prepared.bind_named(params)?)
+    }
+
+    pub fn insert_as<T: Serialize>(&mut self, x: u32, y: u32, value: &T) -> Result<()> {
+        let data = serde_json::to_value(value)
+            .map_err(|source| PngDbError::SerializeError { type_name: std::any::type_name::<T>(), source })?;
+        self.insert(x, y, data)
+    }
+
+    // Runs a query and deserializes each result row, keyed by column name, into `T`
+    pub fn query_as<T: DeserializeOwned>(&self, query_str: &str) -> Result<Vec<T>> {
+        self.query(query_str)?.to_objects().into_iter().map(|object| {
+            let row = match (object.get("x").and_then(Value::as_u64), object.get("y").and_then(Value::as_u64)) {
+                (Some(x), Some(y)) => Some((x as u32, y as u32)),
+                _ => None,
+            };
+            serde_json::from_value(object)
+                .map_err(|source| PngDbError::DeserializeError { type_name: std::any::type_name::<T>(), row, source })
+        }).collect()
+    }
+
+    pub fn rows_as<T: DeserializeOwned>(&self) -> Result<Vec<T>> {
+        self.rows.iter().map(|row| {
+            T::deserialize(&row.data).map_err(|source| PngDbError::DeserializeError {
+                type_name: std::any::type_name::<T>(),
+                row: Some((row.x, row.y)),
+                source,
+            })
+        }).collect()
+    }
+}
+
+// Applies a JSON merge patch (RFC 7386): objects are merged recursively,
+// null removes a key and any other value replaces the target
+fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch_object) = patch else {
+        *target = patch.clone();
+        return;
+    };
+
+    if !target.is_object() {
+        *target = Value::Object(serde_json::Map::new());
+    }
+    if let Value::Object(target_object) = target {
+        for (key, value) in patch_object {
+            if value.is_null() {
+                target_object.remove(key);
             } else {
-                Ok(false)
+                merge_patch(target_object.entry(key.clone()).or_insert(Value::Null), value);
             }
         }
     }
 }
 
-fn compare_numbers(left: i64, right: i64, op: &ComparisonOp) -> bool {
-    match op {
-        ComparisonOp::Equal => left == right,
-        ComparisonOp::NotEqual => left != right,
-        ComparisonOp::GreaterThan => left > right,
-        ComparisonOp::LessThan => left < right,
-        ComparisonOp::GreaterThanOrEqual => left >= right,
-        ComparisonOp::LessThanOrEqual => left <= right,
+fn parse_field_type(name: &str, field_type: &str) -> Result<FieldType> {
+    field_type.parse::<FieldType>()
+        .map_err(|e| PngDbError::SchemaError(format!("{} for column '{}'", e, name)))
+}
+
+// Attaches the coordinates of the offending row to schema errors
+fn row_error(row: &DataRow, error: PngDbError) -> PngDbError {
+    match error {
+        PngDbError::SchemaViolation { violations, .. } => PngDbError::SchemaViolation { row: Some((row.x, row.y)), violations },
+        PngDbError::SchemaError(msg) => PngDbError::SchemaError(format!("row at ({}, {}): {}", row.x, row.y, msg)),
+        other => other,
     }
 }
 
-fn compare_json_values(left: &Value, right: &Value, op: &ComparisonOp) -> Result<bool> {
-    use Value::*;
-    
-    match (left, right) {
-        (String(l), String(r)) => Ok(match op {
-            ComparisonOp::Equal => l == r,
-            ComparisonOp::NotEqual => l != r,
-            _ => return Err(PngDbError::QueryError("String comparison only supports = and !=".to_string()).into()),
-        }),
-        (Number(l), Number(r)) => {
-            let l_val = l.as_f64().unwrap_or(0.0);
-            let r_val = r.as_f64().unwrap_or(0.0);
-            Ok(match op {
-                ComparisonOp::Equal => (l_val - r_val).abs() < f64::EPSILON,
-                ComparisonOp::NotEqual => (l_val - r_val).abs() >= f64::EPSILON,
-                ComparisonOp::GreaterThan => l_val > r_val,
-                ComparisonOp::LessThan => l_val < r_val,
-                ComparisonOp::GreaterThanOrEqual => l_val >= r_val,
-                ComparisonOp::LessThanOrEqual => l_val <= r_val,
-            })
-        }
-        (Bool(l), Bool(r)) => Ok(match op {
-            ComparisonOp::Equal => l == r,
-            ComparisonOp::NotEqual => l != r,
-            _ => return Err(PngDbError::QueryError("Boolean comparison only supports = and !=".to_string()).into()),
-        }),
-        _ => Err(PngDbError::QueryError("Cannot compare different value types".to_string()).into()),
+// Collects the text of every png-db chunk as (keyword, text) pairs. Files written before
+// UTF-8 support store Latin-1 zTXt chunks, which are read first; current files use iTXt.
+fn read_text_chunks(info: &png::Info) -> Result<Vec<(String, String)>> {
+    let mut chunks = Vec::new();
+    for chunk in &info.compressed_latin1_text {
+        chunks.push((chunk.keyword.clone(), chunk.get_text()?));
     }
+    for chunk in &info.utf8_text {
+        chunks.push((chunk.keyword.clone(), chunk.get_text()?));
+    }
+    Ok(chunks)
+}
+
+// Whether the metadata among `info`'s text chunks records appended rows
+fn has_appended_rows(info: &png::Info) -> bool {
+    info.utf8_text.iter()
+        .filter(|chunk| chunk.keyword == METADATA_KEYWORD)
+        .filter_map(|chunk| serde_json::from_str::<Metadata>(&chunk.get_text().ok()?).ok())
+        .any(|metadata| metadata.has_appended_rows())
 }
 
+pub(crate) fn itxt_chunk(keyword: &str, text: String) -> ITXtChunk {
+    let mut chunk = ITXtChunk::new(keyword, text);
+    chunk.compressed = true;
+    chunk
+}
+
+// Room the metadata text is padded to, so that appends can rewrite the chunk
+// in place with a new stamp and features
+pub(crate) const METADATA_TEXT_LEN: usize = 512;
+
+// The metadata chunk: uncompressed, and padded with spaces, which JSON parsers skip
+pub(crate) fn metadata_chunk(mut text: String) -> ITXtChunk {
+    let padding = METADATA_TEXT_LEN.saturating_sub(text.len());
+    text.extend(std::iter::repeat_n(' ', padding));
+    ITXtChunk::new(METADATA_KEYWORD, text)
+}
@@ -2,7 +2,8 @@
 mod cli {
     use clap::{Parser, Subcommand};
     use color_eyre::Result;
-    use png_db::{PngDatabase, Schema};
+    use png_db::format::format_timestamp;
+    use png_db::{parse_query, CellPolicy, ConversionRule, PngDatabase, Schema, Shape, FORMAT_VERSION};
     use serde_json::Value;
     use std::collections::HashMap;
 
@@ -25,7 +26,14 @@ mod cli {
             height: u32,
             #[arg(short, long)]
             schema: String,
+            /// What inserting into an occupied cell does: unique, upsert or multi
+            #[arg(long, default_value = "unique")]
+            cells: CellPolicy,
+            /// Store the spatial index in the file, so that loading it is faster
+            #[arg(long)]
+            spatial_index: bool,
         },
+        /// Insert a row, appending it to the end of the file rather than rewriting it
         Insert {
             #[arg(short, long)]
             file: String,
@@ -37,15 +45,123 @@ mod cli {
             data: String,
         },
         Query {
+            #[arg(short, long)]
+            file: String,
+            /// A WHERE clause or a full SELECT statement
+            #[arg(short = 'w', long = "where-clause", visible_alias = "sql")]
+            query: String,
+            /// Show how the rows would be found instead of running the query;
+            /// the same as starting it with EXPLAIN
+            #[arg(long)]
+            explain: bool,
+        },
+        /// List the rows inside a shape, nearest to its centre first
+        Within {
+            #[arg(short, long)]
+            file: String,
+            /// RECT(x0, y0, x1, y1), RADIUS(cx, cy, r) or POLYGON(x1, y1, x2, y2, x3, y3, ...)
+            #[arg(short, long)]
+            shape: Shape,
+        },
+        /// List the rows closest to a point, nearest first
+        Nearest {
+            #[arg(short, long)]
+            file: String,
+            #[arg(short, long)]
+            x: f64,
+            #[arg(short, long)]
+            y: f64,
+            /// How many rows to list
+            #[arg(short, long, default_value = "5")]
+            k: usize,
+            /// Only consider rows matching this WHERE clause
+            #[arg(short, long)]
+            where_clause: Option<String>,
+        },
+        Update {
             #[arg(short, long)]
             file: String,
             #[arg(short, long)]
             where_clause: String,
+            /// JSON merge patch applied to every matching row
+            #[arg(short, long)]
+            data: String,
+        },
+        Delete {
+            #[arg(short, long)]
+            file: String,
+            #[arg(short, long)]
+            where_clause: String,
+        },
+        AddColumn {
+            #[arg(short, long)]
+            file: String,
+            #[arg(short, long)]
+            name: String,
+            #[arg(short = 't', long = "type")]
+            field_type: String,
+            /// JSON value written to every existing row
+            #[arg(short, long)]
+            default: Option<String>,
+        },
+        DropColumn {
+            #[arg(short, long)]
+            file: String,
+            #[arg(short, long)]
+            name: String,
+        },
+        RenameColumn {
+            #[arg(short, long)]
+            file: String,
+            #[arg(long)]
+            from: String,
+            #[arg(long)]
+            to: String,
+        },
+        ChangeType {
+            #[arg(short, long)]
+            file: String,
+            #[arg(short, long)]
+            name: String,
+            #[arg(short = 't', long = "type")]
+            field_type: String,
+            /// What to do with values that cannot be converted: fail, null, remove or default
+            #[arg(long, default_value = "fail")]
+            on_error: String,
+            /// JSON value used when --on-error is default
+            #[arg(short, long)]
+            default: Option<String>,
         },
         List {
             #[arg(short, long)]
             file: String,
         },
+        /// Start or stop storing the spatial index in the file
+        SpatialIndex {
+            #[arg(short, long)]
+            file: String,
+            /// Stop storing it; it is then rebuilt on every load
+            #[arg(long)]
+            disable: bool,
+        },
+        /// Create or drop an index on a field
+        Index {
+            #[arg(short, long)]
+            file: String,
+            /// CREATE INDEX ON field [USING BTREE|HASH] or DROP INDEX ON field
+            #[arg(short, long)]
+            sql: String,
+        },
+        /// Rewrite a file in full, moving appended rows in with the others
+        Compact {
+            #[arg(short, long)]
+            file: String,
+        },
+        /// Rewrite a file written by an older png-db in the current format
+        Migrate {
+            #[arg(short, long)]
+            file: String,
+        },
     }
 
     pub fn run() -> Result<()> {
@@ -54,40 +170,189 @@ mod cli {
         let cli = Cli::parse();
 
         match cli.command {
-            Commands::Create { file, width, height, schema } => {
+            Commands::Create { file, width, height, schema, cells, spatial_index } => {
                 let schema_map = parse_schema(&schema)?;
-                let schema = Schema { fields: schema_map };
-                PngDatabase::create_empty_png(width, height, schema, &file)?;
+                let schema = Schema::new(schema_map);
+                schema.field_types()?;
+                let mut db = PngDatabase::new(width, height, schema);
+                db.cell_policy = cells;
+                db.persist_spatial_index = spatial_index;
+                db.save_to_png(&file)?;
                 println!("Created database: {}", file);
             }
             Commands::Insert { file, x, y, data } => {
-                let mut db = PngDatabase::load_from_png(&file)?;
                 let json_data: Value = serde_json::from_str(&data)?;
-                db.insert(x, y, json_data)?;
-                db.save_to_png(&file)?;
+                PngDatabase::append_row_to_png(&file, x, y, json_data)?;
                 println!("Inserted data at ({}, {})", x, y);
             }
-            Commands::Query { file, where_clause } => {
+            Commands::Query { file, query, explain } => {
                 let db = PngDatabase::load_from_png(&file)?;
-                let results = db.query(&where_clause)?;
+                let explain = explain || query.split_whitespace().next().is_some_and(|word| word.eq_ignore_ascii_case("explain"));
+                if explain {
+                    let plan = db.explain(&query)?;
+                    println!("Plan: {}", plan);
+                    println!("Considered:");
+                    for (access, estimated_rows) in &plan.considered {
+                        println!("  {}: {} row(s)", access, estimated_rows);
+                    }
+                    return Ok(());
+                }
+
+                let results = db.query(&query)?;
 
                 if results.is_empty() {
                     println!("No results found");
                 } else {
                     println!("Found {} result(s):", results.len());
-                    for row in results {
-                        println!("  Position ({}, {}): {}", row.x, row.y, serde_json::to_string_pretty(&row.data)?);
+                    for row in &results.rows {
+                        let fields: Vec<String> = results.columns.iter().zip(row)
+                            .map(|(column, value)| format!("{}: {}", column, value))
+                            .collect();
+                        println!("  {}", fields.join(", "));
+                    }
+                }
+            }
+            Commands::Within { file, shape } => {
+                let db = PngDatabase::load_from_png(&file)?;
+                let (min_x, min_y, max_x, max_y) = shape.bounds();
+                let (cx, cy) = ((min_x + max_x) / 2.0, (min_y + max_y) / 2.0);
+                let mut rows = db.within(&shape);
+                rows.sort_by(|a, b| {
+                    let distance = |x: u32, y: u32| (x as f64 - cx).hypot(y as f64 - cy);
+                    distance(a.x, a.y).total_cmp(&distance(b.x, b.y))
+                });
+
+                if rows.is_empty() {
+                    println!("No rows within {}", shape);
+                } else {
+                    println!("Found {} row(s) within {}:", rows.len(), shape);
+                    for row in rows {
+                        println!("  Position ({}, {}): {}", row.x, row.y, serde_json::to_string(&row.data)?);
+                    }
+                }
+            }
+            Commands::Nearest { file, x, y, k, where_clause } => {
+                let db = PngDatabase::load_from_png(&file)?;
+                let filter = where_clause.as_deref().map(parse_query).transpose()?;
+                let rows = db.nearest(x, y, k, filter.as_ref())?;
+
+                if rows.is_empty() {
+                    println!("No results found");
+                } else {
+                    println!("Found {} row(s) nearest to ({}, {}):", rows.len(), x, y);
+                    for row in rows {
+                        let distance = (row.x as f64 - x).hypot(row.y as f64 - y);
+                        println!("  Position ({}, {}) at {:.2}: {}", row.x, row.y, distance, serde_json::to_string(&row.data)?);
                     }
                 }
             }
+            Commands::Update { file, where_clause, data } => {
+                let mut db = PngDatabase::load_from_png(&file)?;
+                let patch: Value = serde_json::from_str(&data)?;
+                let updated = db.update(&where_clause, patch)?;
+                db.save_to_png(&file)?;
+                println!("Updated {} row(s)", updated);
+            }
+            Commands::Delete { file, where_clause } => {
+                let mut db = PngDatabase::load_from_png(&file)?;
+                let deleted = db.delete(&where_clause)?;
+                db.save_to_png(&file)?;
+                println!("Deleted {} row(s)", deleted);
+            }
+            Commands::AddColumn { file, name, field_type, default } => {
+                let mut db = PngDatabase::load_from_png(&file)?;
+                let default: Value = match default {
+                    Some(default) => serde_json::from_str(&default)?,
+                    None => Value::Null,
+                };
+                let rewritten = db.add_column(&name, &field_type, default)?;
+                db.save_to_png(&file)?;
+                println!("Added column '{}' (schema v{}, {} row(s) rewritten)", name, db.schema.version, rewritten);
+            }
+            Commands::DropColumn { file, name } => {
+                let mut db = PngDatabase::load_from_png(&file)?;
+                let rewritten = db.drop_column(&name)?;
+                db.save_to_png(&file)?;
+                println!("Dropped column '{}' (schema v{}, {} row(s) rewritten)", name, db.schema.version, rewritten);
+            }
+            Commands::RenameColumn { file, from, to } => {
+                let mut db = PngDatabase::load_from_png(&file)?;
+                let rewritten = db.rename_column(&from, &to)?;
+                db.save_to_png(&file)?;
+                println!("Renamed column '{}' to '{}' (schema v{}, {} row(s) rewritten)", from, to, db.schema.version, rewritten);
+            }
+            Commands::ChangeType { file, name, field_type, on_error, default } => {
+                let rule = match (on_error.as_str(), default) {
+                    ("fail", _) => ConversionRule::Fail,
+                    ("null", _) => ConversionRule::SetNull,
+                    ("remove", _) => ConversionRule::Remove,
+                    ("default", Some(default)) => ConversionRule::Default(serde_json::from_str(&default)?),
+                    ("default", None) => return Err(color_eyre::eyre::eyre!("--on-error default requires --default")),
+                    (other, _) => return Err(color_eyre::eyre::eyre!("Unknown --on-error value '{}'", other)),
+                };
+                let mut db = PngDatabase::load_from_png(&file)?;
+                let rewritten = db.change_column_type(&name, &field_type, rule)?;
+                db.save_to_png(&file)?;
+                println!("Changed column '{}' to {} (schema v{}, {} row(s) rewritten)", name, field_type, db.schema.version, rewritten);
+            }
             Commands::List { file } => {
                 let db = PngDatabase::load_from_png(&file)?;
                 println!("Database: {} ({}x{})", file, db.width, db.height);
-                println!("Schema: {:?}", db.schema.fields);
+                println!("Schema (v{}): {:?}", db.schema.version, db.schema.fields);
+                println!("Cell policy: {}", db.cell_policy);
+                println!("Spatial index: {}", if db.persist_spatial_index { "stored" } else { "built on load" });
+                println!(
+                    "Format: v{} (created by {} on {}, last modified by {} on {})",
+                    db.metadata.format_version,
+                    db.metadata.created_by,
+                    format_timestamp(db.metadata.created_at),
+                    db.metadata.modified_by,
+                    format_timestamp(db.metadata.modified_at),
+                );
+                for index in db.indexes() {
+                    println!("Index on {} ({}, {} row(s), {} distinct)", index.field(), index.kind(), index.len(), index.distinct());
+                }
                 println!("Rows: {}", db.rows.len());
 
                 for row in &db.rows {
-                    println!("  Position ({}, {}): {}", row.x, row.y, serde_json::to_string(&row.data)?);
+                    if db.cell_policy == CellPolicy::Multi {
+                        println!("  Position ({}, {}) #{}: {}", row.x, row.y, row.seq, serde_json::to_string(&row.data)?);
+                    } else {
+                        println!("  Position ({}, {}): {}", row.x, row.y, serde_json::to_string(&row.data)?);
+                    }
+                }
+            }
+            Commands::SpatialIndex { file, disable } => {
+                let mut db = PngDatabase::load_from_png(&file)?;
+                db.persist_spatial_index = !disable;
+                db.save_to_png(&file)?;
+                if disable {
+                    println!("{} no longer stores its spatial index", file);
+                } else {
+                    println!("Stored the spatial index of {} ({} row(s))", file, db.spatial_index().len());
+                }
+            }
+            Commands::Index { file, sql } => {
+                let mut db = PngDatabase::load_from_png(&file)?;
+                db.execute_index_statement(&sql)?;
+                db.save_to_png(&file)?;
+                println!("{} now has {} index(es)", file, db.indexes().len());
+            }
+            Commands::Compact { file } => {
+                let before = std::fs::metadata(&file)?.len();
+                let mut db = PngDatabase::load_from_png(&file)?;
+                db.save_to_png(&file)?;
+                let after = std::fs::metadata(&file)?.len();
+                println!("Compacted {} ({} row(s), {} -> {} bytes)", file, db.rows.len(), before, after);
+            }
+            Commands::Migrate { file } => {
+                let mut db = PngDatabase::load_from_png(&file)?;
+                if db.metadata.is_current() {
+                    println!("{} is already at format v{}", file, FORMAT_VERSION);
+                } else {
+                    let from = db.metadata.format_version;
+                    db.save_to_png(&file)?;
+                    println!("Migrated {} from format v{} to v{}", file, from, FORMAT_VERSION);
                 }
             }
         }
@@ -0,0 +1,99 @@
+use serde_json::Value;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+// A field reference such as `address.city`, `tags[0]` or `meta."zip code"`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FieldPath {
+    pub segments: Vec<PathSegment>,
+}
+
+impl FieldPath {
+    pub fn key(name: &str) -> Self {
+        Self { segments: vec![PathSegment::Key(name.to_string())] }
+    }
+
+    // The value itself, e.g. each element tested by a condition on `ANY(tags)`
+    pub fn root() -> Self {
+        Self { segments: Vec::new() }
+    }
+
+    // Extends the path with an object key, e.g. `FieldPath::key("address").child("city")`
+    pub fn child(mut self, key: &str) -> Self {
+        self.segments.push(PathSegment::Key(key.to_string()));
+        self
+    }
+
+    pub fn index(mut self, index: usize) -> Self {
+        self.segments.push(PathSegment::Index(index));
+        self
+    }
+
+    // Returns the key if this path is a single top-level key
+    pub fn as_key(&self) -> Option<&str> {
+        match self.segments.as_slice() {
+            [PathSegment::Key(key)] => Some(key),
+            _ => None,
+        }
+    }
+
+    pub fn is_coordinate(&self) -> bool {
+        matches!(self.as_key(), Some("x") | Some("y"))
+    }
+
+    pub fn resolve<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+        let mut current = value;
+        for segment in &self.segments {
+            current = match (segment, current) {
+                (PathSegment::Key(key), Value::Object(object)) => object.get(key)?,
+                (PathSegment::Index(index), Value::Array(array)) => array.get(*index)?,
+                _ => return None,
+            };
+        }
+        Some(current)
+    }
+}
+
+// A single top-level key, taken literally: `"a.b"` is one key, not a nested path
+impl From<&str> for FieldPath {
+    fn from(key: &str) -> Self {
+        FieldPath::key(key)
+    }
+}
+
+// Keys that can be written without quotes; boolean keywords are quoted so they
+// are not read as operators, and literal names so they are not read as values
+fn is_plain_key(key: &str) -> bool {
+    !key.is_empty()
+        && !key.starts_with(|c: char| c.is_ascii_digit())
+        && key.chars().all(|c| c.is_alphanumeric() || c == '_')
+        && !["and", "or", "not", "true", "false", "null"].iter().any(|keyword| key.eq_ignore_ascii_case(keyword))
+}
+
+impl fmt::Display for FieldPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, segment) in self.segments.iter().enumerate() {
+            match segment {
+                PathSegment::Key(key) if is_plain_key(key) => {
+                    if i > 0 {
+                        write!(f, ".")?;
+                    }
+                    write!(f, "{}", key)?;
+                }
+                PathSegment::Key(key) => {
+                    if i > 0 {
+                        write!(f, ".")?;
+                    }
+                    write!(f, "{}", Value::String(key.clone()))?;
+                }
+                PathSegment::Index(index) => write!(f, "[{}]", index)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,237 @@
+use crate::index::IndexKind;
+use crate::path::FieldPath;
+use crate::query::{self, ComparisonOp, Condition, Query, Select};
+use crate::PngDatabase;
+use serde_json::Value;
+use std::fmt;
+
+// What the planner knows about a column
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnStats {
+    // Rows that have the column
+    pub rows: usize,
+    // Number of distinct values, when known
+    pub distinct: Option<usize>,
+    // Smallest and largest numeric values, when known
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+// How a statement finds the rows it tests against its WHERE clause
+#[derive(Debug, Clone, PartialEq)]
+pub enum Access {
+    // Every row
+    FullScan,
+    // Rows whose cell lies in a rectangle, (min x, min y, max x, max y), that
+    // conditions on x and y limit the rows to; found through the spatial index
+    CoordinateIndex { bounds: (f64, f64, f64, f64) },
+    // The same, for a rectangle around the shapes of WITHIN conditions
+    SpatialIndex { bounds: (f64, f64, f64, f64) },
+    // Rows the indexes on these fields find for the values the query looks for
+    FieldIndex { fields: Vec<FieldPath> },
+    // `NEAREST k TO (x, y)`: rows in order of distance, through the spatial
+    // index, until `k` of them match
+    Nearest { k: usize, x: f64, y: f64 },
+}
+
+// The access path chosen for a statement
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    pub access: Access,
+    // Rows the access path is expected to read
+    pub estimated_rows: usize,
+    pub total_rows: usize,
+    // Every access path that could serve the statement, with its estimate, the
+    // chosen one included
+    pub considered: Vec<(Access, usize)>,
+}
+
+// Picks the access path expected to read the fewest rows; a full scan wins ties
+pub(crate) fn plan_select(db: &PngDatabase, select: &Select) -> Plan {
+    let total_rows = db.rows.len();
+    if let Some(nearest) = &select.nearest {
+        let access = Access::Nearest { k: nearest.k, x: nearest.x, y: nearest.y };
+        let estimated_rows = nearest.k.min(total_rows);
+        return Plan { access: access.clone(), estimated_rows, total_rows, considered: vec![(access, estimated_rows)] };
+    }
+    plan_filter(db, select.filter.as_ref())
+}
+
+pub(crate) fn plan_filter(db: &PngDatabase, filter: Option<&Query>) -> Plan {
+    let total_rows = db.rows.len();
+    let mut considered = vec![(Access::FullScan, total_rows)];
+
+    if let Some(filter) = filter {
+        if let Some(bounds) = query::spatial_bounds(filter) {
+            let access = if has_within(filter) { Access::SpatialIndex { bounds } } else { Access::CoordinateIndex { bounds } };
+            considered.push((access, estimate_area(db, bounds)));
+        }
+        if let Some(estimate) = estimate_indexed(db, filter) {
+            let mut fields = Vec::new();
+            indexed_fields(db, filter, &mut fields);
+            considered.push((Access::FieldIndex { fields }, estimate.min(total_rows)));
+        }
+    }
+
+    let (access, estimated_rows) = considered.iter()
+        .min_by_key(|(_, estimate)| *estimate)
+        .cloned()
+        .unwrap_or((Access::FullScan, total_rows));
+    Plan { access, estimated_rows, total_rows, considered }
+}
+
+fn has_within(query: &Query) -> bool {
+    match query {
+        Query::Condition(condition) => matches!(condition, Condition::Within(_)),
+        Query::And(left, right) | Query::Or(left, right) => has_within(left) || has_within(right),
+        Query::Not(inner) | Query::Group(inner) => has_within(inner),
+    }
+}
+
+// Rows in the rectangle, taking them to be spread evenly over the area their
+// cells cover
+fn estimate_area(db: &PngDatabase, (min_x, min_y, max_x, max_y): (f64, f64, f64, f64)) -> usize {
+    let x = db.column_stats(&FieldPath::key("x"));
+    let y = db.column_stats(&FieldPath::key("y"));
+    let (Some(x), Some(y)) = (x, y) else {
+        return 0;
+    };
+    // A cell covers one unit along each axis
+    let share = |stats: &ColumnStats, low: f64, high: f64| match (stats.min, stats.max) {
+        (Some(min), Some(max)) => coverage(low, high + 1.0, min, max + 1.0),
+        _ => 1.0,
+    };
+    rows(x.rows, share(&x, min_x, max_x) * share(&y, min_y, max_y))
+}
+
+// Share of [min, max] that [low, high] covers
+fn coverage(low: f64, high: f64, min: f64, max: f64) -> f64 {
+    if high < low || high < min || low > max {
+        return 0.0;
+    }
+    if max <= min {
+        return 1.0;
+    }
+    ((high.min(max) - low.max(min)) / (max - min)).clamp(0.0, 1.0)
+}
+
+fn rows(total: usize, share: f64) -> usize {
+    (total as f64 * share).round() as usize
+}
+
+// Rows the field indexes would return for `query`, or `None` where
+// `query::indexed_rows` would not use them
+fn estimate_indexed(db: &PngDatabase, query: &Query) -> Option<usize> {
+    match query {
+        Query::Condition(condition) => {
+            let field = condition.field()?;
+            let index = db.field_index(&field)?;
+            let stats = db.column_stats(&field)?;
+            let per_value = stats.rows.div_ceil(stats.distinct.unwrap_or(1).max(1));
+            let range = |low: Option<&Value>, high: Option<&Value>| -> Option<usize> {
+                if index.kind() != IndexKind::BTree {
+                    return None;
+                }
+                match (low.or(high)?, low.and(high)) {
+                    (Value::Number(_), None) | (Value::Number(_), Some(Value::Number(_))) => {
+                        let (Some(min), Some(max)) = (stats.min, stats.max) else {
+                            return Some(0);
+                        };
+                        let low = low.and_then(Value::as_f64).unwrap_or(f64::NEG_INFINITY);
+                        let high = high.and_then(Value::as_f64).unwrap_or(f64::INFINITY);
+                        Some(rows(stats.rows, coverage(low, high, min, max)))
+                    }
+                    // Without a distribution of the strings, guess a third
+                    (Value::String(_), None) | (Value::String(_), Some(Value::String(_))) => Some(stats.rows.div_ceil(3)),
+                    _ => None,
+                }
+            };
+            match condition {
+                Condition::JsonField { op, value, .. } => match op {
+                    ComparisonOp::Equal if !value.is_array() && !value.is_object() => Some(per_value),
+                    ComparisonOp::GreaterThan | ComparisonOp::GreaterThanOrEqual => range(Some(value), None),
+                    ComparisonOp::LessThan | ComparisonOp::LessThanOrEqual => range(None, Some(value)),
+                    _ => None,
+                },
+                Condition::In { values, .. } if values.iter().all(|value| !value.is_array() && !value.is_object()) => {
+                    Some(per_value * values.len())
+                }
+                Condition::Between { low, high, .. } => range(Some(low), Some(high)),
+                _ => None,
+            }
+        }
+        Query::And(left, right) => match (estimate_indexed(db, left), estimate_indexed(db, right)) {
+            (Some(l), Some(r)) => Some(l.min(r)),
+            (estimate, None) | (None, estimate) => estimate,
+        },
+        Query::Or(left, right) => Some(estimate_indexed(db, left)? + estimate_indexed(db, right)?),
+        Query::Group(inner) => estimate_indexed(db, inner),
+        Query::Not(_) => None,
+    }
+}
+
+// The indexed fields `estimate_indexed` relied on, in order of appearance
+fn indexed_fields(db: &PngDatabase, query: &Query, fields: &mut Vec<FieldPath>) {
+    if estimate_indexed(db, query).is_none() {
+        return;
+    }
+    match query {
+        Query::Condition(condition) => {
+            if let Some(field) = condition.field()
+                && !fields.contains(&field)
+            {
+                fields.push(field);
+            }
+        }
+        Query::And(left, right) | Query::Or(left, right) => {
+            indexed_fields(db, left, fields);
+            indexed_fields(db, right, fields);
+        }
+        Query::Group(inner) => indexed_fields(db, inner, fields),
+        Query::Not(_) => {}
+    }
+}
+
+// Per-axis limits, e.g. `8 <= x <= 10, y < 4`
+fn describe_bounds(f: &mut fmt::Formatter<'_>, (min_x, min_y, max_x, max_y): (f64, f64, f64, f64)) -> fmt::Result {
+    let mut limits = Vec::new();
+    for (axis, low, high) in [("x", min_x, max_x), ("y", min_y, max_y)] {
+        match (low.is_finite(), high.is_finite()) {
+            (true, true) if low == high => limits.push(format!("{} = {}", axis, low)),
+            (true, true) => limits.push(format!("{} <= {} <= {}", low, axis, high)),
+            (true, false) => limits.push(format!("{} >= {}", axis, low)),
+            (false, true) => limits.push(format!("{} <= {}", axis, high)),
+            (false, false) => {}
+        }
+    }
+    write!(f, "{}", limits.join(", "))
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Access::FullScan => write!(f, "full scan"),
+            Access::CoordinateIndex { bounds } => {
+                write!(f, "coordinate index (")?;
+                describe_bounds(f, *bounds)?;
+                write!(f, ")")
+            }
+            Access::SpatialIndex { bounds } => {
+                write!(f, "spatial index (")?;
+                describe_bounds(f, *bounds)?;
+                write!(f, ")")
+            }
+            Access::FieldIndex { fields } => {
+                let fields: Vec<String> = fields.iter().map(FieldPath::to_string).collect();
+                write!(f, "field index on {}", fields.join(", "))
+            }
+            Access::Nearest { k, x, y } => write!(f, "nearest {} to ({}, {})", k, x, y),
+        }
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: estimated {} of {} row(s)", self.access, self.estimated_rows, self.total_rows)
+    }
+}
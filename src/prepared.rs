@@ -0,0 +1,94 @@
+use crate::query::{parse_prepared, Condition, Param, Query, Select};
+use crate::{PngDbError, Result};
+use serde_json::{Map, Value};
+
+// A statement parsed once and run many times with different values bound to its
+// `?` or `:name` placeholders. Bound values never pass through the query parser,
+// so quotes and operators inside them are just data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreparedQuery {
+    select: Select,
+    params: Vec<Param>,
+}
+
+impl PreparedQuery {
+    pub fn new(query_str: &str) -> Result<Self> {
+        let (select, params) = parse_prepared(query_str)?;
+        Ok(Self { select, params })
+    }
+
+    // Placeholders in order of appearance; a repeated `:name` is listed once
+    pub fn params(&self) -> Vec<&Param> {
+        let mut params: Vec<&Param> = Vec::new();
+        for param in &self.params {
+            if !params.contains(&param) {
+                params.push(param);
+            }
+        }
+        params
+    }
+
+    // Binds `?` placeholders, in order
+    pub fn bind(&self, values: &[Value]) -> Result<Select> {
+        let expected = self.params().len();
+        if self.params.iter().any(|param| matches!(param, Param::Named(_))) {
+            return Err(PngDbError::QueryError("Query uses named parameters; bind them by name".to_string()));
+        }
+        if values.len() != expected {
+            return Err(PngDbError::QueryError(format!("Expected {} parameter(s) but got {}", expected, values.len())));
+        }
+
+        self.bind_with(&|param| match param {
+            Param::Positional(index) => Ok(values[*index].clone()),
+            Param::Named(_) => unreachable!(),
+        })
+    }
+
+    // Binds `:name` placeholders; every name must be given and no others
+    pub fn bind_named(&self, values: &Map<String, Value>) -> Result<Select> {
+        if self.params.iter().any(|param| matches!(param, Param::Positional(_))) {
+            return Err(PngDbError::QueryError("Query uses '?' parameters; bind them in order".to_string()));
+        }
+        if let Some(unknown) = values.keys().find(|name| !self.params.contains(&Param::Named(name.to_string()))) {
+            return Err(PngDbError::QueryError(format!("Query has no parameter :{}", unknown)));
+        }
+
+        self.bind_with(&|param| match param {
+            Param::Named(name) => values.get(name).cloned()
+                .ok_or_else(|| PngDbError::QueryError(format!("Missing value for parameter :{}", name))),
+            Param::Positional(_) => unreachable!(),
+        })
+    }
+
+    fn bind_with(&self, value_of: &dyn Fn(&Param) -> Result<Value>) -> Result<Select> {
+        let mut select = self.select.clone();
+        select.filter = select.filter.map(|query| bind_query(query, value_of)).transpose()?;
+        select.having = select.having.map(|query| bind_query(query, value_of)).transpose()?;
+        Ok(select)
+    }
+}
+
+fn bind_query(query: Query, value_of: &dyn Fn(&Param) -> Result<Value>) -> Result<Query> {
+    Ok(match query {
+        Query::Condition(Condition::Parameter { operand, op, param }) => {
+            let value = value_of(&param)?;
+            if operand.is_coordinate() && !op.is_pattern() && value.as_u64().is_none_or(|v| v > u32::MAX as u64) {
+                return Err(PngDbError::QueryError(
+                    format!("Parameter {} for {} must be a non-negative integer, got {}", param, operand, value)
+                ));
+            }
+            operand.compare(op, value)
+        }
+        Query::Condition(condition) => Query::Condition(condition),
+        Query::And(left, right) => Query::And(Box::new(bind_query(*left, value_of)?), Box::new(bind_query(*right, value_of)?)),
+        Query::Or(left, right) => Query::Or(Box::new(bind_query(*left, value_of)?), Box::new(bind_query(*right, value_of)?)),
+        Query::Not(inner) => Query::Not(Box::new(bind_query(*inner, value_of)?)),
+        Query::Group(inner) => Query::Group(Box::new(bind_query(*inner, value_of)?)),
+    })
+}
+
+impl std::fmt::Display for PreparedQuery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.select)
+    }
+}
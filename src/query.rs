@@ -0,0 +1,2040 @@
+use crate::builder::Operand;
+use crate::expr::{ArithOp, Expr};
+use crate::functions::FunctionRegistry;
+use crate::index::{self, FieldIndex, IndexKind};
+use crate::path::{FieldPath, PathSegment};
+use crate::schema::describe;
+use crate::spatial::Shape;
+use crate::{DataRow, PngDatabase, PngDbError, Result};
+use serde::Serialize;
+use serde_json::Value;
+use regex::Regex;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    Condition(Condition),
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+    Group(Box<Query>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Coordinate { field: String, op: ComparisonOp, value: u32 },
+    JsonField { field: FieldPath, op: ComparisonOp, value: Value },
+    Aggregate { func: AggregateFunc, column: Option<FieldPath>, op: ComparisonOp, value: Value },
+    // A `?` or `:name` placeholder in a prepared query, replaced by a real condition when bound
+    Parameter { operand: Operand, op: ComparisonOp, param: Param },
+    // `field IN (a, b, ...)`
+    In { field: FieldPath, values: Vec<Value> },
+    // `field BETWEEN low AND high`, inclusive at both ends
+    Between { field: FieldPath, low: Value, high: Value },
+    // `field IS [NOT] NULL` only looks at fields that are present, and
+    // `field IS [NOT] MISSING` at whether the field is present at all
+    IsNull { field: FieldPath, negated: bool },
+    IsMissing { field: FieldPath, negated: bool },
+    // `field CONTAINS value`: an array with an element equal to `value`, or a
+    // string with `value` as a substring
+    Contains { field: FieldPath, value: Value },
+    // `field HAS KEY "name"`, for objects
+    HasKey { field: FieldPath, key: String },
+    // `field @> '<json>'` and `field <@ '<json>'`: structural containment as in
+    // Postgres jsonb, e.g. `{"a": 1, "b": [1, 2]} @> '{"b": [2]}'`
+    JsonContains { field: FieldPath, value: Value },
+    JsonContainedBy { field: FieldPath, value: Value },
+    // `ANY(field) <condition>`: the field is an array with an element matching
+    // `condition`, whose own field is `FieldPath::root()`
+    Any { field: FieldPath, condition: Box<Condition> },
+    // `<expression> <condition>`, e.g. `lower(name) = "ada"` or `x + y < 100`:
+    // `condition` is tested against the computed value, with `FieldPath::root()`
+    // as its field
+    Computed { expr: Expr, condition: Box<Condition> },
+    // `WITHIN RECT(...)`, `WITHIN RADIUS(...)` or `WITHIN POLYGON(...)`: the row's
+    // cell lies in the shape
+    Within(Shape),
+}
+
+impl Condition {
+    // The field tested by the condition; aggregates and placeholders have none
+    pub(crate) fn field(&self) -> Option<FieldPath> {
+        match self {
+            Condition::Coordinate { field, .. } => Some(FieldPath::key(field)),
+            Condition::Aggregate { .. } | Condition::Parameter { .. } | Condition::Computed { .. } | Condition::Within(_) => None,
+            Condition::JsonField { field, .. }
+            | Condition::In { field, .. }
+            | Condition::Between { field, .. }
+            | Condition::IsNull { field, .. }
+            | Condition::IsMissing { field, .. }
+            | Condition::Contains { field, .. }
+            | Condition::HasKey { field, .. }
+            | Condition::JsonContains { field, .. }
+            | Condition::JsonContainedBy { field, .. }
+            | Condition::Any { field, .. } => Some(field.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Param {
+    // Zero-based position among the query's `?` placeholders
+    Positional(usize),
+    Named(String),
+}
+
+impl std::fmt::Display for Param {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Param::Positional(_) => write!(f, "?"),
+            Param::Named(name) => write!(f, ":{}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ComparisonOp {
+    Equal,
+    NotEqual,
+    GreaterThan,
+    LessThan,
+    GreaterThanOrEqual,
+    LessThanOrEqual,
+    // SQL patterns, where `%` matches any run of characters and `_` any one character
+    Like,
+    ILike,
+    // `~`, a regular expression matched anywhere in the value
+    Regex,
+}
+
+impl ComparisonOp {
+    // LIKE, ILIKE and ~, which match text against a pattern rather than compare values
+    pub fn is_pattern(&self) -> bool {
+        matches!(self, ComparisonOp::Like | ComparisonOp::ILike | ComparisonOp::Regex)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Select {
+    pub projection: Projection,
+    pub filter: Option<Query>,
+    pub group_by: Vec<FieldPath>,
+    pub having: Option<Query>,
+    pub order_by: Vec<OrderBy>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    pub nearest: Option<Nearest>,
+}
+
+// `NEAREST k TO (x, y)`: keeps the `k` matching rows closest to (x, y), nearest
+// first unless the statement has an ORDER BY
+#[derive(Debug, Clone, PartialEq)]
+pub struct Nearest {
+    pub k: usize,
+    pub x: f64,
+    pub y: f64,
+}
+
+// `CREATE INDEX ON field [USING BTREE | HASH]` or `DROP INDEX ON field`
+#[derive(Debug, Clone, PartialEq)]
+pub enum IndexStatement {
+    Create { field: FieldPath, kind: IndexKind },
+    Drop { field: FieldPath },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Projection {
+    All,
+    Items(Vec<SelectItem>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SelectItem {
+    Column { path: FieldPath, alias: Option<String> },
+    Aggregate { func: AggregateFunc, column: Option<FieldPath>, alias: Option<String> },
+    Expr { expr: Expr, alias: Option<String> },
+}
+
+impl SelectItem {
+    pub fn output_name(&self) -> String {
+        match self {
+            SelectItem::Column { path, alias } => alias.clone().unwrap_or_else(|| path.to_string()),
+            SelectItem::Aggregate { func, column, alias } => {
+                alias.clone().unwrap_or_else(|| aggregate_name(func, column.as_ref()))
+            }
+            SelectItem::Expr { expr, alias } => alias.clone().unwrap_or_else(|| expr.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AggregateFunc {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+impl AggregateFunc {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "count" => Some(AggregateFunc::Count),
+            "sum" => Some(AggregateFunc::Sum),
+            "avg" => Some(AggregateFunc::Avg),
+            "min" => Some(AggregateFunc::Min),
+            "max" => Some(AggregateFunc::Max),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for AggregateFunc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            AggregateFunc::Count => "COUNT",
+            AggregateFunc::Sum => "SUM",
+            AggregateFunc::Avg => "AVG",
+            AggregateFunc::Min => "MIN",
+            AggregateFunc::Max => "MAX",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+pub(crate) fn aggregate_name(func: &AggregateFunc, column: Option<&FieldPath>) -> String {
+    match column {
+        Some(column) => format!("{}({})", func, column),
+        None => format!("{}(*)", func),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderBy {
+    pub key: OrderKey,
+    pub descending: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderKey {
+    Field(FieldPath),
+    Aggregate { func: AggregateFunc, column: Option<FieldPath> },
+    Expr(Expr),
+}
+
+impl OrderKey {
+    fn name(&self) -> String {
+        match self {
+            OrderKey::Field(path) => path.to_string(),
+            OrderKey::Aggregate { func, column } => aggregate_name(func, column.as_ref()),
+            OrderKey::Expr(expr) => expr.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ResultSet {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl ResultSet {
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    // Each row as a JSON object keyed by column name
+    pub fn to_objects(&self) -> Vec<Value> {
+        self.rows.iter().map(|row| {
+            let object = self.columns.iter().cloned().zip(row.iter().cloned()).collect();
+            Value::Object(object)
+        }).collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(String),
+    // Double-quoted: a string value, or a field name where a field is expected
+    Str(String),
+    // Single-quoted: always a string value
+    Text(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+    Comma,
+    Star,
+    Dot,
+    LBracket,
+    RBracket,
+    Positional,
+    Named(String),
+    // A JSON array or object literal, as written
+    Json(String),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Ident(s) | Token::Number(s) => write!(f, "{}", s),
+            Token::Str(s) => write!(f, "\"{}\"", s),
+            Token::Text(s) => write!(f, "'{}'", s),
+            Token::Op(op) => write!(f, "{}", op),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::Comma => write!(f, ","),
+            Token::Star => write!(f, "*"),
+            Token::Dot => write!(f, "."),
+            Token::LBracket => write!(f, "["),
+            Token::RBracket => write!(f, "]"),
+            Token::Positional => write!(f, "?"),
+            Token::Named(name) => write!(f, ":{}", name),
+            Token::Json(json) => write!(f, "{}", json),
+        }
+    }
+}
+
+// Splits a query into tokens paired with the character offset each one starts at
+fn tokenize(input: &str) -> Result<Vec<(Token, usize)>> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let token_start = i;
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '(' {
+            tokens.push((Token::LParen, token_start));
+            i += 1;
+        } else if c == ')' {
+            tokens.push((Token::RParen, token_start));
+            i += 1;
+        } else if c == ',' {
+            tokens.push((Token::Comma, token_start));
+            i += 1;
+        } else if c == '*' {
+            tokens.push((Token::Star, token_start));
+            i += 1;
+        } else if c == '.' {
+            tokens.push((Token::Dot, token_start));
+            i += 1;
+        } else if c == '{' || (c == '[' && !ends_operand(tokens.last())) {
+            // A '[' after an operand indexes into it; anywhere else it starts a JSON literal
+            i = json_literal_end(&chars, i).ok_or_else(|| parse_error("Unterminated JSON literal", token_start, input))?;
+            tokens.push((Token::Json(chars[token_start..i].iter().collect()), token_start));
+        } else if c == '[' {
+            tokens.push((Token::LBracket, token_start));
+            i += 1;
+        } else if c == ']' {
+            tokens.push((Token::RBracket, token_start));
+            i += 1;
+        } else if c == '?' {
+            tokens.push((Token::Positional, token_start));
+            i += 1;
+        } else if c == ':' {
+            let start = i + 1;
+            i = start;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            if i == start {
+                return Err(parse_error("Expected parameter name after ':'", token_start, input));
+            }
+            tokens.push((Token::Named(chars[start..i].iter().collect()), token_start));
+        } else if c == '"' || c == '\'' {
+            // Double or single quotes, with JSON escapes and `\'`; any other
+            // backslash is kept as written
+            let mut text = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(parse_error("Unterminated string literal", token_start, input)),
+                    Some(&quote) if quote == c => break,
+                    Some('\\') => {
+                        let escaped = match chars.get(i + 1) {
+                            Some(&quote @ ('"' | '\'')) => quote,
+                            Some('\\') => '\\',
+                            Some('n') => '\n',
+                            Some('t') => '\t',
+                            Some('r') => '\r',
+                            Some('b') => '\u{8}',
+                            Some('f') => '\u{c}',
+                            Some('/') => '/',
+                            Some('u') => {
+                                let (code, len) = unicode_escape(&chars, i).ok_or_else(|| {
+                                    let hex: String = chars.iter().skip(i + 2).take(4).collect();
+                                    parse_error(format!("Invalid unicode escape: \\u{}", hex), i, input)
+                                })?;
+                                text.push(code);
+                                i += len;
+                                continue;
+                            }
+                            _ => {
+                                text.push('\\');
+                                i += 1;
+                                continue;
+                            }
+                        };
+                        text.push(escaped);
+                        i += 2;
+                    }
+                    Some(&other) => {
+                        text.push(other);
+                        i += 1;
+                    }
+                }
+            }
+            tokens.push((if c == '"' { Token::Str(text) } else { Token::Text(text) }, token_start));
+            i += 1;
+        } else if c.is_ascii_digit()
+            || (c == '-' && chars.get(i + 1).is_some_and(|n| n.is_ascii_digit()) && !ends_operand(tokens.last()))
+        {
+            let start = i;
+            i += 1;
+            // A sign belongs to the number only as the sign of its exponent, as in 1e-5
+            while i < chars.len()
+                && (chars[i].is_ascii_alphanumeric()
+                    || chars[i] == '.'
+                    || (matches!(chars[i], '+' | '-')
+                        && matches!(chars[i - 1], 'e' | 'E')
+                        && chars.get(i + 1).is_some_and(|n| n.is_ascii_digit())))
+            {
+                i += 1;
+            }
+            tokens.push((Token::Number(chars[start..i].iter().collect()), token_start));
+        } else if c.is_alphanumeric() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push((Token::Ident(chars[start..i].iter().collect()), token_start));
+        } else {
+            let two: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+            let op = match two.as_str() {
+                ">=" => ">=",
+                "<=" => "<=",
+                "!=" => "!=",
+                "@>" => "@>",
+                "<@" => "<@",
+                _ => match c {
+                    '=' => "=",
+                    '>' => ">",
+                    '<' => "<",
+                    '~' => "~",
+                    '+' => "+",
+                    '-' => "-",
+                    '/' => "/",
+                    '%' => "%",
+                    _ => return Err(parse_error(format!("Unexpected character: {}", c), i, input)),
+                },
+            };
+            i += op.len();
+            tokens.push((Token::Op(op), token_start));
+        }
+    }
+
+    Ok(tokens)
+}
+
+// The character for the `\uXXXX` escape at `start` and the length of the
+// escape, which is two of them for a character outside the basic plane
+fn unicode_escape(chars: &[char], start: usize) -> Option<(char, usize)> {
+    let hex = |at: usize| -> Option<u32> {
+        let digits = chars.get(at + 2..at + 6)?;
+        if chars.get(at + 1) != Some(&'u') || !digits.iter().all(char::is_ascii_hexdigit) {
+            return None;
+        }
+        u32::from_str_radix(&digits.iter().collect::<String>(), 16).ok()
+    };
+    let high = hex(start)?;
+    if !(0xD800..0xDC00).contains(&high) {
+        return char::from_u32(high).map(|c| (c, 6));
+    }
+    let low = hex(start + 6).filter(|low| (0xDC00..0xE000).contains(low) && chars[start + 6] == '\\')?;
+    char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)).map(|c| (c, 12))
+}
+
+// The offset just past the JSON array or object starting at `start`, found by
+// matching brackets outside of strings
+fn json_literal_end(chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if in_string => i += 1,
+            '"' => in_string = !in_string,
+            '[' | '{' if !in_string => depth += 1,
+            ']' | '}' if !in_string => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+// Whether a `-` after `token` subtracts rather than starting a negative number
+fn ends_operand(token: Option<&(Token, usize)>) -> bool {
+    matches!(
+        token,
+        Some((Token::Ident(_) | Token::Number(_) | Token::Str(_) | Token::Text(_) | Token::RParen | Token::RBracket, _))
+    )
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    tokens: Vec<Token>,
+    // Character offset of each token, and of the end of the input
+    positions: Vec<usize>,
+    end: usize,
+    pos: usize,
+    // Placeholders are only accepted when preparing a query
+    allow_params: bool,
+    params: Vec<Param>,
+    // Field and function names a query may refer to, used to suggest a fix for
+    // misspelled ones; `None` skips the check. Only declared fields are offered
+    // as a fix, since any other field may just be absent from every row.
+    known_fields: Option<Vec<String>>,
+    declared_fields: Vec<String>,
+    known_functions: Option<Vec<String>>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Result<Self> {
+        let (tokens, positions) = tokenize(input)?.into_iter().unzip();
+        Ok(Parser {
+            input,
+            tokens,
+            positions,
+            end: input.chars().count(),
+            pos: 0,
+            allow_params: false,
+            params: Vec::new(),
+            known_fields: None,
+            declared_fields: Vec::new(),
+            known_functions: None,
+        })
+    }
+
+    // A parse error pointing at the next unconsumed token
+    fn error(&self, message: impl Into<String>) -> PngDbError {
+        parse_error(message, self.positions.get(self.pos).copied().unwrap_or(self.end), self.input)
+    }
+
+    // A parse error pointing at the token most recently returned by `next`
+    fn error_at_last(&self, message: impl Into<String>) -> PngDbError {
+        let last = self.pos.saturating_sub(1);
+        parse_error(message, self.positions.get(last).copied().unwrap_or(self.end), self.input)
+    }
+
+    fn learn(&mut self, known: Option<KnownNames>) {
+        if let Some(known) = known {
+            self.known_fields = Some(known.fields);
+            self.declared_fields = known.declared;
+            self.known_functions = Some(known.functions);
+        }
+    }
+
+    // Rejects a call to a function that is not registered, after its name and
+    // `(` have been consumed
+    fn check_function(&self, name: &str) -> Result<()> {
+        let Some(known) = &self.known_functions else {
+            return Ok(());
+        };
+        if known.iter().any(|function| function.eq_ignore_ascii_case(name)) {
+            return Ok(());
+        }
+        let position = self.positions.get(self.pos - 2).copied().unwrap_or(self.end);
+        let message = match suggest(name, known) {
+            Some(suggestion) => format!("Unknown function '{}', did you mean '{}'?", name, suggestion),
+            None => format!("Unknown function '{}'", name),
+        };
+        Err(parse_error(message, position, self.input))
+    }
+
+    // Rejects a top-level field that is unknown but close to a declared one. A
+    // short name is only taken for a typo of one with the same letters, so that
+    // `z` or `age2` are not mistaken for `x` or `age`.
+    fn check_field(&self, key: &str) -> Result<()> {
+        let Some(known) = &self.known_fields else {
+            return Ok(());
+        };
+        if known.iter().any(|field| field == key) {
+            return Ok(());
+        }
+        let suggestion = suggest(key, &self.declared_fields)
+            .filter(|suggestion| key.chars().count() >= 5 || same_letters(key, suggestion));
+        match suggestion {
+            Some(suggestion) => Err(self.error_at_last(format!("Unknown field '{}', did you mean '{}'?", key, suggestion))),
+            None => Ok(()),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(s)) if s.eq_ignore_ascii_case(keyword))
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.peek_keyword(keyword) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<()> {
+        if self.eat_keyword(keyword) {
+            return Ok(());
+        }
+        match self.peek() {
+            Some(token) => Err(self.error(format!("Expected {} but found '{}'", keyword.to_uppercase(), token))),
+            None => Err(self.error(format!("Expected {}", keyword.to_uppercase()))),
+        }
+    }
+
+    fn parse_ident(&mut self, what: &str) -> Result<String> {
+        match self.next() {
+            Some(Token::Ident(name)) => Ok(name),
+            Some(token) => Err(self.error_at_last(format!("Expected {} but found '{}'", what, token))),
+            None => Err(self.error_at_last(format!("Expected {}", what))),
+        }
+    }
+
+    fn parse_count(&mut self, clause: &str) -> Result<usize> {
+        match self.next() {
+            Some(Token::Number(n)) => n.parse::<usize>()
+                .map_err(|_| self.error_at_last(format!("Invalid {} value: {}", clause, n))),
+            Some(token) => Err(self.error_at_last(format!("Invalid {} value: {}", clause, token))),
+            None => Err(self.error_at_last(format!("Missing {} value", clause))),
+        }
+    }
+
+    // select := SELECT projection [FROM ident] [WHERE or_expr] [NEAREST n TO '(' x ',' y ')']
+    //            [GROUP BY column_list] [HAVING or_expr] [ORDER BY order_list] [LIMIT n] [OFFSET n]
+    //         | WHERE or_expr
+    fn parse_select(&mut self) -> Result<Select> {
+        let mut select = Select {
+            projection: Projection::All,
+            filter: None,
+            group_by: Vec::new(),
+            having: None,
+            order_by: Vec::new(),
+            limit: None,
+            offset: None,
+            nearest: None,
+        };
+
+        if self.eat_keyword("select") {
+            select.projection = self.parse_projection()?;
+            if self.eat_keyword("from") {
+                self.parse_ident("table name")?;
+            }
+            if self.eat_keyword("where") {
+                select.filter = Some(self.parse_or()?);
+            }
+        } else if self.eat_keyword("where") {
+            select.filter = Some(self.parse_or()?);
+        } else {
+            return Err(self.error("Query must start with SELECT or WHERE"));
+        }
+
+        if self.eat_keyword("nearest") {
+            let k = self.parse_count("NEAREST")?;
+            self.expect_keyword("to")?;
+            let point = self.parse_numbers("NEAREST")?;
+            let &[x, y] = point.as_slice() else {
+                return Err(self.error_at_last("NEAREST takes a point: NEAREST k TO (x, y)"));
+            };
+            select.nearest = Some(Nearest { k, x, y });
+        }
+
+        if self.eat_keyword("group") {
+            self.expect_keyword("by")?;
+            select.group_by.push(self.parse_field_path()?);
+            while self.peek() == Some(&Token::Comma) {
+                self.pos += 1;
+                select.group_by.push(self.parse_field_path()?);
+            }
+        }
+        if self.eat_keyword("having") {
+            select.having = Some(self.parse_or()?);
+        }
+
+        if self.eat_keyword("order") {
+            self.expect_keyword("by")?;
+            loop {
+                let key = match self.parse_aggregate_call()? {
+                    Some((func, column)) => OrderKey::Aggregate { func, column },
+                    None => match self.parse_expr()? {
+                        Expr::Field(path) => OrderKey::Field(path),
+                        expr => OrderKey::Expr(expr),
+                    },
+                };
+                let descending = if self.eat_keyword("desc") {
+                    true
+                } else {
+                    self.eat_keyword("asc");
+                    false
+                };
+                select.order_by.push(OrderBy { key, descending });
+                if self.peek() != Some(&Token::Comma) {
+                    break;
+                }
+                self.pos += 1;
+            }
+        }
+
+        if self.eat_keyword("limit") {
+            select.limit = Some(self.parse_count("LIMIT")?);
+        }
+        if self.eat_keyword("offset") {
+            select.offset = Some(self.parse_count("OFFSET")?);
+        }
+
+        Ok(select)
+    }
+
+    // index_statement := CREATE INDEX ON path [USING (BTREE | HASH)] | DROP INDEX ON path
+    fn parse_index_statement(&mut self) -> Result<IndexStatement> {
+        let create = if self.eat_keyword("create") {
+            true
+        } else if self.eat_keyword("drop") {
+            false
+        } else {
+            return Err(self.error("Statement must start with CREATE INDEX or DROP INDEX"));
+        };
+        self.expect_keyword("index")?;
+        self.expect_keyword("on")?;
+        let field = self.parse_field_path()?;
+        if !create {
+            return Ok(IndexStatement::Drop { field });
+        }
+
+        let kind = if self.eat_keyword("using") {
+            let name = self.parse_ident("index kind")?;
+            name.parse::<IndexKind>().map_err(|e| self.error_at_last(e))?
+        } else {
+            IndexKind::default()
+        };
+        Ok(IndexStatement::Create { field, kind })
+    }
+
+    fn parse_projection(&mut self) -> Result<Projection> {
+        if self.peek() == Some(&Token::Star) {
+            self.pos += 1;
+            return Ok(Projection::All);
+        }
+
+        let mut items = vec![self.parse_select_item()?];
+        while self.peek() == Some(&Token::Comma) {
+            self.pos += 1;
+            items.push(self.parse_select_item()?);
+        }
+        Ok(Projection::Items(items))
+    }
+
+    fn parse_select_item(&mut self) -> Result<SelectItem> {
+        let aggregate = self.parse_aggregate_call()?;
+        let expr = match aggregate {
+            Some(_) => None,
+            None => Some(self.parse_expr()?),
+        };
+        let alias = if self.eat_keyword("as") {
+            let alias = self.parse_ident("alias")?;
+            if let Some(known) = &mut self.known_fields {
+                known.push(alias.clone());
+            }
+            Some(alias)
+        } else {
+            None
+        };
+
+        Ok(match (aggregate, expr) {
+            (Some((func, column)), _) => SelectItem::Aggregate { func, column, alias },
+            (None, Some(Expr::Field(path))) => SelectItem::Column { path, alias },
+            (None, Some(expr)) => SelectItem::Expr { expr, alias },
+            (None, None) => unreachable!(),
+        })
+    }
+
+    // Parses `name(column)` or `COUNT(*)` if the next tokens are an aggregate call
+    fn parse_aggregate_call(&mut self) -> Result<Option<(AggregateFunc, Option<FieldPath>)>> {
+        let func = match (self.peek(), self.tokens.get(self.pos + 1)) {
+            (Some(Token::Ident(name)), Some(Token::LParen)) => match AggregateFunc::from_name(name) {
+                Some(func) => func,
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        self.pos += 2;
+
+        let column = if self.peek() == Some(&Token::Star) && func == AggregateFunc::Count {
+            self.pos += 1;
+            None
+        } else {
+            Some(self.parse_field_path()?)
+        };
+
+        match self.next() {
+            Some(Token::RParen) => Ok(Some((func, column))),
+            _ => Err(self.error_at_last(format!("Expected ')' after {} argument", func))),
+        }
+    }
+
+    // path := (ident | string) ('.' (ident | string) | '[' (number | string) ']')*
+    fn parse_field_path(&mut self) -> Result<FieldPath> {
+        let mut segments = match self.next() {
+            Some(Token::Ident(key)) | Some(Token::Str(key)) | Some(Token::Text(key)) => {
+                self.check_field(&key)?;
+                vec![PathSegment::Key(key)]
+            }
+            Some(token) => return Err(self.error_at_last(format!("Expected field name but found '{}'", token))),
+            None => return Err(self.error_at_last("Expected field name")),
+        };
+
+        loop {
+            match self.peek() {
+                Some(Token::Dot) => {
+                    self.pos += 1;
+                    match self.next() {
+                        Some(Token::Ident(key)) | Some(Token::Str(key)) | Some(Token::Text(key)) => segments.push(PathSegment::Key(key)),
+                        Some(token) => return Err(self.error_at_last(format!("Expected key after '.' but found '{}'", token))),
+                        None => return Err(self.error_at_last("Expected key after '.'")),
+                    }
+                }
+                Some(Token::LBracket) => {
+                    self.pos += 1;
+                    let segment = match self.next() {
+                        Some(Token::Number(n)) => PathSegment::Index(n.parse::<usize>()
+                            .map_err(|_| self.error_at_last(format!("Invalid array index: {}", n)))?),
+                        Some(Token::Str(key) | Token::Text(key)) => PathSegment::Key(key),
+                        Some(token) => return Err(self.error_at_last(format!("Invalid array index: {}", token))),
+                        None => return Err(self.error_at_last("Missing array index")),
+                    };
+                    if self.next() != Some(Token::RBracket) {
+                        return Err(self.error_at_last("Expected ']' after array index"));
+                    }
+                    segments.push(segment);
+                }
+                _ => return Ok(FieldPath { segments }),
+            }
+        }
+    }
+
+    fn expect_end(&self) -> Result<()> {
+        match self.peek() {
+            Some(token) => Err(self.error(format!("Unexpected '{}' at end of query", token))),
+            None => Ok(()),
+        }
+    }
+
+    // or_expr := and_expr (OR and_expr)*
+    fn parse_or(&mut self) -> Result<Query> {
+        let mut left = self.parse_and()?;
+        while self.eat_keyword("or") {
+            let right = self.parse_and()?;
+            left = Query::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    // and_expr := not_expr (AND not_expr)*
+    fn parse_and(&mut self) -> Result<Query> {
+        let mut left = self.parse_not()?;
+        while self.eat_keyword("and") {
+            let right = self.parse_not()?;
+            left = Query::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    // not_expr := NOT not_expr | '(' or_expr ')' | condition
+    fn parse_not(&mut self) -> Result<Query> {
+        if self.eat_keyword("not") {
+            return Ok(Query::Not(Box::new(self.parse_not()?)));
+        }
+
+        // `(` opens either a group of conditions or an expression such as
+        // `(x + y) * 2 < 100`; the first is tried first
+        if self.peek() == Some(&Token::LParen) {
+            let (start, params) = (self.pos, self.params.len());
+            let group = self.parse_group();
+            if group.is_ok() {
+                return group;
+            }
+            let group_end = self.pos;
+            self.pos = start;
+            self.params.truncate(params);
+            return match self.parse_condition() {
+                Ok(condition) => Ok(Query::Condition(condition)),
+                // Report whichever attempt got further
+                Err(_) if group_end >= self.pos => group,
+                Err(err) => Err(err),
+            };
+        }
+
+        // `within` followed by a shape name, so that a field called `within` can
+        // still be compared
+        if self.peek_keyword("within") && matches!(self.tokens.get(self.pos + 1), Some(Token::Ident(_))) {
+            self.pos += 1;
+            return Ok(Query::Condition(Condition::Within(self.parse_shape()?)));
+        }
+
+        Ok(Query::Condition(self.parse_condition()?))
+    }
+
+    // shape := (RECT | RADIUS | POLYGON) '(' number (',' number)* ')'
+    fn parse_shape(&mut self) -> Result<Shape> {
+        let name = self.parse_ident("shape")?.to_uppercase();
+        if !["RECT", "RADIUS", "POLYGON"].contains(&name.as_str()) {
+            return Err(self.error_at_last(format!("Unknown shape {}; expected RECT, RADIUS or POLYGON", name)));
+        }
+        let numbers = self.parse_numbers(&name)?;
+        match (name.as_str(), numbers.as_slice()) {
+            ("RECT", &[x0, y0, x1, y1]) => Ok(Shape::rect(x0, y0, x1, y1)),
+            ("RADIUS", &[_, _, r]) if r < 0.0 => Err(self.error_at_last("RADIUS must not be negative")),
+            ("RADIUS", &[cx, cy, r]) => Ok(Shape::radius(cx, cy, r)),
+            ("POLYGON", points) if points.len() >= 6 && points.len().is_multiple_of(2) => {
+                Ok(Shape::polygon(points.chunks(2).map(|point| (point[0], point[1])).collect()))
+            }
+            ("RECT", _) => Err(self.error_at_last("RECT takes x0, y0, x1, y1")),
+            ("RADIUS", _) => Err(self.error_at_last("RADIUS takes cx, cy, r")),
+            _ => Err(self.error_at_last("POLYGON takes at least three x, y pairs")),
+        }
+    }
+
+    // '(' number (',' number)* ')', the arguments of a shape or a point
+    fn parse_numbers(&mut self, what: &str) -> Result<Vec<f64>> {
+        if self.next() != Some(Token::LParen) {
+            return Err(self.error_at_last(format!("Expected '(' after {}", what)));
+        }
+        let mut numbers = Vec::new();
+        loop {
+            let value = match self.next() {
+                Some(token @ Token::Number(_)) => self.parse_value(what, token)?,
+                Some(token) => return Err(self.error_at_last(format!("Expected a number in {} but found '{}'", what, token))),
+                None => return Err(self.error_at_last(format!("Missing closing ')' for {}", what))),
+            };
+            numbers.push(value.as_f64().unwrap_or(0.0));
+            match self.next() {
+                Some(Token::Comma) => continue,
+                Some(Token::RParen) => return Ok(numbers),
+                Some(token) => return Err(self.error_at_last(format!("Expected ',' or ')' in {} but found '{}'", what, token))),
+                None => return Err(self.error_at_last(format!("Missing closing ')' for {}", what))),
+            }
+        }
+    }
+
+    fn parse_group(&mut self) -> Result<Query> {
+        self.pos += 1;
+        let inner = self.parse_or()?;
+        match self.next() {
+            Some(Token::RParen) => Ok(Query::Group(Box::new(inner))),
+            Some(token) => Err(self.error_at_last(format!("Expected ')' but found '{}'", token))),
+            None => Err(self.error_at_last("Missing closing ')'")),
+        }
+    }
+
+    fn parse_condition(&mut self) -> Result<Condition> {
+        if self.peek().is_none() {
+            return Err(self.error("Expected condition"));
+        }
+        let operand = self.parse_operand()?;
+        let lhs = operand.to_string();
+
+        if self.eat_keyword("in") {
+            let values = self.parse_in_list(&lhs)?;
+            return Ok(operand.predicate(|field| Condition::In { field, values }));
+        }
+        if self.eat_keyword("between") {
+            let low = self.parse_literal(&lhs)?;
+            self.expect_keyword("and")?;
+            let high = self.parse_literal(&lhs)?;
+            return Ok(operand.predicate(|field| Condition::Between { field, low, high }));
+        }
+        if self.eat_keyword("is") {
+            let negated = self.eat_keyword("not");
+            if self.eat_keyword("null") {
+                return Ok(operand.predicate(|field| Condition::IsNull { field, negated }));
+            }
+            if self.eat_keyword("missing") {
+                return Ok(operand.predicate(|field| Condition::IsMissing { field, negated }));
+            }
+            return Err(self.error("Expected NULL or MISSING after IS"));
+        }
+        if self.eat_keyword("contains") {
+            let value = self.parse_literal(&lhs)?;
+            return Ok(operand.predicate(|field| Condition::Contains { field, value }));
+        }
+        if self.eat_keyword("has") {
+            self.expect_keyword("key")?;
+            let key = match self.next() {
+                Some(Token::Str(key) | Token::Text(key) | Token::Ident(key)) => key,
+                Some(token) => return Err(self.error_at_last(format!("Expected key name after HAS KEY but found '{}'", token))),
+                None => return Err(self.error_at_last("Expected key name after HAS KEY")),
+            };
+            return Ok(operand.predicate(|field| Condition::HasKey { field, key }));
+        }
+        if let Some(Token::Op(op @ ("@>" | "<@"))) = self.peek() {
+            let contains = *op == "@>";
+            self.pos += 1;
+            let value = self.parse_json_literal(&lhs)?;
+            return Ok(operand.predicate(|field| match contains {
+                true => Condition::JsonContains { field, value },
+                false => Condition::JsonContainedBy { field, value },
+            }));
+        }
+
+        let op = match self.next() {
+            Some(Token::Op(op)) => match op {
+                "=" => ComparisonOp::Equal,
+                "!=" => ComparisonOp::NotEqual,
+                ">" => ComparisonOp::GreaterThan,
+                "<" => ComparisonOp::LessThan,
+                ">=" => ComparisonOp::GreaterThanOrEqual,
+                "<=" => ComparisonOp::LessThanOrEqual,
+                "~" => ComparisonOp::Regex,
+                // Arithmetic left over after an aggregate or ANY(), which take no part in expressions
+                _ => return Err(self.error_at_last(format!("Arithmetic on '{}' is not supported; expected a comparison but found '{}'", lhs, op))),
+            },
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case("like") => ComparisonOp::Like,
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case("ilike") => ComparisonOp::ILike,
+            Some(token) => return Err(self.error_at_last(format!("Expected operator after '{}' but found '{}'", lhs, token))),
+            None => return Err(self.error_at_last(format!("Invalid condition: {}", lhs))),
+        };
+
+        let value_token = self.next()
+            .ok_or_else(|| self.error_at_last(format!("Missing value for field '{}'", lhs)))?;
+
+        if let Some(param) = self.parse_param(&value_token)? {
+            return Ok(Condition::Parameter { operand, op, param });
+        }
+
+        if op == ComparisonOp::Regex
+            && let Token::Str(pattern) | Token::Text(pattern) = &value_token
+            && let Err(err) = regex::Regex::new(pattern)
+        {
+            return Err(self.error_at_last(format!("Invalid regular expression: {}", err)));
+        }
+
+        // x and y compare against the coordinates, through the coordinate index for
+        // whole numbers and like any field value otherwise, as the builder does
+        let value = self.parse_value(&lhs, value_token)?;
+        Ok(operand.condition(op, value))
+    }
+
+    // operand := aggregate_call | ANY '(' path ')' | LENGTH '(' path ')' | path
+    fn parse_operand(&mut self) -> Result<Operand> {
+        if let Some((func, column)) = self.parse_aggregate_call()? {
+            return Ok(Query::aggregate(func, column));
+        }
+        if let Some(path) = self.parse_path_call("any")? {
+            return Ok(Query::any(path));
+        }
+        Ok(match self.parse_expr()? {
+            Expr::Field(path) => Query::field(path),
+            expr => Query::expr(expr),
+        })
+    }
+
+    // expr := term (('+' | '-') term)*
+    fn parse_expr(&mut self) -> Result<Expr> {
+        let mut left = self.parse_term()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Op("+")) => ArithOp::Add,
+                Some(Token::Op("-")) => ArithOp::Subtract,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            let right = self.parse_term()?;
+            left = Expr::Binary { op, left: Box::new(left), right: Box::new(right) };
+        }
+    }
+
+    // term := unary (('*' | '/' | '%') unary)*
+    fn parse_term(&mut self) -> Result<Expr> {
+        let mut left = self.parse_unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Star) => ArithOp::Multiply,
+                Some(Token::Op("/")) => ArithOp::Divide,
+                Some(Token::Op("%")) => ArithOp::Remainder,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            let right = self.parse_unary()?;
+            left = Expr::Binary { op, left: Box::new(left), right: Box::new(right) };
+        }
+    }
+
+    // unary := '-' unary | primary
+    fn parse_unary(&mut self) -> Result<Expr> {
+        if self.peek() == Some(&Token::Op("-")) {
+            self.pos += 1;
+            return Ok(Expr::Negate(Box::new(self.parse_unary()?)));
+        }
+        self.parse_primary()
+    }
+
+    // primary := number | 'text' | true | false | null | '(' expr ')' | function '(' [expr (',' expr)*] ')' | path
+    fn parse_primary(&mut self) -> Result<Expr> {
+        match self.peek().cloned() {
+            Some(token @ Token::Number(_)) => {
+                self.pos += 1;
+                Ok(Expr::Literal(self.parse_value("expression", token)?))
+            }
+            Some(Token::Text(text)) => {
+                self.pos += 1;
+                Ok(Expr::Literal(Value::String(text)))
+            }
+            Some(token @ Token::Json(_)) => {
+                self.pos += 1;
+                Ok(Expr::Literal(self.parse_value("expression", token)?))
+            }
+            Some(Token::Ident(word)) if ["true", "false", "null"].contains(&word.to_lowercase().as_str()) => {
+                self.pos += 1;
+                Ok(Expr::Literal(serde_json::from_str(&word.to_lowercase())?))
+            }
+            Some(Token::LParen) => {
+                self.pos += 1;
+                let inner = self.parse_expr()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(inner),
+                    _ => Err(self.error_at_last("Expected ')' to close expression")),
+                }
+            }
+            Some(Token::Ident(name)) if self.tokens.get(self.pos + 1) == Some(&Token::LParen) => {
+                self.pos += 2;
+                self.check_function(&name)?;
+                let mut args = Vec::new();
+                if self.peek() == Some(&Token::RParen) {
+                    self.pos += 1;
+                } else {
+                    loop {
+                        args.push(self.parse_expr()?);
+                        match self.next() {
+                            Some(Token::Comma) => continue,
+                            Some(Token::RParen) => break,
+                            _ => return Err(self.error_at_last(format!("Expected ',' or ')' in arguments to {}", name))),
+                        }
+                    }
+                }
+                Ok(Expr::call(&name, args))
+            }
+            _ => Ok(Expr::Field(self.parse_field_path()?)),
+        }
+    }
+
+    // Parses `name(path)` if the next tokens are a call to `name`
+    fn parse_path_call(&mut self, name: &str) -> Result<Option<FieldPath>> {
+        if !self.peek_keyword(name) || self.tokens.get(self.pos + 1) != Some(&Token::LParen) {
+            return Ok(None);
+        }
+        self.pos += 2;
+        let path = self.parse_field_path()?;
+        match self.next() {
+            Some(Token::RParen) => Ok(Some(path)),
+            _ => Err(self.error_at_last(format!("Expected ')' after {} argument", name.to_uppercase()))),
+        }
+    }
+
+    // The operand of @> and <@: a string holding JSON, or a plain number or boolean
+    fn parse_json_literal(&mut self, lhs: &str) -> Result<Value> {
+        match self.next() {
+            Some(Token::Str(json) | Token::Text(json)) => serde_json::from_str(&json)
+                .map_err(|e| self.error_at_last(format!("Invalid JSON for '{}': {}", lhs, e))),
+            Some(token @ (Token::Positional | Token::Named(_))) => {
+                Err(self.error_at_last(format!("Placeholder {} is only allowed after a comparison operator", token)))
+            }
+            Some(token) => self.parse_value(lhs, token),
+            None => Err(self.error_at_last(format!("Missing value for field '{}'", lhs))),
+        }
+    }
+
+    // in_list := '(' value (',' value)* ')', after IN has been consumed
+    fn parse_in_list(&mut self, lhs: &str) -> Result<Vec<Value>> {
+        if self.next() != Some(Token::LParen) {
+            return Err(self.error_at_last("Expected '(' after IN"));
+        }
+        let mut values = vec![self.parse_literal(lhs)?];
+        loop {
+            match self.next() {
+                Some(Token::Comma) => values.push(self.parse_literal(lhs)?),
+                Some(Token::RParen) => return Ok(values),
+                Some(token) => return Err(self.error_at_last(format!("Expected ',' or ')' in IN list but found '{}'", token))),
+                None => return Err(self.error_at_last("Missing closing ')' for IN list")),
+            }
+        }
+    }
+
+    // A value operand of IN, BETWEEN or CONTAINS, which do not take placeholders
+    fn parse_literal(&mut self, lhs: &str) -> Result<Value> {
+        match self.next() {
+            Some(token @ (Token::Positional | Token::Named(_))) => {
+                Err(self.error_at_last(format!("Placeholder {} is only allowed after a comparison operator", token)))
+            }
+            Some(token) => self.parse_value(lhs, token),
+            None => Err(self.error_at_last(format!("Missing value for field '{}'", lhs))),
+        }
+    }
+
+    // Turns the placeholder token just consumed into a parameter. A query may use
+    // `?` or `:name` placeholders but not both.
+    fn parse_param(&mut self, token: &Token) -> Result<Option<Param>> {
+        let param = match token {
+            Token::Positional => Param::Positional(self.params.len()),
+            Token::Named(name) => Param::Named(name.clone()),
+            _ => return Ok(None),
+        };
+        if !self.allow_params {
+            return Err(self.error_at_last(format!("Placeholder {} is only allowed in a prepared query", token)));
+        }
+        if self.params.first().is_some_and(|first| std::mem::discriminant(first) != std::mem::discriminant(&param)) {
+            return Err(self.error_at_last("Cannot mix '?' and ':name' placeholders in one query"));
+        }
+        self.params.push(param.clone());
+        Ok(Some(param))
+    }
+
+    // Converts the value token just consumed into a JSON literal
+    fn parse_value(&self, lhs: &str, token: Token) -> Result<Value> {
+        Ok(match token {
+            Token::Str(s) | Token::Text(s) => Value::String(s),
+            Token::Number(n) => {
+                if let Ok(num) = n.parse::<i64>() {
+                    Value::Number(serde_json::Number::from(num))
+                } else if let Some(num) = n.parse::<f64>().ok().and_then(serde_json::Number::from_f64) {
+                    Value::Number(num)
+                } else {
+                    return Err(self.error_at_last(format!("Invalid number: {}", n)));
+                }
+            }
+            Token::Ident(s) if s == "true" => Value::Bool(true),
+            Token::Ident(s) if s == "false" => Value::Bool(false),
+            Token::Ident(s) if s == "null" => Value::Null,
+            Token::Ident(s) => Value::String(s),
+            Token::Json(json) => serde_json::from_str(&json)
+                .map_err(|e| self.error_at_last(format!("Invalid JSON for '{}': {}", lhs, e)))?,
+            token => return Err(self.error_at_last(format!("Expected value for field '{}' but found '{}'", lhs, token))),
+        })
+    }
+}
+
+fn parse_error(message: impl Into<String>, position: usize, query: &str) -> PngDbError {
+    PngDbError::ParseError { message: message.into(), position, query: query.to_string() }
+}
+
+// Renders a parse error with the offending line of the query and a caret under
+// the error position
+pub(crate) fn describe_parse_error(message: &str, position: usize, query: &str) -> String {
+    let before: String = query.chars().take(position).collect();
+    let line_number = before.matches('\n').count();
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let column = before[line_start..].chars().count();
+    let line = query.lines().nth(line_number).unwrap_or("");
+
+    let location = if query.contains('\n') {
+        format!("line {}, column {}", line_number + 1, column + 1)
+    } else {
+        format!("column {}", column + 1)
+    };
+    format!("Parse error at {}: {}\n  {}\n  {}^", location, message, line, " ".repeat(column))
+}
+
+// The closest known name to a misspelled one, if any is close enough to be a likely typo
+fn suggest<'a>(name: &str, known: &'a [String]) -> Option<&'a str> {
+    let name = name.to_lowercase();
+    let max_distance = (name.chars().count() / 3).max(1);
+    known.iter()
+        .map(|candidate| (edit_distance(&name, &candidate.to_lowercase()), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate.as_str())
+}
+
+// Whether `a` and `b` are the same letters in some order, ignoring case
+fn same_letters(a: &str, b: &str) -> bool {
+    let letters = |s: &str| {
+        let mut letters: Vec<char> = s.to_lowercase().chars().collect();
+        letters.sort_unstable();
+        letters
+    };
+    letters(a) == letters(b)
+}
+
+// Edit distance where swapping two adjacent characters counts as one edit
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d: Vec<Vec<usize>> = (0..=a.len()).map(|i| (i..=i + b.len()).collect()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+pub fn parse_query(query_str: &str) -> Result<Query> {
+    parse_query_for(query_str, None)
+}
+
+// The fields and functions a database knows about, so that the parser can
+// report misspellings of them
+pub(crate) struct KnownNames {
+    pub fields: Vec<String>,
+    pub declared: Vec<String>,
+    pub functions: Vec<String>,
+}
+
+// Like `parse_query`, but misspellings of `known` names are reported as errors
+pub(crate) fn parse_query_for(query_str: &str, known: Option<KnownNames>) -> Result<Query> {
+    let mut parser = Parser::new(query_str)?;
+    parser.learn(known);
+
+    if !parser.eat_keyword("where") {
+        return Err(parser.error("Query must start with WHERE"));
+    }
+
+    let query = parser.parse_or()?;
+    parser.expect_end()?;
+
+    Ok(query)
+}
+
+pub fn parse_select(query_str: &str) -> Result<Select> {
+    parse_select_for(query_str, None)
+}
+
+pub(crate) fn parse_select_for(query_str: &str, known: Option<KnownNames>) -> Result<Select> {
+    let mut parser = Parser::new(query_str)?;
+    parser.learn(known);
+
+    let select = parser.parse_select()?;
+    parser.expect_end()?;
+
+    Ok(select)
+}
+
+// Like `parse_select_for`, after an optional EXPLAIN
+pub(crate) fn parse_explain_for(query_str: &str, known: Option<KnownNames>) -> Result<Select> {
+    let mut parser = Parser::new(query_str)?;
+    parser.learn(known);
+    parser.eat_keyword("explain");
+
+    let select = parser.parse_select()?;
+    parser.expect_end()?;
+
+    Ok(select)
+}
+
+// Parses a statement that may contain placeholders, returning it with its
+// parameters in order of appearance
+pub(crate) fn parse_prepared(query_str: &str) -> Result<(Select, Vec<Param>)> {
+    let mut parser = Parser::new(query_str)?;
+    parser.allow_params = true;
+
+    let select = parser.parse_select()?;
+    parser.expect_end()?;
+
+    Ok((select, parser.params))
+}
+
+pub fn parse_field_path(path_str: &str) -> Result<FieldPath> {
+    let mut parser = Parser::new(path_str)?;
+
+    let path = parser.parse_field_path()?;
+    parser.expect_end()?;
+
+    Ok(path)
+}
+
+pub fn parse_index_statement(statement: &str) -> Result<IndexStatement> {
+    let mut parser = Parser::new(statement)?;
+
+    let parsed = parser.parse_index_statement()?;
+    parser.expect_end()?;
+
+    Ok(parsed)
+}
+
+pub fn parse_shape(shape_str: &str) -> Result<Shape> {
+    let mut parser = Parser::new(shape_str)?;
+
+    let shape = parser.parse_shape()?;
+    parser.expect_end()?;
+
+    Ok(shape)
+}
+
+pub(crate) fn execute_select(select: &Select, db: &PngDatabase) -> Result<ResultSet> {
+    let functions = &db.functions;
+    let mut matched = match &select.nearest {
+        Some(nearest) => db.nearest(nearest.x, nearest.y, nearest.k, select.filter.as_ref())?,
+        None => db.matching_rows(select.filter.as_ref())?.into_iter().map(|index| &db.rows[index]).collect(),
+    };
+
+    let is_aggregate = !select.group_by.is_empty()
+        || select.having.is_some()
+        || matches!(&select.projection, Projection::Items(items)
+            if items.iter().any(|item| matches!(item, SelectItem::Aggregate { .. })));
+    if is_aggregate {
+        return execute_grouped(select, matched, functions);
+    }
+
+    if !select.order_by.is_empty() {
+        let mut keys = Vec::new();
+        for order in &select.order_by {
+            let expr = match &order.key {
+                OrderKey::Field(path) => resolve_alias(&select.projection, path),
+                OrderKey::Expr(expr) => expr.clone(),
+                OrderKey::Aggregate { .. } => {
+                    return Err(PngDbError::QueryError(format!("Cannot ORDER BY {} without GROUP BY or aggregates in the select list", order.key.name())));
+                }
+            };
+            keys.push(expr);
+        }
+
+        // Sort keys are computed once per row, as evaluating them may fail
+        let mut keyed = Vec::with_capacity(matched.len());
+        for row in matched {
+            let values = keys.iter().map(|expr| expr.eval(row, functions)).collect::<Result<Vec<_>>>()?;
+            keyed.push((values, row));
+        }
+        keyed.sort_by(|(a, _), (b, _)| {
+            for (index, order) in select.order_by.iter().enumerate() {
+                let ordering = compare_for_sort(&a[index], &b[index]);
+                let ordering = if order.descending { ordering.reverse() } else { ordering };
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            Ordering::Equal
+        });
+        matched = keyed.into_iter().map(|(_, row)| row).collect();
+    }
+
+    let (columns, sources) = match &select.projection {
+        Projection::Items(items) => {
+            let mut columns = Vec::new();
+            let mut sources = Vec::new();
+            for item in items {
+                match item {
+                    SelectItem::Column { path, .. } => sources.push(Expr::Field(path.clone())),
+                    SelectItem::Expr { expr, .. } => sources.push(expr.clone()),
+                    SelectItem::Aggregate { .. } => continue,
+                }
+                columns.push(item.output_name());
+            }
+            (columns, sources)
+        }
+        Projection::All => {
+            let mut fields: BTreeSet<String> = db.schema.fields.keys().cloned().collect();
+            for row in &matched {
+                if let Value::Object(object) = &row.data {
+                    fields.extend(object.keys().cloned());
+                }
+            }
+            fields.remove("x");
+            fields.remove("y");
+            let columns: Vec<String> = ["x", "y"].iter().map(|c| c.to_string()).chain(fields).collect();
+            let sources = columns.iter().map(|column| Expr::field(FieldPath::key(column))).collect();
+            (columns, sources)
+        }
+    };
+
+    let rows = paginate(select, matched)
+        .map(|row| sources.iter().map(|source| source.eval(row, functions)).collect())
+        .collect::<Result<_>>()?;
+
+    Ok(ResultSet { columns, rows })
+}
+
+// ORDER BY may name a column or expression alias from the select list
+fn resolve_alias(projection: &Projection, path: &FieldPath) -> Expr {
+    if let (Projection::Items(items), Some(name)) = (projection, path.as_key()) {
+        for item in items {
+            match item {
+                SelectItem::Column { path: source, alias: Some(alias) } if alias == name => return Expr::Field(source.clone()),
+                SelectItem::Expr { expr, alias: Some(alias) } if alias == name => return expr.clone(),
+                _ => {}
+            }
+        }
+    }
+    Expr::Field(path.clone())
+}
+
+fn paginate<T>(select: &Select, rows: Vec<T>) -> impl Iterator<Item = T> {
+    rows.into_iter()
+        .skip(select.offset.unwrap_or(0))
+        .take(select.limit.unwrap_or(usize::MAX))
+}
+
+fn execute_grouped(select: &Select, matched: Vec<&DataRow>, functions: &FunctionRegistry) -> Result<ResultSet> {
+    let items = match &select.projection {
+        Projection::Items(items) => items,
+        Projection::All => {
+            return Err(PngDbError::QueryError("SELECT * cannot be used with GROUP BY or aggregate functions".to_string()));
+        }
+    };
+
+    for item in items {
+        let fields = match item {
+            SelectItem::Column { path, .. } => vec![path],
+            SelectItem::Expr { expr, .. } => expr.fields(),
+            SelectItem::Aggregate { .. } => continue,
+        };
+        if let Some(path) = fields.into_iter().find(|path| !select.group_by.contains(path)) {
+            return Err(PngDbError::QueryError(format!("Column '{}' must appear in GROUP BY or be used in an aggregate function", path)));
+        }
+    }
+
+    // Groups keep the order in which their first row was seen
+    let mut groups: Vec<Vec<&DataRow>> = Vec::new();
+    if select.group_by.is_empty() {
+        groups.push(matched);
+    } else {
+        let mut group_index: HashMap<String, usize> = HashMap::new();
+        for row in matched {
+            let key: Vec<Value> = select.group_by.iter().map(|column| column_value(row, column)).collect();
+            let key = Value::Array(key).to_string();
+            let index = *group_index.entry(key).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[index].push(row);
+        }
+    }
+
+    let columns: Vec<String> = items.iter().map(SelectItem::output_name).collect();
+    let mut output = Vec::new();
+
+    for group in &groups {
+        if let Some(having) = &select.having
+            && !evaluate(having, &|condition| matches_group_condition(group, select, items, condition, functions))?
+        {
+            continue;
+        }
+
+        let mut values = Vec::new();
+        for item in items {
+            let value = match item {
+                SelectItem::Column { path, .. } => group.first().map(|row| column_value(row, path)).unwrap_or(Value::Null),
+                SelectItem::Expr { expr, .. } => match group.first() {
+                    Some(row) => expr.eval(row, functions)?,
+                    None => Value::Null,
+                },
+                SelectItem::Aggregate { func, column, .. } => aggregate(*func, column.as_ref(), group)?,
+            };
+            values.push(value);
+        }
+        output.push(values);
+    }
+
+    if !select.order_by.is_empty() {
+        let mut keys = Vec::new();
+        for order in &select.order_by {
+            let name = order.key.name();
+            let index = columns.iter().position(|column| column == &name)
+                .or_else(|| items.iter().position(|item| match (item, &order.key) {
+                    (SelectItem::Column { path, .. }, OrderKey::Field(key)) => path == key,
+                    (SelectItem::Expr { expr, .. }, OrderKey::Expr(key)) => expr == key,
+                    (SelectItem::Aggregate { func, column, .. }, OrderKey::Aggregate { func: key_func, column: key_column }) => {
+                        func == key_func && column == key_column
+                    }
+                    _ => false,
+                }))
+                .ok_or_else(|| PngDbError::QueryError(format!("ORDER BY column '{}' must appear in the select list of a grouped query", name)))?;
+            keys.push((index, order.descending));
+        }
+
+        output.sort_by(|a, b| {
+            for &(index, descending) in &keys {
+                let ordering = compare_for_sort(&a[index], &b[index]);
+                let ordering = if descending { ordering.reverse() } else { ordering };
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            Ordering::Equal
+        });
+    }
+
+    let rows = paginate(select, output).collect();
+    Ok(ResultSet { columns, rows })
+}
+
+fn aggregate(func: AggregateFunc, column: Option<&FieldPath>, rows: &[&DataRow]) -> Result<Value> {
+    let values: Vec<Value> = match column {
+        Some(column) => rows.iter().map(|row| column_value(row, column)).filter(|v| !v.is_null()).collect(),
+        None => return Ok(Value::from(rows.len())),
+    };
+
+    match func {
+        AggregateFunc::Count => Ok(Value::from(values.len())),
+        AggregateFunc::Sum | AggregateFunc::Avg => {
+            if values.is_empty() {
+                return Ok(Value::Null);
+            }
+
+            let mut int_sum: Option<i64> = Some(0);
+            let mut float_sum = 0.0;
+            for value in &values {
+                let number = match value {
+                    Value::Number(number) => number,
+                    other => {
+                        return Err(PngDbError::QueryError(format!("{} requires numeric values, found {}", func, other)));
+                    }
+                };
+                int_sum = int_sum.zip(number.as_i64()).and_then(|(sum, n)| sum.checked_add(n));
+                float_sum += number.as_f64().unwrap_or(0.0);
+            }
+
+            if func == AggregateFunc::Avg {
+                return Ok(Value::from(float_sum / values.len() as f64));
+            }
+            Ok(match int_sum {
+                Some(sum) => Value::from(sum),
+                None => Value::from(float_sum),
+            })
+        }
+        AggregateFunc::Min => Ok(values.into_iter().min_by(compare_for_sort).unwrap_or(Value::Null)),
+        AggregateFunc::Max => Ok(values.into_iter().max_by(compare_for_sort).unwrap_or(Value::Null)),
+    }
+}
+
+fn matches_group_condition(
+    group: &[&DataRow],
+    select: &Select,
+    items: &[SelectItem],
+    condition: &Condition,
+    functions: &FunctionRegistry,
+) -> Result<bool> {
+    let field = match condition {
+        Condition::Aggregate { func, column, op, value } => {
+            let aggregated = aggregate(*func, column.as_ref(), group)?;
+            if aggregated.is_null() {
+                return Ok(false);
+            }
+            return compare_json_values(&aggregated, value, op);
+        }
+        Condition::Parameter { param, .. } => return Err(unbound(param)),
+        Condition::Within(_) => return Err(PngDbError::QueryError("WITHIN is only allowed in WHERE".to_string())),
+        Condition::Computed { expr, .. } => {
+            if let Some(path) = expr.fields().into_iter().find(|path| !select.group_by.contains(path)) {
+                return Err(PngDbError::QueryError(format!("HAVING column '{}' must appear in GROUP BY", path)));
+            }
+            return match group.first() {
+                Some(row) => matches_condition(row, condition, functions),
+                None => Ok(false),
+            };
+        }
+        _ => condition.field().unwrap_or_else(FieldPath::root),
+    };
+
+    if select.group_by.contains(&field) {
+        return match group.first() {
+            Some(row) => matches_condition(row, condition, functions),
+            None => Ok(false),
+        };
+    }
+
+    // HAVING may refer to an aggregate in the select list by its alias, or by its
+    // call for operators that only take a field, e.g. `COUNT(*) BETWEEN 2 AND 5`
+    let aggregated = items.iter().find_map(|item| match item {
+        SelectItem::Aggregate { func, column, alias } => {
+            let name = field.as_key()?;
+            (alias.as_deref() == Some(name) || aggregate_name(func, column.as_ref()) == name).then_some((*func, column.as_ref()))
+        }
+        SelectItem::Column { .. } | SelectItem::Expr { .. } => None,
+    });
+    let (func, column) = aggregated
+        .ok_or_else(|| PngDbError::QueryError(format!("HAVING column '{}' must appear in GROUP BY", field)))?;
+    let aggregated = aggregate(func, column, group)?;
+    if aggregated.is_null() && !matches!(condition, Condition::IsNull { .. } | Condition::IsMissing { .. }) {
+        return Ok(false);
+    }
+    test_condition(Some(&aggregated), condition)
+}
+
+fn column_value(row: &DataRow, path: &FieldPath) -> Value {
+    match path.as_key() {
+        Some("x") => Value::from(row.x),
+        Some("y") => Value::from(row.y),
+        _ => path.resolve(&row.data).cloned().unwrap_or(Value::Null),
+    }
+}
+
+// Total order used by ORDER BY: null < bool < number < string < array < object
+fn compare_for_sort(left: &Value, right: &Value) -> Ordering {
+    fn rank(value: &Value) -> u8 {
+        match value {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Number(_) => 2,
+            Value::String(_) => 3,
+            Value::Array(_) => 4,
+            Value::Object(_) => 5,
+        }
+    }
+
+    match (left, right) {
+        (Value::Bool(l), Value::Bool(r)) => l.cmp(r),
+        (Value::Number(l), Value::Number(r)) => {
+            let l_val = l.as_f64().unwrap_or(0.0);
+            let r_val = r.as_f64().unwrap_or(0.0);
+            l_val.total_cmp(&r_val)
+        }
+        (Value::String(l), Value::String(r)) => l.cmp(r),
+        _ => rank(left).cmp(&rank(right)),
+    }
+}
+
+// The smallest area, as (min x, min y, max x, max y), holding the cell of every
+// row that can match `query`; `None` if the query does not limit it
+pub(crate) fn spatial_bounds(query: &Query) -> Option<(f64, f64, f64, f64)> {
+    const EVERYWHERE: (f64, f64, f64, f64) = (f64::NEG_INFINITY, f64::NEG_INFINITY, f64::INFINITY, f64::INFINITY);
+
+    match query {
+        Query::Condition(condition) => {
+            let (axis, low, high) = match condition {
+                Condition::Within(shape) => return Some(shape.bounds()),
+                Condition::Coordinate { field, op, value } => {
+                    let value = *value as f64;
+                    let (low, high) = match op {
+                        ComparisonOp::Equal => (value, value),
+                        ComparisonOp::GreaterThan | ComparisonOp::GreaterThanOrEqual => (value, f64::INFINITY),
+                        ComparisonOp::LessThan | ComparisonOp::LessThanOrEqual => (f64::NEG_INFINITY, value),
+                        _ => return None,
+                    };
+                    (field.as_str(), low, high)
+                }
+                Condition::Between { field, low, high } if field.is_coordinate() => {
+                    (field.as_key()?, low.as_f64()?, high.as_f64()?)
+                }
+                _ => return None,
+            };
+            let (min_x, min_y, max_x, max_y) = EVERYWHERE;
+            Some(if axis == "x" { (low, min_y, high, max_y) } else { (min_x, low, max_x, high) })
+        }
+        Query::And(left, right) => match (spatial_bounds(left), spatial_bounds(right)) {
+            (Some(l), Some(r)) => Some((l.0.max(r.0), l.1.max(r.1), l.2.min(r.2), l.3.min(r.3))),
+            (bounds, None) | (None, bounds) => bounds,
+        },
+        Query::Or(left, right) => {
+            let (l, r) = (spatial_bounds(left)?, spatial_bounds(right)?);
+            Some((l.0.min(r.0), l.1.min(r.1), l.2.max(r.2), l.3.max(r.3)))
+        }
+        Query::Group(inner) => spatial_bounds(inner),
+        Query::Not(_) => None,
+    }
+}
+
+// Indices, in storage order, of a set of rows holding every row that can match
+// `query`, found through the field indexes `find` returns; `None` if the query
+// does not limit its fields to values an index can look up
+pub(crate) fn indexed_rows<'a, F>(query: &Query, find: &F) -> Option<Vec<usize>>
+where
+    F: Fn(&FieldPath) -> Option<&'a FieldIndex>,
+{
+    match query {
+        Query::Condition(condition) => match condition {
+            Condition::JsonField { field, op, value } => {
+                let index = find(field)?;
+                match op {
+                    ComparisonOp::Equal => index.rows_equal(value),
+                    ComparisonOp::GreaterThan => index.rows_in_range(Bound::Excluded(value), Bound::Unbounded),
+                    ComparisonOp::GreaterThanOrEqual => index.rows_in_range(Bound::Included(value), Bound::Unbounded),
+                    ComparisonOp::LessThan => index.rows_in_range(Bound::Unbounded, Bound::Excluded(value)),
+                    ComparisonOp::LessThanOrEqual => index.rows_in_range(Bound::Unbounded, Bound::Included(value)),
+                    _ => None,
+                }
+            }
+            Condition::In { field, values } => {
+                let index = find(field)?;
+                let mut found = Vec::new();
+                for value in values {
+                    found = index::union(&found, &index.rows_equal(value)?);
+                }
+                Some(found)
+            }
+            Condition::Between { field, low, high } => find(field)?.rows_in_range(Bound::Included(low), Bound::Included(high)),
+            _ => None,
+        },
+        Query::And(left, right) => match (indexed_rows(left, find), indexed_rows(right, find)) {
+            (Some(l), Some(r)) => Some(index::intersect(&l, &r)),
+            (rows, None) | (None, rows) => rows,
+        },
+        Query::Or(left, right) => Some(index::union(&indexed_rows(left, find)?, &indexed_rows(right, find)?)),
+        Query::Group(inner) => indexed_rows(inner, find),
+        Query::Not(_) => None,
+    }
+}
+
+pub(crate) fn matches_query(row: &DataRow, query: &Query, functions: &FunctionRegistry) -> Result<bool> {
+    evaluate(query, &|condition| matches_condition(row, condition, functions))
+}
+
+fn evaluate<F>(query: &Query, matches: &F) -> Result<bool>
+where
+    F: Fn(&Condition) -> Result<bool>,
+{
+    match query {
+        Query::Condition(condition) => matches(condition),
+        Query::And(left, right) => Ok(evaluate(left, matches)? && evaluate(right, matches)?),
+        Query::Or(left, right) => Ok(evaluate(left, matches)? || evaluate(right, matches)?),
+        Query::Not(inner) => Ok(!evaluate(inner, matches)?),
+        Query::Group(inner) => evaluate(inner, matches),
+    }
+}
+
+fn matches_condition(row: &DataRow, condition: &Condition, functions: &FunctionRegistry) -> Result<bool> {
+    let field = match condition {
+        Condition::Within(shape) => return Ok(shape.contains(row.x as f64, row.y as f64)),
+        // A null result, e.g. from arithmetic on a missing field, only matches IS NULL
+        Condition::Computed { expr, condition } => {
+            let value = expr.eval(row, functions)?;
+            if value.is_null() && !matches!(**condition, Condition::IsNull { .. }) {
+                return Ok(false);
+            }
+            return test_condition(Some(&value), condition);
+        }
+        Condition::Coordinate { field, .. } => return test_condition(Some(&Value::from(coordinate(row, field))), condition),
+        Condition::Aggregate { func, .. } => {
+            return Err(PngDbError::QueryError(format!("Aggregate function {} is only allowed in HAVING", func)));
+        }
+        Condition::Parameter { param, .. } => return Err(unbound(param)),
+        _ => condition.field().unwrap_or_else(FieldPath::root),
+    };
+
+    // x and y name the row's coordinates, as in the select list
+    match field.as_key() {
+        Some(key @ ("x" | "y")) => test_condition(Some(&Value::from(coordinate(row, key))), condition),
+        _ => test_condition(field.resolve(&row.data), condition),
+    }
+}
+
+fn coordinate(row: &DataRow, field: &str) -> u32 {
+    if field == "x" { row.x } else { row.y }
+}
+
+// Applies a condition's operator to the value of its field, `None` if the field is missing
+fn test_condition(actual: Option<&Value>, condition: &Condition) -> Result<bool> {
+    match condition {
+        Condition::IsNull { negated, .. } => return Ok(actual.is_some_and(|value| value.is_null() != *negated)),
+        Condition::IsMissing { negated, .. } => return Ok(actual.is_none() != *negated),
+        _ => {}
+    }
+    let Some(actual) = actual else {
+        return Ok(false);
+    };
+
+    match condition {
+        Condition::Coordinate { op, value, .. } => compare_json_values(actual, &Value::from(*value), op),
+        Condition::JsonField { op, value, .. } | Condition::Aggregate { op, value, .. } => compare_json_values(actual, value, op),
+        Condition::In { values, .. } => Ok(values.iter().any(|value| json_equals(actual, value))),
+        Condition::Between { low, high, .. } => {
+            Ok(compare_json_values(actual, low, &ComparisonOp::GreaterThanOrEqual)?
+                && compare_json_values(actual, high, &ComparisonOp::LessThanOrEqual)?)
+        }
+        Condition::Contains { value, .. } => Ok(match (actual, value) {
+            (Value::Array(elements), _) => elements.iter().any(|element| json_equals(element, value)),
+            (Value::String(text), Value::String(part)) => text.contains(part.as_str()),
+            _ => false,
+        }),
+        Condition::HasKey { key, .. } => Ok(matches!(actual, Value::Object(object) if object.contains_key(key))),
+        Condition::JsonContains { value, .. } => Ok(json_contains(actual, value)),
+        Condition::JsonContainedBy { value, .. } => Ok(json_contains(value, actual)),
+        Condition::Any { condition, .. } => match actual {
+            Value::Array(elements) => {
+                for element in elements {
+                    if test_condition(Some(element), condition)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            _ => Ok(false),
+        },
+        Condition::Computed { expr, .. } => Err(PngDbError::QueryError(format!("Expression {} cannot be used here", expr))),
+        Condition::Within(shape) => Err(PngDbError::QueryError(format!("WITHIN {} cannot be used here", shape))),
+        Condition::Parameter { param, .. } => Err(unbound(param)),
+        Condition::IsNull { .. } | Condition::IsMissing { .. } => unreachable!(),
+    }
+}
+
+// jsonb-style containment: every key of an object and every element of an array
+// in `contained` is matched in `container`, recursively. As in Postgres, an array
+// also contains a bare scalar that is one of its elements.
+fn json_contains(container: &Value, contained: &Value) -> bool {
+    match (container, contained) {
+        (Value::Object(container), Value::Object(contained)) => contained.iter()
+            .all(|(key, value)| container.get(key).is_some_and(|inner| json_contains(inner, value))),
+        (Value::Array(container), Value::Array(contained)) => contained.iter()
+            .all(|value| container.iter().any(|inner| json_contains(inner, value))),
+        (Value::Array(container), scalar) if !scalar.is_array() && !scalar.is_object() => {
+            container.iter().any(|inner| json_equals(inner, scalar))
+        }
+        _ => json_equals(container, contained),
+    }
+}
+
+// Equality where a value of another type is simply not a match. Numbers, also
+// inside arrays and objects, are compared as `numbers_equal` does.
+fn json_equals(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Number(l), Value::Number(r)) => numbers_equal(l.as_f64().unwrap_or(0.0), r.as_f64().unwrap_or(0.0)),
+        (Value::Array(l), Value::Array(r)) => l.len() == r.len() && l.iter().zip(r).all(|(l, r)| json_equals(l, r)),
+        (Value::Object(l), Value::Object(r)) => {
+            l.len() == r.len() && l.iter().all(|(key, l)| r.get(key).is_some_and(|r| json_equals(l, r)))
+        }
+        _ => left == right,
+    }
+}
+
+// Numbers are equal when they differ by less than f64::EPSILON, for =, IN and
+// CONTAINS alike, and the field indexes bucket numbers to match
+fn numbers_equal(left: f64, right: f64) -> bool {
+    (left - right).abs() < f64::EPSILON
+}
+
+fn unbound(param: &Param) -> PngDbError {
+    PngDbError::QueryError(format!("Parameter {} has no bound value; execute the query through a PreparedQuery", param))
+}
+
+fn compare_json_values(left: &Value, right: &Value, op: &ComparisonOp) -> Result<bool> {
+    use Value::*;
+
+    if op.is_pattern() {
+        return matches_pattern(left, right, op);
+    }
+
+    match (left, right) {
+        // Lexicographic, by Unicode code point
+        (String(l), String(r)) => Ok(match op {
+            ComparisonOp::Equal => l == r,
+            ComparisonOp::NotEqual => l != r,
+            ComparisonOp::GreaterThan => l > r,
+            ComparisonOp::LessThan => l < r,
+            ComparisonOp::GreaterThanOrEqual => l >= r,
+            ComparisonOp::LessThanOrEqual => l <= r,
+            _ => unreachable!(),
+        }),
+        (Number(l), Number(r)) => {
+            let l_val = l.as_f64().unwrap_or(0.0);
+            let r_val = r.as_f64().unwrap_or(0.0);
+            Ok(match op {
+                ComparisonOp::Equal => numbers_equal(l_val, r_val),
+                ComparisonOp::NotEqual => !numbers_equal(l_val, r_val),
+                ComparisonOp::GreaterThan => l_val > r_val,
+                ComparisonOp::LessThan => l_val < r_val,
+                ComparisonOp::GreaterThanOrEqual => l_val >= r_val,
+                ComparisonOp::LessThanOrEqual => l_val <= r_val,
+                _ => unreachable!(),
+            })
+        }
+        (Bool(l), Bool(r)) => Ok(match op {
+            ComparisonOp::Equal => l == r,
+            ComparisonOp::NotEqual => l != r,
+            _ => return Err(PngDbError::QueryError("Boolean comparison only supports = and !=".to_string())),
+        }),
+        // Values of different types, or arrays, objects and nulls, are never equal
+        // to one another but cannot be ordered
+        _ => match op {
+            ComparisonOp::Equal => Ok(json_equals(left, right)),
+            ComparisonOp::NotEqual => Ok(!json_equals(left, right)),
+            _ => Err(PngDbError::QueryError(format!("Cannot compare {} with {} using {}", describe(left), describe(right), op))),
+        },
+    }
+}
+
+// LIKE, ILIKE and ~. Numbers and booleans are matched by their text, so
+// `x LIKE "1%"` works on coordinates; null never matches.
+fn matches_pattern(value: &Value, pattern: &Value, op: &ComparisonOp) -> Result<bool> {
+    let Value::String(pattern) = pattern else {
+        return Err(PngDbError::QueryError(format!("{} requires a string pattern, found {}", op, pattern)));
+    };
+    let text = match value {
+        Value::Null => return Ok(false),
+        Value::String(text) => text.clone(),
+        Value::Number(_) | Value::Bool(_) => value.to_string(),
+        Value::Array(_) | Value::Object(_) => {
+            return Err(PngDbError::QueryError(format!("{} cannot match an array or object", op)));
+        }
+    };
+
+    thread_local! {
+        // Compiled patterns, so a query compiles each one once rather than once per row
+        static PATTERNS: RefCell<HashMap<(ComparisonOp, String), Regex>> = RefCell::new(HashMap::new());
+    }
+
+    PATTERNS.with(|patterns| {
+        let mut patterns = patterns.borrow_mut();
+        let key = (*op, pattern.clone());
+        if !patterns.contains_key(&key) {
+            let source = match op {
+                ComparisonOp::Like => like_to_regex(pattern),
+                ComparisonOp::ILike => format!("(?i){}", like_to_regex(pattern)),
+                _ => pattern.clone(),
+            };
+            let regex = Regex::new(&source)
+                .map_err(|e| PngDbError::QueryError(format!("Invalid regular expression: {}", e)))?;
+            if patterns.len() >= 64 {
+                patterns.clear();
+            }
+            patterns.insert(key.clone(), regex);
+        }
+        Ok(patterns[&key].is_match(&text))
+    })
+}
+
+// Translates a LIKE pattern to an anchored regex; a backslash makes the next
+// character literal, e.g. `100\%`
+fn like_to_regex(pattern: &str) -> String {
+    let mut regex = String::from("(?s)^");
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '%' => regex.push_str(".*"),
+            '_' => regex.push('.'),
+            '\\' => match chars.next() {
+                Some(escaped) => regex.push_str(&regex::escape(&escaped.to_string())),
+                None => regex.push_str(&regex::escape("\\")),
+            },
+            other => regex.push_str(&regex::escape(&other.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
@@ -0,0 +1,277 @@
+use crate::{PngDbError, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schema {
+    pub fields: HashMap<String, String>,
+    // Bumped by every schema change; files written before versioning count as version 1
+    #[serde(default = "initial_version")]
+    pub version: u32,
+}
+
+fn initial_version() -> u32 {
+    1
+}
+
+// Types whose rows have a known png-db schema; see `#[derive(HasSchema)]` behind the `derive` feature
+pub trait HasSchema {
+    fn schema() -> Schema;
+}
+
+// A single field of a row that does not match the schema
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub field: String,
+    pub message: String,
+}
+
+impl Violation {
+    pub fn new(field: &str, message: impl Into<String>) -> Self {
+        Self { field: field.to_string(), message: message.into() }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "field '{}' {}", self.field, self.message)
+    }
+}
+
+impl From<Violation> for PngDbError {
+    fn from(violation: Violation) -> Self {
+        PngDbError::SchemaViolation { row: None, violations: vec![violation] }
+    }
+}
+
+// What to do with an existing value that cannot be converted to a column's new type
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConversionRule {
+    // Abort the whole change
+    Fail,
+    // Replace the value with null
+    SetNull,
+    // Remove the field from the row
+    Remove,
+    // Replace the value with a fixed default
+    Default(Value),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    String,
+    Integer,
+    Float,
+    // Integer or float, the original png-db numeric type
+    Number,
+    Boolean,
+    Null,
+    Array,
+    Object,
+    Any,
+}
+
+// A parsed schema type such as `integer`, `string?` (nullable) or `float!` (required)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldType {
+    pub kind: FieldKind,
+    pub nullable: bool,
+    pub required: bool,
+}
+
+impl FieldKind {
+    // Converts a value to this kind, e.g. "42" to 42 or 1 to true
+    pub fn convert(&self, value: &Value) -> Option<Value> {
+        if self.accepts(value) {
+            return Some(value.clone());
+        }
+
+        match (self, value) {
+            (FieldKind::String, Value::Number(n)) => Some(Value::String(n.to_string())),
+            (FieldKind::String, Value::Bool(b)) => Some(Value::String(b.to_string())),
+            (FieldKind::String, Value::Array(_) | Value::Object(_)) => Some(Value::String(value.to_string())),
+            (FieldKind::Integer, Value::Number(n)) => {
+                let float = n.as_f64()?;
+                (float.fract() == 0.0 && float.abs() < i64::MAX as f64).then(|| Value::from(float as i64))
+            }
+            (FieldKind::Integer, Value::String(s)) => s.trim().parse::<i64>().ok().map(Value::from),
+            (FieldKind::Integer | FieldKind::Float | FieldKind::Number, Value::Bool(b)) => Some(Value::from(*b as i64)),
+            (FieldKind::Float | FieldKind::Number, Value::String(s)) => {
+                let trimmed = s.trim();
+                trimmed.parse::<i64>().ok().map(Value::from)
+                    .or_else(|| trimmed.parse::<f64>().ok().and_then(serde_json::Number::from_f64).map(Value::Number))
+            }
+            (FieldKind::Boolean, Value::String(s)) => match s.trim().to_lowercase().as_str() {
+                "true" => Some(Value::Bool(true)),
+                "false" => Some(Value::Bool(false)),
+                _ => None,
+            },
+            (FieldKind::Boolean, Value::Number(n)) => match n.as_f64() {
+                Some(0.0) => Some(Value::Bool(false)),
+                Some(1.0) => Some(Value::Bool(true)),
+                _ => None,
+            },
+            (FieldKind::Array | FieldKind::Object, Value::String(s)) => {
+                serde_json::from_str::<Value>(s).ok().filter(|parsed| self.accepts(parsed))
+            }
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            FieldKind::String => "string",
+            FieldKind::Integer => "integer",
+            FieldKind::Float => "float",
+            FieldKind::Number => "number",
+            FieldKind::Boolean => "boolean",
+            FieldKind::Null => "null",
+            FieldKind::Array => "array",
+            FieldKind::Object => "object",
+            FieldKind::Any => "any",
+        }
+    }
+
+    fn accepts(&self, value: &Value) -> bool {
+        match self {
+            FieldKind::String => value.is_string(),
+            FieldKind::Integer => value.is_i64() || value.is_u64(),
+            FieldKind::Float | FieldKind::Number => value.is_number(),
+            FieldKind::Boolean => value.is_boolean(),
+            FieldKind::Null => value.is_null(),
+            FieldKind::Array => value.is_array(),
+            FieldKind::Object => value.is_object(),
+            FieldKind::Any => true,
+        }
+    }
+}
+
+impl FromStr for FieldType {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut name = s.trim();
+        let mut nullable = false;
+        let mut required = false;
+
+        // Markers may appear in either order, e.g. `integer?!` or `integer!?`
+        loop {
+            if let Some(rest) = name.strip_suffix('?') {
+                nullable = true;
+                name = rest;
+            } else if let Some(rest) = name.strip_suffix('!') {
+                required = true;
+                name = rest;
+            } else {
+                break;
+            }
+        }
+
+        let kind = match name.to_lowercase().as_str() {
+            "string" | "str" => FieldKind::String,
+            "integer" | "int" => FieldKind::Integer,
+            "float" => FieldKind::Float,
+            "number" => FieldKind::Number,
+            "boolean" | "bool" => FieldKind::Boolean,
+            "null" => FieldKind::Null,
+            "array" => FieldKind::Array,
+            "object" => FieldKind::Object,
+            "any" => FieldKind::Any,
+            _ => return Err(format!("Unknown type '{}'", s)),
+        };
+
+        Ok(FieldType { kind, nullable, required })
+    }
+}
+
+impl fmt::Display for FieldType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind.name())?;
+        if self.nullable {
+            write!(f, "?")?;
+        }
+        if self.required {
+            write!(f, "!")?;
+        }
+        Ok(())
+    }
+}
+
+impl FieldType {
+    pub fn accepts(&self, value: &Value) -> bool {
+        (value.is_null() && self.nullable) || self.kind.accepts(value)
+    }
+
+    pub fn convert(&self, value: &Value) -> Option<Value> {
+        if value.is_null() && self.nullable {
+            return Some(Value::Null);
+        }
+        self.kind.convert(value)
+    }
+}
+
+pub(crate) fn describe(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_f64() => "float",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+impl Schema {
+    pub fn new(fields: HashMap<String, String>) -> Self {
+        Self { fields, version: initial_version() }
+    }
+
+    pub fn field_types(&self) -> Result<HashMap<String, FieldType>> {
+        let parsed = self.parse_fields()?;
+        Ok(parsed.into_iter().map(|(name, field_type)| (name.clone(), field_type)).collect())
+    }
+
+    // Field types sorted by name so that violations are reported in a stable order
+    fn parse_fields(&self) -> Result<Vec<(&String, FieldType)>> {
+        let mut field_types = Vec::new();
+        for (name, type_str) in &self.fields {
+            let field_type = type_str.parse::<FieldType>()
+                .map_err(|e| PngDbError::SchemaError(format!("{} for field '{}'", e, name)))?;
+            field_types.push((name, field_type));
+        }
+        field_types.sort_by(|a, b| a.0.cmp(b.0));
+        Ok(field_types)
+    }
+
+    pub fn validate(&self, data: &Value) -> Result<()> {
+        if self.fields.is_empty() {
+            return Ok(());
+        }
+
+        let Value::Object(object) = data else {
+            return Err(PngDbError::SchemaError(format!("Row data must be a JSON object, got {}", describe(data))));
+        };
+
+        let mut violations = Vec::new();
+        for (name, field_type) in self.parse_fields()? {
+            match object.get(name) {
+                None if field_type.required => violations.push(Violation::new(name, "is required but missing")),
+                None => {}
+                Some(value) if !field_type.accepts(value) => {
+                    violations.push(Violation::new(name, format!("expects {} but got {} {}", field_type, describe(value), value)));
+                }
+                Some(_) => {}
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(PngDbError::SchemaViolation { row: None, violations })
+        }
+    }
+}
@@ -0,0 +1,334 @@
+use crate::format::Metadata;
+use crate::{DataRow, PngDbError, Result};
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::str::FromStr;
+
+// A region of the pixel grid, for `WITHIN` conditions. Points on the boundary
+// are inside.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    // `RECT(x0, y0, x1, y1)`: opposite corners, in any order
+    Rect { x0: f64, y0: f64, x1: f64, y1: f64 },
+    // `RADIUS(cx, cy, r)`: a circle around (cx, cy)
+    Radius { cx: f64, cy: f64, r: f64 },
+    // `POLYGON(x1, y1, x2, y2, x3, y3, ...)`: vertices in order, implicitly closed
+    Polygon(Vec<(f64, f64)>),
+}
+
+impl Shape {
+    pub fn rect(x0: f64, y0: f64, x1: f64, y1: f64) -> Self {
+        Shape::Rect { x0, y0, x1, y1 }
+    }
+
+    pub fn radius(cx: f64, cy: f64, r: f64) -> Self {
+        Shape::Radius { cx, cy, r }
+    }
+
+    pub fn polygon(points: Vec<(f64, f64)>) -> Self {
+        Shape::Polygon(points)
+    }
+
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        match self {
+            Shape::Rect { x0, y0, x1, y1 } => {
+                x >= x0.min(*x1) && x <= x0.max(*x1) && y >= y0.min(*y1) && y <= y0.max(*y1)
+            }
+            Shape::Radius { cx, cy, r } => (x - cx).hypot(y - cy) <= *r,
+            Shape::Polygon(points) => polygon_contains(points, x, y),
+        }
+    }
+
+    // The smallest rectangle around the shape, as (min x, min y, max x, max y)
+    pub fn bounds(&self) -> (f64, f64, f64, f64) {
+        match self {
+            Shape::Rect { x0, y0, x1, y1 } => (x0.min(*x1), y0.min(*y1), x0.max(*x1), y0.max(*y1)),
+            Shape::Radius { cx, cy, r } => (cx - r, cy - r, cx + r, cy + r),
+            Shape::Polygon(points) => points.iter().fold(
+                (f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+                |(min_x, min_y, max_x, max_y), &(x, y)| (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y)),
+            ),
+        }
+    }
+}
+
+// Even-odd ray casting, after checking the edges themselves
+fn polygon_contains(points: &[(f64, f64)], x: f64, y: f64) -> bool {
+    let edges = || points.iter().zip(points.iter().cycle().skip(1));
+
+    let on_edge = edges().any(|(&(ax, ay), &(bx, by))| {
+        let cross = (bx - ax) * (y - ay) - (by - ay) * (x - ax);
+        cross.abs() <= f64::EPSILON * (1.0 + ax.abs() + bx.abs() + ay.abs() + by.abs())
+            && x >= ax.min(bx) && x <= ax.max(bx) && y >= ay.min(by) && y <= ay.max(by)
+    });
+    if on_edge {
+        return true;
+    }
+
+    let mut inside = false;
+    for (&(ax, ay), &(bx, by)) in edges() {
+        if (ay > y) != (by > y) && x < ax + (y - ay) * (bx - ax) / (by - ay) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Shape::Rect { x0, y0, x1, y1 } => write!(f, "RECT({}, {}, {}, {})", x0, y0, x1, y1),
+            Shape::Radius { cx, cy, r } => write!(f, "RADIUS({}, {}, {})", cx, cy, r),
+            Shape::Polygon(points) => {
+                let coordinates: Vec<String> = points.iter().map(|(x, y)| format!("{}, {}", x, y)).collect();
+                write!(f, "POLYGON({})", coordinates.join(", "))
+            }
+        }
+    }
+}
+
+// Parses the query syntax, e.g. `"RADIUS(10, 10, 5)".parse::<Shape>()`
+impl FromStr for Shape {
+    type Err = PngDbError;
+
+    fn from_str(text: &str) -> std::result::Result<Self, Self::Err> {
+        crate::query::parse_shape(text)
+    }
+}
+
+// A 2-d tree over the cells of a database's rows, for nearest-neighbour and
+// region searches that do not visit every row. Entries are row indices, so the
+// index must be rebuilt when rows are added or removed.
+#[derive(Debug, Clone)]
+pub struct SpatialIndex {
+    // (x, y, row index), arranged so that the middle entry of each range splits
+    // the rest of it: on x at even depths of the tree and on y at odd ones
+    points: Vec<(u32, u32, usize)>,
+    // (min x, min y, max x, max y) over all points
+    extent: Option<(u32, u32, u32, u32)>,
+}
+
+type Bounds = [f64; 4];
+
+impl SpatialIndex {
+    pub fn build(rows: &[DataRow]) -> Self {
+        let mut points: Vec<_> = rows.iter().enumerate().map(|(index, row)| (row.x, row.y, index)).collect();
+        arrange(&mut points, 0);
+        Self::arranged(points)
+    }
+
+    fn arranged(points: Vec<(u32, u32, usize)>) -> Self {
+        let extent = points.iter().fold(None, |extent, &(x, y, _)| match extent {
+            None => Some((x, y, x, y)),
+            Some((min_x, min_y, max_x, max_y)) => Some((min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y))),
+        });
+        Self { points, extent }
+    }
+
+    // The smallest rectangle holding every row's cell, as (min x, min y, max x,
+    // max y); `None` without rows
+    pub fn extent(&self) -> Option<(u32, u32, u32, u32)> {
+        self.extent
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    // Row indices in order of distance from (x, y); rows at the same distance
+    // come in storage order
+    pub fn nearest(&self, x: f64, y: f64) -> NearestRows<'_> {
+        let everywhere = [f64::NEG_INFINITY, f64::NEG_INFINITY, f64::INFINITY, f64::INFINITY];
+        let mut heap = BinaryHeap::new();
+        if !self.points.is_empty() {
+            heap.push(Reverse(Entry::Range { distance: 0.0, start: 0, end: self.points.len(), depth: 0, bounds: everywhere }));
+        }
+        NearestRows { index: self, x, y, heap }
+    }
+
+    // Row indices whose cell lies in the rectangle from (min_x, min_y) to
+    // (max_x, max_y), edges included, in no particular order
+    pub fn within_bounds(&self, min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> Vec<usize> {
+        let mut found = Vec::new();
+        self.collect_within(0, self.points.len(), 0, [min_x, min_y, max_x, max_y], &mut found);
+        found
+    }
+
+    fn collect_within(&self, start: usize, end: usize, depth: usize, bounds: Bounds, found: &mut Vec<usize>) {
+        if start >= end {
+            return;
+        }
+        let mid = start + (end - start) / 2;
+        let (x, y, row) = self.points[mid];
+        let (x, y) = (x as f64, y as f64);
+        if x >= bounds[0] && x <= bounds[2] && y >= bounds[1] && y <= bounds[3] {
+            found.push(row);
+        }
+
+        let (value, low, high) = if depth.is_multiple_of(2) { (x, bounds[0], bounds[2]) } else { (y, bounds[1], bounds[3]) };
+        if low <= value {
+            self.collect_within(start, mid, depth + 1, bounds, found);
+        }
+        if high >= value {
+            self.collect_within(mid + 1, end, depth + 1, bounds, found);
+        }
+    }
+}
+
+// The `spatial_index` chunk. `written_by` and `written_at` repeat the metadata
+// of the save that wrote it, so that a file rewritten by a writer that kept
+// the chunk but did not update it is detected.
+#[derive(Serialize, Deserialize)]
+struct StoredIndex {
+    written_by: String,
+    written_at: u64,
+    // x, y and row index of each entry, flattened
+    points: Vec<u64>,
+}
+
+impl SpatialIndex {
+    pub(crate) fn to_chunk(&self, metadata: &Metadata) -> Result<String> {
+        let stored = StoredIndex {
+            written_by: metadata.modified_by.clone(),
+            written_at: metadata.modified_at,
+            points: self.points.iter().flat_map(|&(x, y, row)| [x as u64, y as u64, row as u64]).collect(),
+        };
+        Ok(serde_json::to_string(&stored)?)
+    }
+
+    // The stored index, if it was written along with the current metadata, no
+    // rows were appended since, and it covers exactly `rows`; `None` means it
+    // has to be rebuilt
+    pub(crate) fn from_chunk(text: &str, metadata: &Metadata, rows: &[DataRow]) -> Option<Self> {
+        let stored: StoredIndex = serde_json::from_str(text).ok()?;
+        if stored.written_by != metadata.modified_by
+            || stored.written_at != metadata.modified_at
+            || stored.points.len() != rows.len() * 3
+            || metadata.has_appended_rows()
+        {
+            return None;
+        }
+
+        let mut seen = vec![false; rows.len()];
+        let mut points = Vec::with_capacity(rows.len());
+        for point in stored.points.chunks(3) {
+            let (x, y, row) = (point[0], point[1], usize::try_from(point[2]).ok()?);
+            let stored_row = rows.get(row)?;
+            if seen[row] || (stored_row.x as u64, stored_row.y as u64) != (x, y) {
+                return None;
+            }
+            seen[row] = true;
+            points.push((stored_row.x, stored_row.y, row));
+        }
+        Some(Self::arranged(points))
+    }
+}
+
+// Recursively moves the median of each range, by the axis of its depth, to
+// the middle
+fn arrange(points: &mut [(u32, u32, usize)], depth: usize) {
+    if points.len() <= 1 {
+        return;
+    }
+    let mid = points.len() / 2;
+    if depth.is_multiple_of(2) {
+        points.select_nth_unstable_by_key(mid, |&(x, y, row)| (x, y, row));
+    } else {
+        points.select_nth_unstable_by_key(mid, |&(x, y, row)| (y, x, row));
+    }
+    let (left, right) = points.split_at_mut(mid);
+    arrange(left, depth + 1);
+    arrange(&mut right[1..], depth + 1);
+}
+
+// Best-first traversal of the tree: a range is expanded before any point at
+// its distance is returned, so points come out in order of distance
+pub struct NearestRows<'a> {
+    index: &'a SpatialIndex,
+    x: f64,
+    y: f64,
+    heap: BinaryHeap<Reverse<Entry>>,
+}
+
+enum Entry {
+    // A range of `points` whose cells all lie within `bounds`, at least
+    // `distance` (squared) away
+    Range { distance: f64, start: usize, end: usize, depth: usize, bounds: Bounds },
+    Point { distance: f64, row: usize },
+}
+
+impl Entry {
+    fn key(&self) -> (f64, u8, usize) {
+        match self {
+            Entry::Range { distance, .. } => (*distance, 0, 0),
+            Entry::Point { distance, row } => (*distance, 1, *row),
+        }
+    }
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (a, b) = (self.key(), other.key());
+        a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+impl NearestRows<'_> {
+    fn distance_to(&self, bounds: &Bounds) -> f64 {
+        let dx = (bounds[0] - self.x).max(self.x - bounds[2]).max(0.0);
+        let dy = (bounds[1] - self.y).max(self.y - bounds[3]).max(0.0);
+        dx * dx + dy * dy
+    }
+}
+
+impl Iterator for NearestRows<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        while let Some(Reverse(entry)) = self.heap.pop() {
+            let (start, end, depth, bounds) = match entry {
+                Entry::Point { row, .. } => return Some(row),
+                Entry::Range { start, end, depth, bounds, .. } => (start, end, depth, bounds),
+            };
+
+            let mid = start + (end - start) / 2;
+            let (x, y, row) = self.index.points[mid];
+            let (x, y) = (x as f64, y as f64);
+            let distance = (x - self.x).powi(2) + (y - self.y).powi(2);
+            self.heap.push(Reverse(Entry::Point { distance, row }));
+
+            let axis = depth % 2;
+            let split = if axis == 0 { x } else { y };
+            let (mut below, mut above) = (bounds, bounds);
+            below[axis + 2] = split;
+            above[axis] = split;
+            for (start, end, bounds) in [(start, mid, below), (mid + 1, end, above)] {
+                if start < end {
+                    let distance = self.distance_to(&bounds);
+                    self.heap.push(Reverse(Entry::Range { distance, start, end, depth: depth + 1, bounds }));
+                }
+            }
+        }
+        None
+    }
+}
@@ -1,6 +1,6 @@
 use wasm_bindgen::prelude::*;
 // Console logging is handled via the log macro defined below
-use crate::{PngDatabase, Schema, DataRow};
+use crate::{parse_field_path, CellPolicy, Operand, PngDatabase, PreparedQuery, Query, Schema, Select, Shape};
 use serde_json::Value;
 use std::collections::HashMap;
 
@@ -22,7 +22,9 @@ impl WebPngDatabase {
         let schema_map: HashMap<String, String> = serde_json::from_str(schema_json)
             .map_err(|e| JsValue::from_str(&format!("Invalid schema JSON: {}", e)))?;
 
-        let schema = Schema { fields: schema_map };
+        let schema = Schema::new(schema_map);
+        schema.field_types()
+            .map_err(|e| JsValue::from_str(&format!("Invalid schema: {}", e)))?;
         let db = PngDatabase::new(width, height, schema);
 
         Ok(WebPngDatabase { db })
@@ -30,53 +32,8 @@ impl WebPngDatabase {
 
     #[wasm_bindgen]
     pub fn from_png_bytes(png_bytes: &[u8]) -> Result<WebPngDatabase, JsValue> {
-        // Create a temporary file-like reader from bytes
-        let cursor = std::io::Cursor::new(png_bytes);
-        let decoder = png::Decoder::new(cursor);
-        let reader = decoder.read_info()
-            .map_err(|e| JsValue::from_str(&format!("PNG decode error: {}", e)))?;
-
-        let info = reader.info();
-        let width = info.width;
-        let height = info.height;
-
-        let mut schema = Schema { fields: HashMap::new() };
-        let mut rows = Vec::new();
-
-        // Read zTXt chunks
-        for chunk in reader.info().compressed_latin1_text.iter() {
-            if chunk.keyword == "schema" {
-                let decompressed = chunk.get_text()
-                    .map_err(|e| JsValue::from_str(&format!("Schema decompression error: {}", e)))?;
-                schema = serde_json::from_str(&decompressed)
-                    .map_err(|e| JsValue::from_str(&format!("Schema parse error: {}", e)))?;
-            } else if chunk.keyword.starts_with("row_") {
-                let decompressed = chunk.get_text()
-                    .map_err(|e| JsValue::from_str(&format!("Row decompression error: {}", e)))?;
-                let row_data: Value = serde_json::from_str(&decompressed)
-                    .map_err(|e| JsValue::from_str(&format!("Row parse error: {}", e)))?;
-
-                // Extract coordinates from keyword (row_x_y format)
-                let coords: Vec<&str> = chunk.keyword.split('_').collect();
-                if coords.len() == 3 {
-                    let x = coords[1].parse::<u32>().unwrap_or(0);
-                    let y = coords[2].parse::<u32>().unwrap_or(0);
-
-                    rows.push(DataRow {
-                        x,
-                        y,
-                        data: row_data,
-                    });
-                }
-            }
-        }
-
-        let db = PngDatabase {
-            width,
-            height,
-            schema,
-            rows,
-        };
+        let db = PngDatabase::from_reader(png_bytes)
+            .map_err(|e| JsValue::from_str(&format!("Load error: {}", e)))?;
 
         Ok(WebPngDatabase { db })
     }
@@ -93,19 +50,135 @@ impl WebPngDatabase {
     }
 
     #[wasm_bindgen]
-    pub fn query(&self, where_clause: &str) -> Result<String, JsValue> {
-        let results = self.db.query(where_clause)
+    pub fn update(&mut self, where_clause: &str, patch_json: &str) -> Result<usize, JsValue> {
+        let patch: Value = serde_json::from_str(patch_json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid JSON patch: {}", e)))?;
+
+        self.db.update(where_clause, patch)
+            .map_err(|e| JsValue::from_str(&format!("Update error: {}", e)))
+    }
+
+    #[wasm_bindgen]
+    pub fn delete(&mut self, where_clause: &str) -> Result<usize, JsValue> {
+        self.db.delete(where_clause)
+            .map_err(|e| JsValue::from_str(&format!("Delete error: {}", e)))
+    }
+
+    #[wasm_bindgen]
+    pub fn query(&self, query: &str) -> Result<String, JsValue> {
+        let results = self.db.query(query)
+            .map_err(|e| JsValue::from_str(&format!("Query error: {}", e)))?;
+
+        serde_json::to_string(&results)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+
+    // The access path a query would use, with or without a leading EXPLAIN, e.g.
+    // `{"plan": "field index on department", "estimated_rows": 12, ...}`
+    #[wasm_bindgen]
+    pub fn explain(&self, query: &str) -> Result<String, JsValue> {
+        let plan = self.db.explain(query)
+            .map_err(|e| JsValue::from_str(&format!("Query error: {}", e)))?;
+        let considered: Vec<_> = plan.considered.iter().map(|(access, estimated_rows)| {
+            serde_json::json!({
+                "plan": access.to_string(),
+                "estimated_rows": estimated_rows
+            })
+        }).collect();
+
+        serde_json::to_string(&serde_json::json!({
+            "plan": plan.access.to_string(),
+            "estimated_rows": plan.estimated_rows,
+            "total_rows": plan.total_rows,
+            "considered": considered
+        }))
+        .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+
+    #[wasm_bindgen]
+    pub fn query_with(&self, query: &WebQuery) -> Result<String, JsValue> {
+        let results = self.db.select(&Select::new().filter(query.query.clone()))
             .map_err(|e| JsValue::from_str(&format!("Query error: {}", e)))?;
 
-        let serializable_results: Vec<_> = results.iter().map(|row| {
+        serde_json::to_string(&results)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+
+    #[wasm_bindgen]
+    pub fn update_with(&mut self, query: &WebQuery, patch_json: &str) -> Result<usize, JsValue> {
+        let patch: Value = serde_json::from_str(patch_json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid JSON patch: {}", e)))?;
+
+        self.db.update_matching(&query.query, patch)
+            .map_err(|e| JsValue::from_str(&format!("Update error: {}", e)))
+    }
+
+    #[wasm_bindgen]
+    pub fn delete_with(&mut self, query: &WebQuery) -> Result<usize, JsValue> {
+        self.db.delete_matching(&query.query)
+            .map_err(|e| JsValue::from_str(&format!("Delete error: {}", e)))
+    }
+
+    // `params` is a JS array for `?` placeholders or an object for `:name` ones
+    #[wasm_bindgen]
+    pub fn execute(&self, prepared: &WebPreparedQuery, params: JsValue) -> Result<String, JsValue> {
+        let results = match js_to_json(&params)? {
+            Value::Array(values) => self.db.execute(&prepared.prepared, &values),
+            Value::Object(values) => self.db.execute_named(&prepared.prepared, &values),
+            _ => return Err(JsValue::from_str("Parameters must be an array or an object")),
+        }
+        .map_err(|e| JsValue::from_str(&format!("Query error: {}", e)))?;
+
+        serde_json::to_string(&results)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+
+    // The `k` rows closest to (x, y), nearest first
+    #[wasm_bindgen]
+    pub fn nearest(&self, x: f64, y: f64, k: usize) -> Result<String, JsValue> {
+        self.nearest_rows(x, y, k, None)
+    }
+
+    // The `k` rows matching `query` that are closest to (x, y)
+    #[wasm_bindgen]
+    pub fn nearest_with(&self, x: f64, y: f64, k: usize, query: &WebQuery) -> Result<String, JsValue> {
+        self.nearest_rows(x, y, k, Some(&query.query))
+    }
+
+    fn nearest_rows(&self, x: f64, y: f64, k: usize, filter: Option<&Query>) -> Result<String, JsValue> {
+        let rows: Vec<_> = self.db.nearest(x, y, k, filter)
+            .map_err(|e| JsValue::from_str(&format!("Query error: {}", e)))?
+            .into_iter()
+            .map(|row| {
+                serde_json::json!({
+                    "x": row.x,
+                    "y": row.y,
+                    "seq": row.seq,
+                    "distance": (row.x as f64 - x).hypot(row.y as f64 - y),
+                    "data": row.data
+                })
+            })
+            .collect();
+
+        serde_json::to_string(&rows)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+
+    // `shape` uses the query syntax, e.g. `RADIUS(10, 10, 5)`
+    #[wasm_bindgen]
+    pub fn within(&self, shape: &str) -> Result<String, JsValue> {
+        let shape: Shape = shape.parse()
+            .map_err(|e| JsValue::from_str(&format!("Invalid shape: {}", e)))?;
+        let rows: Vec<_> = self.db.within(&shape).into_iter().map(|row| {
             serde_json::json!({
                 "x": row.x,
                 "y": row.y,
+                "seq": row.seq,
                 "data": row.data
             })
         }).collect();
 
-        serde_json::to_string(&serializable_results)
+        serde_json::to_string(&rows)
             .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
     }
 
@@ -115,6 +188,7 @@ impl WebPngDatabase {
             serde_json::json!({
                 "x": row.x,
                 "y": row.y,
+                "seq": row.seq,
                 "data": row.data
             })
         }).collect();
@@ -124,39 +198,10 @@ impl WebPngDatabase {
     }
 
     #[wasm_bindgen]
-    pub fn to_png_bytes(&self) -> Result<Vec<u8>, JsValue> {
+    pub fn to_png_bytes(&mut self) -> Result<Vec<u8>, JsValue> {
         let mut buf = Vec::new();
-        {
-            let mut encoder = png::Encoder::new(&mut buf, self.db.width, self.db.height);
-            encoder.set_color(png::ColorType::Rgb);
-            encoder.set_depth(png::BitDepth::Eight);
-
-            // Add schema as zTXt chunk
-            let schema_json = serde_json::to_string(&self.db.schema)
-                .map_err(|e| JsValue::from_str(&format!("Schema serialization error: {}", e)))?;
-            encoder.add_ztxt_chunk("schema".to_string(), schema_json)
-                .map_err(|e| JsValue::from_str(&format!("Schema chunk error: {}", e)))?;
-
-            // Add each row as a zTXt chunk
-            for row in &self.db.rows {
-                let row_json = serde_json::to_string(&row.data)
-                    .map_err(|e| JsValue::from_str(&format!("Row serialization error: {}", e)))?;
-                let keyword = format!("row_{}_{}", row.x, row.y);
-                encoder.add_ztxt_chunk(keyword, row_json)
-                    .map_err(|e| JsValue::from_str(&format!("Row chunk error: {}", e)))?;
-            }
-
-            let mut writer = encoder.write_header()
-                .map_err(|e| JsValue::from_str(&format!("PNG header error: {}", e)))?;
-
-            // Create a simple RGB image with black pixels
-            let image_data = vec![0u8; (self.db.width * self.db.height * 3) as usize];
-            writer.write_image_data(&image_data)
-                .map_err(|e| JsValue::from_str(&format!("PNG data error: {}", e)))?;
-            writer.finish()
-                .map_err(|e| JsValue::from_str(&format!("PNG finish error: {}", e)))?;
-        }
-
+        self.db.to_writer(&mut buf)
+            .map_err(|e| JsValue::from_str(&format!("PNG encode error: {}", e)))?;
         Ok(buf)
     }
 
@@ -166,6 +211,57 @@ impl WebPngDatabase {
             .map_err(|e| JsValue::from_str(&format!("Schema serialization error: {}", e)))
     }
 
+    #[wasm_bindgen]
+    pub fn get_cell_policy(&self) -> String {
+        self.db.cell_policy.to_string()
+    }
+
+    #[wasm_bindgen]
+    pub fn set_cell_policy(&mut self, policy: &str) -> Result<(), JsValue> {
+        self.db.cell_policy = policy.parse::<CellPolicy>()
+            .map_err(|e| JsValue::from_str(&e))?;
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn get_persist_spatial_index(&self) -> bool {
+        self.db.persist_spatial_index
+    }
+
+    // Whether `to_png_bytes` includes the spatial index
+    #[wasm_bindgen]
+    pub fn set_persist_spatial_index(&mut self, persist: bool) {
+        self.db.persist_spatial_index = persist;
+    }
+
+    // `CREATE INDEX ON field [USING BTREE|HASH]` or `DROP INDEX ON field`
+    #[wasm_bindgen]
+    pub fn execute_index_statement(&mut self, statement: &str) -> Result<(), JsValue> {
+        self.db.execute_index_statement(statement)
+            .map_err(|e| JsValue::from_str(&format!("Index error: {}", e)))
+    }
+
+    #[wasm_bindgen]
+    pub fn get_indexes(&self) -> Result<String, JsValue> {
+        let indexes: Vec<_> = self.db.indexes().iter().map(|index| {
+            serde_json::json!({
+                "field": index.field().to_string(),
+                "kind": index.kind(),
+                "rows": index.len(),
+                "distinct": index.distinct()
+            })
+        }).collect();
+
+        serde_json::to_string(&indexes)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+
+    #[wasm_bindgen]
+    pub fn get_metadata(&self) -> Result<String, JsValue> {
+        serde_json::to_string(&self.db.metadata)
+            .map_err(|e| JsValue::from_str(&format!("Metadata serialization error: {}", e)))
+    }
+
     #[wasm_bindgen]
     pub fn get_dimensions(&self) -> Vec<u32> {
         vec![self.db.width, self.db.height]
@@ -176,3 +272,212 @@ impl WebPngDatabase {
         self.db.rows.len()
     }
 }
+
+// Fluent query builder for JS: `WebQuery.field("age").gte(30).and(WebQuery.field("name").eq("Ada"))`
+#[wasm_bindgen]
+pub struct WebQuery {
+    query: Query,
+}
+
+#[wasm_bindgen]
+impl WebQuery {
+    // `path` may be nested, e.g. `address.city` or `tags[0]`
+    #[wasm_bindgen]
+    pub fn field(path: &str) -> Result<WebField, JsValue> {
+        let path = parse_field_path(path)
+            .map_err(|e| JsValue::from_str(&format!("Invalid field path: {}", e)))?;
+        Ok(WebField { operand: Query::field(path) })
+    }
+
+    // Each element of an array field, e.g. `WebQuery.any("tags").eq("rust")`
+    #[wasm_bindgen]
+    pub fn any(path: &str) -> Result<WebField, JsValue> {
+        let path = parse_field_path(path)
+            .map_err(|e| JsValue::from_str(&format!("Invalid field path: {}", e)))?;
+        Ok(WebField { operand: Query::any(path) })
+    }
+
+    // The length of an array, object or string field
+    #[wasm_bindgen]
+    pub fn length(path: &str) -> Result<WebField, JsValue> {
+        let path = parse_field_path(path)
+            .map_err(|e| JsValue::from_str(&format!("Invalid field path: {}", e)))?;
+        Ok(WebField { operand: Query::length(path) })
+    }
+
+    #[wasm_bindgen(js_name = withinRect)]
+    pub fn within_rect(x0: f64, y0: f64, x1: f64, y1: f64) -> WebQuery {
+        WebQuery { query: Query::within(Shape::rect(x0, y0, x1, y1)) }
+    }
+
+    #[wasm_bindgen(js_name = withinRadius)]
+    pub fn within_radius(cx: f64, cy: f64, r: f64) -> WebQuery {
+        WebQuery { query: Query::within(Shape::radius(cx, cy, r)) }
+    }
+
+    // `coordinates` is a flat array of vertices: `[x1, y1, x2, y2, x3, y3, ...]`
+    #[wasm_bindgen(js_name = withinPolygon)]
+    pub fn within_polygon(coordinates: Vec<f64>) -> Result<WebQuery, JsValue> {
+        if coordinates.len() < 6 || !coordinates.len().is_multiple_of(2) {
+            return Err(JsValue::from_str("withinPolygon expects at least three x, y pairs"));
+        }
+        let points = coordinates.chunks(2).map(|point| (point[0], point[1])).collect();
+        Ok(WebQuery { query: Query::within(Shape::polygon(points)) })
+    }
+
+    #[wasm_bindgen]
+    pub fn and(&self, other: &WebQuery) -> WebQuery {
+        WebQuery { query: self.query.clone().and(other.query.clone()) }
+    }
+
+    #[wasm_bindgen]
+    pub fn or(&self, other: &WebQuery) -> WebQuery {
+        WebQuery { query: self.query.clone().or(other.query.clone()) }
+    }
+
+    #[wasm_bindgen]
+    pub fn not(&self) -> WebQuery {
+        WebQuery { query: !self.query.clone() }
+    }
+
+    #[wasm_bindgen(js_name = toString)]
+    pub fn to_where_clause(&self) -> String {
+        self.query.to_where_clause()
+    }
+}
+
+// A query parsed once and run with `WebPngDatabase.execute` for each set of values
+#[wasm_bindgen]
+pub struct WebPreparedQuery {
+    prepared: PreparedQuery,
+}
+
+#[wasm_bindgen]
+impl WebPreparedQuery {
+    #[wasm_bindgen(constructor)]
+    pub fn new(query: &str) -> Result<WebPreparedQuery, JsValue> {
+        let prepared = PreparedQuery::new(query)
+            .map_err(|e| JsValue::from_str(&format!("Query error: {}", e)))?;
+        Ok(WebPreparedQuery { prepared })
+    }
+
+    #[wasm_bindgen(js_name = toString)]
+    pub fn to_query_string(&self) -> String {
+        self.prepared.to_string()
+    }
+}
+
+#[wasm_bindgen]
+pub struct WebField {
+    operand: Operand,
+}
+
+#[wasm_bindgen]
+impl WebField {
+    #[wasm_bindgen]
+    pub fn eq(&self, value: JsValue) -> Result<WebQuery, JsValue> {
+        Ok(WebQuery { query: self.operand.clone().eq(js_to_json(&value)?) })
+    }
+
+    #[wasm_bindgen]
+    pub fn ne(&self, value: JsValue) -> Result<WebQuery, JsValue> {
+        Ok(WebQuery { query: self.operand.clone().ne(js_to_json(&value)?) })
+    }
+
+    #[wasm_bindgen]
+    pub fn gt(&self, value: JsValue) -> Result<WebQuery, JsValue> {
+        Ok(WebQuery { query: self.operand.clone().gt(js_to_json(&value)?) })
+    }
+
+    #[wasm_bindgen]
+    pub fn lt(&self, value: JsValue) -> Result<WebQuery, JsValue> {
+        Ok(WebQuery { query: self.operand.clone().lt(js_to_json(&value)?) })
+    }
+
+    #[wasm_bindgen]
+    pub fn gte(&self, value: JsValue) -> Result<WebQuery, JsValue> {
+        Ok(WebQuery { query: self.operand.clone().gte(js_to_json(&value)?) })
+    }
+
+    #[wasm_bindgen]
+    pub fn lte(&self, value: JsValue) -> Result<WebQuery, JsValue> {
+        Ok(WebQuery { query: self.operand.clone().lte(js_to_json(&value)?) })
+    }
+
+    #[wasm_bindgen]
+    pub fn like(&self, pattern: &str) -> WebQuery {
+        WebQuery { query: self.operand.clone().like(pattern) }
+    }
+
+    #[wasm_bindgen]
+    pub fn ilike(&self, pattern: &str) -> WebQuery {
+        WebQuery { query: self.operand.clone().ilike(pattern) }
+    }
+
+    #[wasm_bindgen]
+    pub fn matches(&self, regex: &str) -> WebQuery {
+        WebQuery { query: self.operand.clone().matches(regex) }
+    }
+
+    // `values` is a JS array
+    #[wasm_bindgen(js_name = isIn)]
+    pub fn is_in(&self, values: JsValue) -> Result<WebQuery, JsValue> {
+        match js_to_json(&values)? {
+            Value::Array(values) => Ok(WebQuery { query: self.operand.clone().is_in(values) }),
+            _ => Err(JsValue::from_str("isIn expects an array of values")),
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn between(&self, low: JsValue, high: JsValue) -> Result<WebQuery, JsValue> {
+        Ok(WebQuery { query: self.operand.clone().between(js_to_json(&low)?, js_to_json(&high)?) })
+    }
+
+    #[wasm_bindgen(js_name = isNull)]
+    pub fn is_null(&self) -> WebQuery {
+        WebQuery { query: self.operand.clone().is_null() }
+    }
+
+    #[wasm_bindgen(js_name = isNotNull)]
+    pub fn is_not_null(&self) -> WebQuery {
+        WebQuery { query: self.operand.clone().is_not_null() }
+    }
+
+    #[wasm_bindgen(js_name = isMissing)]
+    pub fn is_missing(&self) -> WebQuery {
+        WebQuery { query: self.operand.clone().is_missing() }
+    }
+
+    #[wasm_bindgen(js_name = isNotMissing)]
+    pub fn is_not_missing(&self) -> WebQuery {
+        WebQuery { query: self.operand.clone().is_not_missing() }
+    }
+
+    #[wasm_bindgen]
+    pub fn contains(&self, value: JsValue) -> Result<WebQuery, JsValue> {
+        Ok(WebQuery { query: self.operand.clone().contains(js_to_json(&value)?) })
+    }
+
+    #[wasm_bindgen(js_name = hasKey)]
+    pub fn has_key(&self, key: &str) -> WebQuery {
+        WebQuery { query: self.operand.clone().has_key(key) }
+    }
+
+    #[wasm_bindgen(js_name = containsJson)]
+    pub fn contains_json(&self, value: JsValue) -> Result<WebQuery, JsValue> {
+        Ok(WebQuery { query: self.operand.clone().contains_json(js_to_json(&value)?) })
+    }
+
+    #[wasm_bindgen(js_name = containedInJson)]
+    pub fn contained_in_json(&self, value: JsValue) -> Result<WebQuery, JsValue> {
+        Ok(WebQuery { query: self.operand.clone().contained_in_json(js_to_json(&value)?) })
+    }
+}
+
+fn js_to_json(value: &JsValue) -> Result<Value, JsValue> {
+    let text = js_sys::JSON::stringify(value)?
+        .as_string()
+        .ok_or_else(|| JsValue::from_str("Value cannot be converted to JSON"))?;
+    serde_json::from_str(&text)
+        .map_err(|e| JsValue::from_str(&format!("Invalid value: {}", e)))
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use common::{column, schema};
+use png_db::{PngDatabase, PngDbError};
+use serde_json::{json, Value};
+
+fn staff() -> PngDatabase {
+    let mut db = PngDatabase::new(16, 16, schema(&[]));
+    db.insert(0, 0, json!({"name": "Ada", "department": "Engineering", "age": 36})).unwrap();
+    db.insert(1, 0, json!({"name": "Grace", "department": "Engineering", "age": 45})).unwrap();
+    db.insert(2, 1, json!({"name": "Linus", "department": "Engineering", "age": null})).unwrap();
+    db.insert(3, 1, json!({"name": "Joan", "department": "Sales", "age": 52})).unwrap();
+    db.insert(4, 1, json!({"name": "Ken"})).unwrap();
+    db
+}
+
+#[test]
+fn aggregates_per_group() {
+    let db = staff();
+    let results = db.query(
+        "SELECT department, COUNT(*), COUNT(age), SUM(age), AVG(age) AS avg_age, MIN(name), MAX(age) GROUP BY department ORDER BY department",
+    ).unwrap();
+    assert_eq!(results.columns, ["department", "COUNT(*)", "COUNT(age)", "SUM(age)", "avg_age", "MIN(name)", "MAX(age)"]);
+    assert_eq!(results.rows, [
+        // Rows without the grouped column form their own group
+        vec![Value::Null, json!(1), json!(0), Value::Null, Value::Null, json!("Ken"), Value::Null],
+        vec![json!("Engineering"), json!(3), json!(2), json!(81), json!(40.5), json!("Ada"), json!(45)],
+        vec![json!("Sales"), json!(1), json!(1), json!(52), json!(52.0), json!("Joan"), json!(52)],
+    ]);
+}
+
+#[test]
+fn without_group_by_every_matching_row_is_one_group() {
+    let db = staff();
+    let results = db.query("SELECT COUNT(*), SUM(age), MIN(age), MAX(name) WHERE x > 0").unwrap();
+    assert_eq!(results.rows, [vec![json!(4), json!(97), json!(45), json!("Linus")]]);
+
+    let results = db.query("SELECT COUNT(*), SUM(age), AVG(age) WHERE x > 100").unwrap();
+    assert_eq!(results.rows, [vec![json!(0), Value::Null, Value::Null]]);
+}
+
+#[test]
+fn having_filters_groups() {
+    let db = staff();
+    assert_eq!(column(&db, "SELECT department, COUNT(*) GROUP BY department HAVING COUNT(*) > 1"), [json!("Engineering")]);
+    // Ken's group has no department at all, so it is MISSING rather than NULL, as in WHERE
+    assert_eq!(
+        column(&db, "SELECT department, AVG(age) AS avg_age GROUP BY department HAVING avg_age >= 45 OR department IS MISSING ORDER BY avg_age DESC"),
+        [json!("Sales"), Value::Null],
+    );
+    assert_eq!(column(&db, "SELECT y, COUNT(*) AS n GROUP BY y HAVING y = 1 AND n = 3"), [json!(1)]);
+    assert_eq!(column(&db, "SELECT x, COUNT(*) GROUP BY x HAVING NOT x < 3 ORDER BY x DESC"), [json!(4), json!(3)]);
+}
+
+#[test]
+fn grouping_errors() {
+    let db = staff();
+    assert!(matches!(db.query("SELECT name, COUNT(*) GROUP BY department"), Err(PngDbError::QueryError(_))));
+    assert!(matches!(db.query("SELECT SUM(name)"), Err(PngDbError::QueryError(_))));
+    assert!(matches!(db.query("SELECT COUNT(* GROUP BY department"), Err(PngDbError::ParseError { .. })));
+    assert!(matches!(db.query("SELECT MEDIAN(age)"), Err(PngDbError::ParseError { .. })));
+}
@@ -0,0 +1,212 @@
+mod common;
+
+use common::{column, encode, png_with_chunks, schema, team, text_chunks, TempPng};
+use png_db::{parse_field_path, Access, CellPolicy, PngDatabase, PngDbError};
+use serde_json::json;
+use std::io::Cursor;
+
+// Keywords of the text chunks ahead of the image data
+fn leading_keywords(bytes: &[u8]) -> Vec<String> {
+    text_chunks(bytes).into_iter().map(|(keyword, _)| keyword).collect()
+}
+
+#[test]
+fn appends_without_rewriting_the_file() {
+    let saved = encode(&mut team(CellPolicy::Unique));
+    let mut db = PngDatabase::from_reader(saved.as_slice()).unwrap();
+    let mut stream = Cursor::new(saved.clone());
+    db.append_to(&mut stream, 3, 3, json!({"name": "Linus"})).unwrap();
+    db.append_to(&mut stream, 4, 4, json!({"name": "Joan"})).unwrap();
+    let appended = stream.into_inner();
+
+    // Only the metadata chunk, padded to some 530 bytes, changed in place; the
+    // rest is untouched and the rows follow it, before a new IEND
+    let metadata_end = saved.windows(5).position(|w| w == b"pngdb").unwrap() + 600;
+    assert_eq!(appended[metadata_end..saved.len() - 12], saved[metadata_end..saved.len() - 12]);
+    assert!(appended.len() < saved.len() + 200);
+    assert!(appended.ends_with(&saved[saved.len() - 12..]));
+    assert!(!leading_keywords(&appended).contains(&"row_3_3".to_string()));
+
+    let mut loaded = PngDatabase::from_reader(appended.as_slice()).unwrap();
+    assert_eq!(column(&loaded, "SELECT name"), [json!("Ada"), json!("Grace"), json!("Linus"), json!("Joan")]);
+    assert!(loaded.metadata.features.contains(&"appended_rows".to_string()));
+    assert_eq!(loaded.metadata, db.metadata);
+
+    // Compacting moves the rows ahead of the image data
+    let compacted = encode(&mut loaded);
+    assert!(leading_keywords(&compacted).contains(&"row_4_4".to_string()));
+    let loaded = PngDatabase::from_reader(compacted.as_slice()).unwrap();
+    assert_eq!(loaded.rows.len(), 4);
+    assert!(!loaded.metadata.features.contains(&"appended_rows".to_string()));
+}
+
+#[test]
+fn appended_rows_follow_the_cell_policy() {
+    let saved = encode(&mut team(CellPolicy::Unique));
+    let mut db = PngDatabase::from_reader(saved.as_slice()).unwrap();
+    let mut stream = Cursor::new(saved.clone());
+    assert!(matches!(db.append_to(&mut stream, 1, 1, json!({"name": "Ken"})), Err(PngDbError::CellOccupied { x: 1, y: 1 })));
+    assert!(db.append_to(&mut stream, 5, 5, json!({"name": 7})).is_err());
+    assert_eq!(stream.into_inner(), saved);
+
+    let saved = encode(&mut team(CellPolicy::Upsert));
+    let mut db = PngDatabase::from_reader(saved.as_slice()).unwrap();
+    let mut stream = Cursor::new(saved);
+    db.append_to(&mut stream, 1, 1, json!({"name": "Ken"})).unwrap();
+    let loaded = PngDatabase::from_reader(stream.get_ref().as_slice()).unwrap();
+    assert_eq!(column(&loaded, "SELECT name"), [json!("Ken"), json!("Grace")]);
+
+    let saved = encode(&mut team(CellPolicy::Multi));
+    let mut db = PngDatabase::from_reader(saved.as_slice()).unwrap();
+    let mut stream = Cursor::new(saved);
+    db.append_to(&mut stream, 1, 1, json!({"name": "Ken"})).unwrap();
+    db.append_to(&mut stream, 1, 1, json!({"name": "Barbara"})).unwrap();
+    let loaded = PngDatabase::from_reader(stream.get_ref().as_slice()).unwrap();
+    assert_eq!(column(&loaded, "SELECT name WHERE x = 1"), [json!("Ada"), json!("Ken"), json!("Barbara")]);
+    assert_eq!(loaded.rows.iter().map(|row| row.seq).collect::<Vec<_>>(), [0, 0, 1, 2]);
+    assert!(loaded.metadata.features.contains(&"row_seq".to_string()));
+}
+
+#[test]
+fn stored_indexes_are_rebuilt_after_an_append() {
+    let mut db = team(CellPolicy::Unique);
+    db.persist_spatial_index = true;
+    db.execute_index_statement("CREATE INDEX ON name").unwrap();
+    let saved = encode(&mut db);
+
+    let mut db = PngDatabase::from_reader(saved.as_slice()).unwrap();
+    let mut stream = Cursor::new(saved);
+    db.append_to(&mut stream, 40, 40, json!({"name": "Linus"})).unwrap();
+    let loaded = PngDatabase::from_reader(stream.get_ref().as_slice()).unwrap();
+    assert_eq!(loaded.indexes()[0].len(), 3);
+    assert_eq!(column(&loaded, "SELECT name WHERE name = 'Linus'"), [json!("Linus")]);
+    assert_eq!(column(&loaded, "SELECT name NEAREST 1 TO (50, 50)"), [json!("Linus")]);
+}
+
+#[test]
+fn upserts_do_not_leave_stored_indexes_stale() {
+    let mut db = PngDatabase::new(64, 64, schema(&[]));
+    db.cell_policy = CellPolicy::Upsert;
+    db.persist_spatial_index = true;
+    for x in 0..40 {
+        db.insert(x, 0, json!({"team": if x % 4 == 0 { "a" } else { "z" }})).unwrap();
+    }
+    db.execute_index_statement("CREATE INDEX ON team").unwrap();
+    let saved = encode(&mut db);
+
+    // The first row of the group the index keys it by, changed without the
+    // row count changing, in the same second as the save
+    let mut loaded = PngDatabase::from_reader(saved.as_slice()).unwrap();
+    let mut stream = Cursor::new(saved);
+    loaded.append_to(&mut stream, 0, 0, json!({"team": "b"})).unwrap();
+    PngDatabase::append_row(&mut stream, 4, 0, json!({"team": "b"})).unwrap();
+
+    let reloaded = PngDatabase::from_reader(stream.get_ref().as_slice()).unwrap();
+    assert_eq!(reloaded.rows.len(), 40);
+    assert_eq!(reloaded.explain("SELECT x WHERE team = 'b'").unwrap().access, Access::FieldIndex { fields: vec![parse_field_path("team").unwrap()] });
+    assert_eq!(column(&reloaded, "SELECT x WHERE team = 'b'"), [json!(0), json!(4)]);
+    assert_eq!(column(&reloaded, "SELECT x WHERE team = 'a'"), (2..10).map(|x| json!(x * 4)).collect::<Vec<_>>());
+    assert_eq!(column(&reloaded, "SELECT x WHERE WITHIN RECT(0, 0, 4, 0) AND team = 'b'"), [json!(0), json!(4)]);
+}
+
+#[test]
+fn refuses_files_that_changed_or_have_no_room() {
+    let saved = encode(&mut team(CellPolicy::Unique));
+    let mut stale = PngDatabase::from_reader(saved.as_slice()).unwrap();
+    let mut current = PngDatabase::from_reader(saved.as_slice()).unwrap();
+    let mut stream = Cursor::new(saved);
+    current.append_to(&mut stream, 3, 3, json!({"name": "Linus"})).unwrap();
+    assert!(matches!(stale.append_to(&mut stream, 4, 4, json!({"name": "Joan"})), Err(PngDbError::DatabaseError(_))));
+    assert_eq!(stale.rows.len(), 2);
+
+    // A file whose metadata chunk was written by another tool is saved in full
+    // once, after which appends go to its end
+    let file = TempPng::new("append-no-room");
+    let chunks: Vec<(String, String)> = text_chunks(&encode(&mut team(CellPolicy::Unique))).into_iter()
+        .map(|(keyword, text)| (keyword, text.trim_end().to_string()))
+        .collect();
+    std::fs::write(file.path(), png_with_chunks(64, 64, &chunks)).unwrap();
+
+    let mut db = PngDatabase::load_from_png(file.path()).unwrap();
+    let mut opened = std::fs::File::options().read(true).write(true).open(file.path()).unwrap();
+    assert!(matches!(db.append_to(&mut opened, 3, 3, json!({"name": "Linus"})), Err(PngDbError::DatabaseError(_))));
+    drop(opened);
+    db.append_to_png(file.path(), 3, 3, json!({"name": "Linus"})).unwrap();
+    let len = std::fs::metadata(file.path()).unwrap().len();
+    db.append_to_png(file.path(), 4, 4, json!({"name": "Joan"})).unwrap();
+    assert!(std::fs::metadata(file.path()).unwrap().len() < len + 200);
+
+    let loaded = PngDatabase::load_from_png(file.path()).unwrap();
+    assert_eq!(column(&loaded, "SELECT name"), [json!("Ada"), json!("Grace"), json!("Linus"), json!("Joan")]);
+}
+
+#[test]
+fn other_changes_are_saved_in_full_rather_than_lost() {
+    let saved = encode(&mut team(CellPolicy::Unique));
+    let mut db = PngDatabase::from_reader(saved.as_slice()).unwrap();
+    assert_eq!(db.delete("WHERE name = 'Ada'").unwrap(), 1);
+    let mut stream = Cursor::new(saved.clone());
+    assert!(matches!(db.append_to(&mut stream, 3, 3, json!({"name": "Linus"})), Err(PngDbError::DatabaseError(_))));
+    assert_eq!(stream.into_inner(), saved);
+    assert_eq!(db.rows.len(), 1);
+
+    let file = TempPng::new("append-unsaved");
+    std::fs::write(file.path(), &saved).unwrap();
+    db.append_to_png(file.path(), 3, 3, json!({"name": "Linus"})).unwrap();
+    let loaded = PngDatabase::load_from_png(file.path()).unwrap();
+    assert_eq!(column(&loaded, "SELECT name"), [json!("Grace"), json!("Linus")]);
+    assert!(!loaded.metadata.features.contains(&"appended_rows".to_string()));
+
+    // Once saved, appends go to the end of the file again
+    let len = std::fs::metadata(file.path()).unwrap().len();
+    db.append_to_png(file.path(), 4, 4, json!({"name": "Joan"})).unwrap();
+    assert!(std::fs::metadata(file.path()).unwrap().len() < len + 200);
+    assert_eq!(PngDatabase::load_from_png(file.path()).unwrap().rows.len(), 3);
+}
+
+#[test]
+fn appends_follow_a_save() {
+    let mut db = team(CellPolicy::Unique);
+    let mut stream = Cursor::new(encode(&mut db));
+    db.append_to(&mut stream, 3, 3, json!({"name": "Linus"})).unwrap();
+    assert_eq!(PngDatabase::from_reader(stream.get_ref().as_slice()).unwrap().rows.len(), 3);
+
+    let file = TempPng::new("append-created");
+    let mut db = PngDatabase::create_empty_png(64, 64, schema(&[("name", "string")]), file.path()).unwrap();
+    let len = std::fs::metadata(file.path()).unwrap().len();
+    db.append_to_png(file.path(), 1, 1, json!({"name": "Ada"})).unwrap();
+    assert!(std::fs::metadata(file.path()).unwrap().len() < len + 200);
+    assert_eq!(column(&PngDatabase::load_from_png(file.path()).unwrap(), "SELECT name"), [json!("Ada")]);
+}
+
+#[test]
+fn rows_are_appended_without_loading_the_database() {
+    for policy in [CellPolicy::Unique, CellPolicy::Upsert, CellPolicy::Multi] {
+        let mut db = team(policy);
+        let mut stream = Cursor::new(encode(&mut db));
+        PngDatabase::append_row(&mut stream, 1, 1, json!({"name": "Ken"})).or_else(|err| match err {
+            PngDbError::CellOccupied { .. } if policy == CellPolicy::Unique => Ok(()),
+            err => Err(err),
+        }).unwrap();
+        PngDatabase::append_row(&mut stream, 3, 3, json!({"name": "Linus"})).unwrap();
+
+        // The same file an in-memory insert and save would give
+        let _ = db.insert(1, 1, json!({"name": "Ken"}));
+        db.insert(3, 3, json!({"name": "Linus"})).unwrap();
+        let loaded = PngDatabase::from_reader(stream.get_ref().as_slice()).unwrap();
+        let rows = |db: &PngDatabase| db.rows.iter().map(|row| (row.x, row.y, row.seq, row.data.clone())).collect::<Vec<_>>();
+        assert_eq!(rows(&loaded), rows(&db), "{}", policy);
+    }
+
+    let mut stream = Cursor::new(encode(&mut team(CellPolicy::Unique)));
+    let before = stream.get_ref().clone();
+    assert!(matches!(PngDatabase::append_row(&mut stream, 64, 0, json!({"name": "Ken"})), Err(PngDbError::OutOfBounds { .. })));
+    assert!(matches!(PngDatabase::append_row(&mut stream, 5, 5, json!({"name": 7})), Err(PngDbError::SchemaViolation { .. })));
+    assert_eq!(stream.into_inner(), before);
+
+    let file = TempPng::new("append-loadless");
+    PngDatabase::create_empty_png(64, 64, schema(&[("name", "string")]), file.path()).unwrap();
+    PngDatabase::append_row_to_png(file.path(), 2, 2, json!({"name": "Ada"})).unwrap();
+    assert!(matches!(PngDatabase::append_row_to_png(file.path(), 2, 2, json!({"name": "Ada"})), Err(PngDbError::CellOccupied { x: 2, y: 2 })));
+    assert_eq!(column(&PngDatabase::load_from_png(file.path()).unwrap(), "SELECT name"), [json!("Ada")]);
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use common::{column, schema};
+use png_db::{parse_query, PngDatabase, PngDbError, Query};
+use serde_json::{json, Value};
+
+// One row for each combination of a, b and c, with x = 4a + 2b + c
+fn truth_table() -> PngDatabase {
+    let mut db = PngDatabase::new(8, 1, schema(&[]));
+    for x in 0..8u32 {
+        db.insert(x, 0, json!({"a": x >> 2 & 1, "b": x >> 1 & 1, "c": x & 1})).unwrap();
+    }
+    db
+}
+
+type Truth = fn(bool, bool, bool) -> bool;
+
+// The x of each row for which `expected` holds
+fn xs_where(expected: Truth) -> Vec<Value> {
+    (0..8u32).filter(|x| expected(x & 4 != 0, x & 2 != 0, x & 1 != 0)).map(|x| json!(x)).collect()
+}
+
+#[test]
+fn not_binds_tighter_than_and_than_or() {
+    let db = truth_table();
+    let cases: [(&str, Truth); 8] = [
+        ("a = 1 OR b = 1 AND c = 1", |a, b, c| a || (b && c)),
+        ("a = 1 AND b = 1 OR c = 1", |a, b, c| (a && b) || c),
+        ("(a = 1 OR b = 1) AND c = 1", |a, b, c| (a || b) && c),
+        ("NOT a = 1 AND b = 1", |a, b, _| !a && b),
+        ("NOT (a = 1 AND b = 1)", |a, b, _| !(a && b)),
+        ("a = 1 AND NOT b = 1 OR c = 1", |a, b, c| (a && !b) || c),
+        ("NOT NOT a = 1 OR NOT c = 1", |a, _, c| a || !c),
+        ("((a = 1) OR (b = 1 AND (c = 0)))", |a, b, c| a || (b && !c)),
+    ];
+    for (clause, expected) in cases {
+        assert_eq!(column(&db, &format!("SELECT x WHERE {}", clause)), xs_where(expected), "{}", clause);
+    }
+}
+
+#[test]
+fn keywords_are_case_insensitive() {
+    let db = truth_table();
+    let lower = column(&db, "select x where a = 1 or not b = 1 and c = 1");
+    assert_eq!(lower, column(&db, "SELECT x WHERE a = 1 OR NOT b = 1 AND c = 1"));
+    assert_eq!(lower, xs_where(|a, b, c| a || (!b && c)));
+}
+
+#[test]
+fn the_tree_follows_precedence() {
+    let a = || Query::field("a").eq(1);
+    let b = || Query::field("b").eq(2);
+    let c = || Query::field("c").eq(3);
+
+    assert_eq!(parse_query("WHERE a = 1 OR b = 2 AND c = 3").unwrap(), a().or(b().and(c())));
+    assert_eq!(parse_query("WHERE NOT a = 1 AND b = 2").unwrap(), (!a()).and(b()));
+    assert_eq!(parse_query("WHERE a = 1 AND b = 2 AND c = 3").unwrap(), a().and(b()).and(c()));
+    assert!(matches!(parse_query("WHERE (a = 1 OR b = 2) AND c = 3").unwrap(), Query::And(left, _) if matches!(*left, Query::Group(_))));
+}
+
+#[test]
+fn unbalanced_or_incomplete_clauses_are_parse_errors() {
+    for clause in ["WHERE (a = 1", "WHERE a = 1)", "WHERE a = 1 OR", "WHERE NOT", "WHERE a = 1 AND AND b = 2", "WHERE ()"] {
+        assert!(matches!(parse_query(clause), Err(PngDbError::ParseError { .. })), "{}", clause);
+    }
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use common::{column, people, schema};
+use png_db::{parse_query, parse_select, AggregateFunc, FieldPath, PngDatabase, Query, Select};
+use serde_json::{json, Value};
+
+#[test]
+fn builder_matches_parsed_structures() {
+    let built = Query::field("age").gte(30).and(Query::field("name").eq("Ada"));
+    assert_eq!(built, parse_query(r#"WHERE age >= 30 AND name = "Ada""#).unwrap());
+
+    let built = Query::field("x").lt(10).or(!Query::field("active").eq(true));
+    assert_eq!(built, parse_query("WHERE x < 10 OR NOT active = true").unwrap());
+
+    let nested = Query::field(FieldPath::key("address").child("city")).eq("Paris");
+    assert_eq!(nested, parse_query(r#"WHERE address.city = "Paris""#).unwrap());
+}
+
+#[test]
+fn rendering_keeps_precedence() {
+    let query = Query::field("a").eq(1).or(Query::field("b").eq(2)).and(Query::field("c").eq(3));
+    assert_eq!(query.to_where_clause(), "WHERE (a = 1 OR b = 2) AND c = 3");
+
+    let query = !(Query::field("a").eq(1).and(Query::field("b").eq(2)));
+    assert_eq!(query.to_string(), "NOT (a = 1 AND b = 2)");
+}
+
+#[test]
+fn every_value_renders_back_to_itself() {
+    let values = [
+        Value::Null,
+        json!(true),
+        json!(-2.5),
+        json!("null"),
+        json!(1e300),
+        json!(1e-7),
+        json!("\u{8}\u{c}\u{1f}/\"\\\n😀"),
+        json!(["a", 1, null, [true]]),
+        json!({"k": "v]}", "nested": {"n": [1, 2]}}),
+    ];
+    for value in values {
+        let query = Query::field("a").eq(value.clone());
+        let rendered = query.to_where_clause();
+        assert_eq!(parse_query(&rendered).unwrap(), query, "{}", rendered);
+
+        let listed = Query::field("a").is_in(vec![value.clone(), json!(0)]);
+        assert_eq!(parse_query(&listed.to_where_clause()).unwrap(), listed, "{}", listed);
+    }
+
+    // Coordinates take any value, and only whole numbers use the coordinate index
+    for value in [json!(-1), json!(1.5), json!("5"), json!(1e-7), json!(4294967296u64), json!(5)] {
+        for field in ["x", "y"] {
+            let query = Query::field(field).eq(value.clone());
+            let rendered = query.to_where_clause();
+            assert_eq!(parse_query(&rendered).unwrap(), query, "{}", rendered);
+        }
+    }
+
+    // Fields named like literals are quoted
+    for name in ["true", "false", "null", "NULL"] {
+        let query = Query::field(name).eq(1);
+        let rendered = query.to_where_clause();
+        assert_eq!(rendered, format!("WHERE \"{}\" = 1", name));
+        assert_eq!(parse_query(&rendered).unwrap(), query);
+    }
+
+    let mut db = PngDatabase::new(8, 8, schema(&[]));
+    db.insert(0, 0, json!({"name": "a", "tags": ["x", "y"], "manager": null, "null": 1})).unwrap();
+    db.insert(1, 0, json!({"name": "b", "tags": ["y"], "manager": "a"})).unwrap();
+    let names = |query: Query| column(&db, &format!("SELECT name {}", query.to_where_clause()));
+    assert_eq!(names(Query::field("tags").eq(json!(["x", "y"]))), [json!("a")]);
+    assert_eq!(names(Query::field("manager").eq(Value::Null)), [json!("a")]);
+    assert_eq!(names(Query::field("null").eq(1)), [json!("a")]);
+    assert_eq!(names(Query::field("x").eq(1.0)), [json!("b")]);
+    assert!(names(Query::field("x").eq(-1)).is_empty());
+    assert!(names(Query::field("x").eq("1")).is_empty());
+}
+
+#[test]
+fn user_supplied_strings_survive_rendering() {
+    let db = people();
+    let input = "O\"Brien";
+    let query = Query::field("name").eq(input);
+
+    let rendered = query.to_where_clause();
+    assert_eq!(rendered, r#"WHERE name = "O\"Brien""#);
+    assert_eq!(db.query(&rendered).unwrap().len(), 1);
+    assert_eq!(db.select(&Select::new().filter(query)).unwrap().len(), 1);
+}
+
+#[test]
+fn select_builder_renders_and_runs() {
+    let db = people();
+    let select = Select::new()
+        .column("team")
+        .aggregate_as(AggregateFunc::Avg, Some("age".into()), "avg_age")
+        .group_by("team")
+        .having(Query::aggregate(AggregateFunc::Count, None).gt(1))
+        .order_by_desc("team")
+        .limit(5);
+
+    let text = select.to_string();
+    assert_eq!(text, "SELECT team, AVG(age) AS avg_age GROUP BY team HAVING COUNT(*) > 1 ORDER BY team DESC LIMIT 5");
+    assert_eq!(parse_select(&text).unwrap(), select);
+
+    let results = db.select(&select).unwrap();
+    assert_eq!(results.rows, vec![vec![json!("core"), json!(60.5)]]);
+}
+
+#[test]
+fn built_queries_update_and_delete() {
+    let mut db = people();
+    let core = Query::field("team").eq("core");
+
+    assert_eq!(db.update_matching(&core, json!({"team": "platform"})).unwrap(), 2);
+    assert_eq!(db.delete_matching(&Query::field("team").eq("platform").and(Query::field("age").gt(80))).unwrap(), 1);
+    assert_eq!(db.rows.len(), 2);
+}
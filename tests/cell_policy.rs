@@ -0,0 +1,111 @@
+mod common;
+
+use common::{column, encode, png_with_chunks, team, text_chunks};
+use png_db::{CellPolicy, PngDatabase, PngDbError};
+use serde_json::json;
+
+// A saved `team(policy)` with the given row chunks added after its own, as a
+// writer that does not know the policy might leave it
+fn saved_with_rows(policy: CellPolicy, rows: &[(&str, &str)]) -> Vec<u8> {
+    let mut chunks = text_chunks(&encode(&mut team(policy)));
+    chunks.extend(rows.iter().map(|(keyword, text)| (keyword.to_string(), text.to_string())));
+    png_with_chunks(64, 64, &chunks)
+}
+
+// (x, y, seq, name) of each row, in order
+fn cells(db: &PngDatabase) -> Vec<(u32, u32, u32, String)> {
+    db.rows.iter().map(|row| (row.x, row.y, row.seq, row.data["name"].as_str().unwrap().to_string())).collect()
+}
+
+fn cell(x: u32, y: u32, seq: u32, name: &str) -> (u32, u32, u32, String) {
+    (x, y, seq, name.to_string())
+}
+
+#[test]
+fn unique_cells_refuse_a_second_row() {
+    let mut db = team(CellPolicy::Unique);
+    assert!(matches!(db.insert(1, 1, json!({"name": "Ken"})), Err(PngDbError::CellOccupied { x: 1, y: 1 })));
+    db.insert(1, 2, json!({"name": "Ken"})).unwrap();
+    assert_eq!(cells(&db), [cell(1, 1, 0, "Ada"), cell(2, 2, 0, "Grace"), cell(1, 2, 0, "Ken")]);
+
+    // Freed by a delete, the cell takes a row again
+    db.delete("WHERE name = 'Ada'").unwrap();
+    db.insert(1, 1, json!({"name": "Linus"})).unwrap();
+    assert_eq!(column(&db, "SELECT name WHERE x = 1 AND y = 1"), [json!("Linus")]);
+}
+
+#[test]
+fn upsert_cells_replace_the_row_in_place() {
+    let mut db = team(CellPolicy::Upsert);
+    db.execute_index_statement("CREATE INDEX ON name").unwrap();
+    db.insert(1, 1, json!({"name": "Ken"})).unwrap();
+    db.insert(1, 1, json!({"name": "Joan"})).unwrap();
+    assert_eq!(cells(&db), [cell(1, 1, 0, "Joan"), cell(2, 2, 0, "Grace")]);
+    assert!(column(&db, "SELECT name WHERE name = 'Ada' OR name = 'Ken'").is_empty());
+    assert_eq!(column(&db, "SELECT name WHERE name = 'Joan'"), [json!("Joan")]);
+}
+
+#[test]
+fn multi_cells_number_their_rows() {
+    let mut db = team(CellPolicy::Multi);
+    db.insert(1, 1, json!({"name": "Ken"})).unwrap();
+    db.insert(1, 1, json!({"name": "Joan"})).unwrap();
+    assert_eq!(column(&db, "SELECT name WHERE x = 1 AND y = 1"), [json!("Ada"), json!("Ken"), json!("Joan")]);
+
+    // Numbers are not reused after a delete
+    db.delete("WHERE name = 'Ken'").unwrap();
+    db.insert(1, 1, json!({"name": "Linus"})).unwrap();
+    assert_eq!(cells(&db), [cell(1, 1, 0, "Ada"), cell(2, 2, 0, "Grace"), cell(1, 1, 2, "Joan"), cell(1, 1, 3, "Linus")]);
+
+    // Each numbered row has its own chunk and comes back with its number
+    let bytes = encode(&mut db);
+    let keywords: Vec<String> = text_chunks(&bytes).into_iter().map(|(keyword, _)| keyword).filter(|k| k.starts_with("row_")).collect();
+    assert_eq!(keywords, ["row_1_1", "row_2_2", "row_1_1_2", "row_1_1_3"]);
+    let loaded = PngDatabase::from_reader(bytes.as_slice()).unwrap();
+    assert_eq!(loaded.cell_policy, CellPolicy::Multi);
+    assert_eq!(cells(&loaded), cells(&db));
+}
+
+#[test]
+fn duplicate_chunks_on_load_follow_the_policy() {
+    let duplicates = [("row_1_1", r#"{"name":"Ken"}"#), ("row_1_1_4", r#"{"name":"Joan"}"#), ("row_1_1", r#"{"name":"Linus"}"#)];
+
+    match PngDatabase::from_reader(saved_with_rows(CellPolicy::Unique, &duplicates).as_slice()) {
+        Err(PngDbError::DatabaseError(message)) => assert!(message.contains("(1, 1)"), "{}", message),
+        Err(other) => panic!("unexpected error: {:?}", other),
+        Ok(_) => panic!("duplicate rows loaded into unique cells"),
+    }
+
+    // The last row for a cell wins, wherever it was numbered
+    let db = PngDatabase::from_reader(saved_with_rows(CellPolicy::Upsert, &duplicates).as_slice()).unwrap();
+    assert_eq!(cells(&db), [cell(1, 1, 0, "Linus"), cell(2, 2, 0, "Grace")]);
+
+    // Rows keep their number unless it is taken, in which case they follow the highest
+    let mut db = PngDatabase::from_reader(saved_with_rows(CellPolicy::Multi, &duplicates).as_slice()).unwrap();
+    assert_eq!(cells(&db), [
+        cell(1, 1, 0, "Ada"),
+        cell(2, 2, 0, "Grace"),
+        cell(1, 1, 1, "Ken"),
+        cell(1, 1, 4, "Joan"),
+        cell(1, 1, 5, "Linus"),
+    ]);
+    db.insert(1, 1, json!({"name": "Barbara"})).unwrap();
+    assert_eq!(db.rows[5].seq, 6);
+}
+
+#[test]
+fn files_without_a_config_keep_every_duplicate() {
+    let bytes = png_with_chunks(8, 8, &[
+        ("schema", r#"{"fields":{"name":"string"}}"#.to_string()),
+        ("row_3_3", r#"{"name":"Ada"}"#.to_string()),
+        ("row_3_3", r#"{"name":"Grace"}"#.to_string()),
+        ("row_3_3_1", r#"{"name":"Ken"}"#.to_string()),
+    ]);
+    let mut db = PngDatabase::from_reader(bytes.as_slice()).unwrap();
+    assert_eq!(db.cell_policy, CellPolicy::Multi);
+    assert_eq!(cells(&db), [cell(3, 3, 0, "Ada"), cell(3, 3, 1, "Grace"), cell(3, 3, 2, "Ken")]);
+
+    let loaded = PngDatabase::from_reader(encode(&mut db).as_slice()).unwrap();
+    assert_eq!(loaded.cell_policy, CellPolicy::Multi);
+    assert_eq!(cells(&loaded), cells(&db));
+}
@@ -0,0 +1,86 @@
+// Helpers shared by the integration tests; each test file uses only some of them
+#![allow(dead_code)]
+
+use png_db::{CellPolicy, PngDatabase, Schema};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+// A file in the temp directory, removed when dropped
+pub struct TempPng(PathBuf);
+
+impl TempPng {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("png-db-{}-{}.png", name, std::process::id()));
+        TempPng(path)
+    }
+
+    pub fn path(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+}
+
+impl Drop for TempPng {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+pub fn schema(fields: &[(&str, &str)]) -> Schema {
+    let fields: HashMap<String, String> = fields.iter()
+        .map(|(name, field_type)| (name.to_string(), field_type.to_string()))
+        .collect();
+    Schema::new(fields)
+}
+
+// Three people on two teams, one with a quote in their name
+pub fn people() -> PngDatabase {
+    let mut db = PngDatabase::new(32, 32, schema(&[]));
+    db.insert(1, 1, json!({"name": "Ada", "age": 36, "team": "core"})).unwrap();
+    db.insert(5, 2, json!({"name": "Grace", "age": 85, "team": "core"})).unwrap();
+    db.insert(9, 3, json!({"name": "O\"Brien", "age": 20, "team": "web"})).unwrap();
+    db
+}
+
+// Two named rows, at (1, 1) and (2, 2), under the given cell policy
+pub fn team(policy: CellPolicy) -> PngDatabase {
+    let mut db = PngDatabase::new(64, 64, schema(&[("name", "string")]));
+    db.cell_policy = policy;
+    db.insert(1, 1, json!({"name": "Ada"})).unwrap();
+    db.insert(2, 2, json!({"name": "Grace"})).unwrap();
+    db
+}
+
+// The first column of each row the query returns
+pub fn column(db: &PngDatabase, query: &str) -> Vec<Value> {
+    db.query(query).unwrap().rows.into_iter().map(|mut row| row.remove(0)).collect()
+}
+
+pub fn encode(db: &mut PngDatabase) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    db.to_writer(&mut bytes).unwrap();
+    bytes
+}
+
+// A PNG whose only text chunks are the given (keyword, text) iTXt pairs, as
+// another writer might produce
+pub fn png_with_chunks<K: AsRef<str>>(width: u32, height: u32, chunks: &[(K, String)]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    for (keyword, text) in chunks {
+        encoder.add_itxt_chunk(keyword.as_ref().to_string(), text.clone()).unwrap();
+    }
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&vec![0u8; width as usize * height as usize * 3]).unwrap();
+    writer.finish().unwrap();
+    bytes
+}
+
+// The (keyword, text) iTXt chunks ahead of the image data, which is all a load
+// that stops there sees
+pub fn text_chunks(bytes: &[u8]) -> Vec<(String, String)> {
+    let reader = png::Decoder::new(bytes).read_info().unwrap();
+    reader.info().utf8_text.iter().map(|chunk| (chunk.keyword.clone(), chunk.get_text().unwrap())).collect()
+}
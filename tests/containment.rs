@@ -0,0 +1,78 @@
+mod common;
+
+use common::{column, schema};
+use png_db::{parse_query, PngDatabase, PreparedQuery, Query};
+use serde_json::{json, Value};
+
+fn posts() -> PngDatabase {
+    let mut db = PngDatabase::new(16, 16, schema(&[]));
+    db.insert(1, 0, json!({"title": "Rust tips", "tags": ["rust", "tips"], "meta": {"lang": "en", "stars": 5}})).unwrap();
+    db.insert(2, 0, json!({"title": "Ownership", "tags": ["rust", "memory", 3], "meta": {"lang": "de"}})).unwrap();
+    db.insert(3, 0, json!({"title": "Untagged", "tags": [], "meta": {"lang": "en", "draft": true}})).unwrap();
+    db.insert(4, 0, json!({"title": "No tags field", "meta": null})).unwrap();
+    db
+}
+
+fn xs(db: &PngDatabase, query: &str) -> Vec<Value> {
+    column(db, &format!("SELECT x {}", query))
+}
+
+#[test]
+fn contains_and_any() {
+    let db = posts();
+    assert_eq!(xs(&db, r#"WHERE tags CONTAINS "rust""#), [json!(1), json!(2)]);
+    assert_eq!(xs(&db, "WHERE tags CONTAINS 3"), [json!(2)]);
+    assert_eq!(xs(&db, r#"WHERE title CONTAINS "tag""#), [json!(3), json!(4)]);
+
+    assert_eq!(xs(&db, r#"WHERE ANY(tags) = "memory""#), [json!(2)]);
+    assert_eq!(xs(&db, "WHERE ANY(tags) LIKE 't%'"), [json!(1)]);
+    assert_eq!(xs(&db, r#"WHERE ANY(tags) IN ("tips", "memory")"#), [json!(1), json!(2)]);
+    assert_eq!(xs(&db, r#"WHERE NOT ANY(tags) = "rust""#), [json!(3), json!(4)]);
+}
+
+#[test]
+fn has_key_and_length() {
+    let db = posts();
+    assert_eq!(xs(&db, r#"WHERE meta HAS KEY "stars""#), [json!(1)]);
+    assert_eq!(xs(&db, "WHERE meta HAS KEY draft"), [json!(3)]);
+
+    assert_eq!(xs(&db, "WHERE LENGTH(tags) >= 2"), [json!(1), json!(2)]);
+    assert_eq!(xs(&db, "WHERE LENGTH(tags) = 0"), [json!(3)]);
+    assert_eq!(xs(&db, "WHERE LENGTH(meta) BETWEEN 2 AND 3"), [json!(1), json!(3)]);
+    assert_eq!(xs(&db, "WHERE LENGTH(title) < 10"), [json!(1), json!(2), json!(3)]);
+}
+
+#[test]
+fn jsonb_style_containment() {
+    let db = posts();
+    assert_eq!(xs(&db, r#"WHERE meta @> '{"lang": "en"}'"#), [json!(1), json!(3)]);
+    assert_eq!(xs(&db, r#"WHERE tags @> '["tips", "rust"]'"#), [json!(1)]);
+    assert_eq!(xs(&db, r#"WHERE tags @> '"memory"'"#), [json!(2)]);
+    assert_eq!(xs(&db, r#"WHERE tags <@ '["rust", "tips", "extra"]'"#), [json!(1), json!(3)]);
+
+    assert!(db.query(r#"WHERE meta @> '{"lang": '"#).is_err());
+}
+
+#[test]
+fn equality_across_types_is_false_not_an_error() {
+    let db = posts();
+    assert_eq!(xs(&db, r#"WHERE meta = "en""#), Vec::<Value>::new());
+    assert_eq!(xs(&db, r#"WHERE tags != "rust""#).len(), 3);
+    assert!(db.query(r#"WHERE tags > "rust""#).is_err());
+}
+
+#[test]
+fn builder_and_placeholders() {
+    let query = Query::any("tags").eq("rust")
+        .and(Query::length("tags").gt(1))
+        .and(Query::field("meta").has_key("lang"))
+        .and(Query::field("meta").contains_json(json!({"lang": "en"})));
+    let text = query.to_where_clause();
+    assert_eq!(text, r#"WHERE ANY(tags) = "rust" AND LENGTH(tags) > 1 AND meta HAS KEY "lang" AND meta @> "{\"lang\":\"en\"}""#);
+    assert_eq!(parse_query(&text).unwrap(), query);
+
+    let db = posts();
+    let prepared = PreparedQuery::new("SELECT x WHERE ANY(tags) = ? AND LENGTH(tags) > ?").unwrap();
+    let results = db.execute(&prepared, &[json!("rust"), json!(2)]).unwrap();
+    assert_eq!(results.rows, vec![vec![json!(2)]]);
+}
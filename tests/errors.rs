@@ -0,0 +1,125 @@
+mod common;
+
+use common::schema;
+use png_db::{PngDatabase, PngDbError};
+use serde_json::json;
+
+fn users() -> PngDatabase {
+    PngDatabase::new(10, 10, schema(&[("name", "string!"), ("age", "integer")]))
+}
+
+#[test]
+fn out_of_bounds_reports_coordinates() {
+    let mut db = users();
+    match db.insert(12, 3, json!({"name": "Ada"})) {
+        Err(PngDbError::OutOfBounds { x, y, width, height }) => assert_eq!((x, y, width, height), (12, 3, 10, 10)),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn occupied_cells_report_coordinates() {
+    let mut db = users();
+    db.insert(1, 1, json!({"name": "Ada"})).unwrap();
+    assert!(matches!(db.insert(1, 1, json!({"name": "Bob"})), Err(PngDbError::CellOccupied { x: 1, y: 1 })));
+}
+
+#[test]
+fn schema_violations_name_each_field() {
+    let mut db = users();
+    let Err(PngDbError::SchemaViolation { row, violations }) = db.insert(0, 0, json!({"age": "old"})) else {
+        panic!("expected a schema violation");
+    };
+    assert_eq!(row, None);
+    let fields: Vec<&str> = violations.iter().map(|v| v.field.as_str()).collect();
+    assert_eq!(fields, ["age", "name"]);
+}
+
+#[test]
+fn update_violations_carry_the_row() {
+    let mut db = users();
+    db.insert(4, 5, json!({"name": "Ada", "age": 36})).unwrap();
+    let err = db.update("WHERE name = \"Ada\"", json!({"age": "thirty"})).unwrap_err();
+    match &err {
+        PngDbError::SchemaViolation { row, violations } => {
+            assert_eq!(*row, Some((4, 5)));
+            assert_eq!(violations[0].field, "age");
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+    assert!(err.to_string().starts_with("Schema violation at (4, 5): field 'age' expects integer"), "{}", err);
+}
+
+#[test]
+fn parse_errors_carry_a_position() {
+    let db = users();
+    match db.query("WHERE age > 3 AND name # \"x\"") {
+        Err(PngDbError::ParseError { position, .. }) => assert_eq!(position, 23),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    match db.query("WHERE age >") {
+        Err(PngDbError::ParseError { position, .. }) => assert_eq!(position, 11),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn parse_errors_point_at_the_column() {
+    let db = users();
+    let err = db.query("WHERE age > 3 AND name # \"x\"").unwrap_err();
+    assert_eq!(err.to_string(), [
+        "Parse error at column 24: Unexpected character: #",
+        "  WHERE age > 3 AND name # \"x\"",
+        "                         ^",
+    ].join("\n"));
+
+    let err = db.query("SELECT name\nWHERE age >= \"3\" AND").unwrap_err();
+    assert!(err.to_string().starts_with("Parse error at line 2, column 21: Expected condition\n  WHERE age >= \"3\" AND\n"), "{}", err);
+}
+
+#[test]
+fn operators_inside_strings_are_literal() {
+    let mut db = users();
+    db.insert(2, 2, json!({"name": "a>=b \"quoted\" = c"})).unwrap();
+    let results = db.query(r#"SELECT x WHERE name = "a>=b \"quoted\" = c""#).unwrap();
+    assert_eq!(results.rows, vec![vec![json!(2)]]);
+}
+
+#[test]
+fn misspelled_fields_get_a_suggestion() {
+    let mut db = users();
+    db.insert(1, 1, json!({"name": "Ada", "nickname": "Countess"})).unwrap();
+
+    let err = db.query("WHERE nmae = \"Ada\"").unwrap_err();
+    assert!(matches!(err, PngDbError::ParseError { position: 6, .. }));
+    assert!(err.to_string().contains("Unknown field 'nmae', did you mean 'name'?"), "{}", err);
+
+    let err = db.delete("WHERE Age > 3").unwrap_err();
+    assert!(err.to_string().contains("did you mean 'age'?"), "{}", err);
+    db.add_column("nickname", "string", json!(null)).unwrap();
+    let err = db.query("SELECT nicknam AS nick").unwrap_err();
+    assert!(err.to_string().contains("did you mean 'nickname'?"), "{}", err);
+
+    // Fields unlike any known one, and aliases, are not typos
+    assert!(db.query("WHERE email = \"a@b.c\"").unwrap().is_empty());
+    assert_eq!(db.query("SELECT name AS nam ORDER BY nam").unwrap().len(), 1);
+}
+
+#[test]
+fn absent_fields_are_not_typos() {
+    let mut db = users();
+    db.insert(1, 1, json!({"name": "Ada", "age": 36, "nickname": "Countess"})).unwrap();
+
+    // Short names a letter away from a declared field, and names close to a
+    // field that only rows have, just match nothing
+    for query in ["WHERE z = 1", "WHERE age2 = 1", "WHERE ag = 1", "WHERE nicknames = 'a'"] {
+        assert!(db.query(query).unwrap().is_empty(), "{}", query);
+    }
+    assert!(db.delete("WHERE age2 = 1").is_ok());
+}
+
+#[test]
+fn png_encoding_errors_are_wrapped() {
+    let mut db = PngDatabase::new(0, 0, schema(&[]));
+    assert!(matches!(db.to_writer(Vec::new()), Err(PngDbError::EncodingError(_))));
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use common::{column, schema};
+use png_db::{parse_select, Expr, PngDatabase, PngDbError, Query, Select};
+use serde_json::{json, Value};
+
+fn people() -> PngDatabase {
+    let mut db = PngDatabase::new(100, 100, schema(&[]));
+    db.insert(10, 20, json!({"name": "Alice", "age": 30, "score": 7.25})).unwrap();
+    db.insert(50, 60, json!({"name": "bob", "age": 25, "score": -3})).unwrap();
+    db.insert(5, 5, json!({"name": "Carol", "age": 41})).unwrap();
+    db
+}
+
+#[test]
+fn arithmetic_and_functions_in_where() {
+    let db = people();
+    assert_eq!(column(&db, r#"SELECT name WHERE lower(name) = "alice""#), [json!("Alice")]);
+    assert_eq!(column(&db, "SELECT name WHERE x + y < 100"), [json!("Alice"), json!("Carol")]);
+    assert_eq!(column(&db, "SELECT name WHERE age * 12 > 360"), [json!("Carol")]);
+    assert_eq!(column(&db, "SELECT name WHERE (age - 1) % 10 = 0"), [json!("Carol")]);
+    assert_eq!(column(&db, "SELECT name WHERE abs(score) > 5"), [json!("Alice")]);
+    assert_eq!(column(&db, "SELECT name WHERE distance(x, y, 0, 0) < 10"), [json!("Carol")]);
+    assert_eq!(column(&db, "SELECT name WHERE UPPER(substr(name, 1, 1)) = 'B'"), [json!("bob")]);
+
+    // Arithmetic on a missing field is null, which only IS NULL matches
+    assert_eq!(column(&db, "SELECT name WHERE score * 2 < 100"), [json!("Alice"), json!("bob")]);
+    assert_eq!(column(&db, "SELECT name WHERE score + 1 IS NULL"), [json!("Carol")]);
+    assert_eq!(column(&db, "SELECT name WHERE coalesce(score, 0) = 0"), [json!("Carol")]);
+}
+
+#[test]
+fn expressions_in_select_and_order_by() {
+    let db = people();
+    let result = db.query("SELECT upper(name) AS shout, age / 2, round(score, 1) ORDER BY x + y DESC").unwrap();
+    assert_eq!(result.columns, ["shout", "age / 2", "ROUND(score, 1)"]);
+    assert_eq!(result.rows, [
+        vec![json!("BOB"), json!(12.5), json!(-3.0)],
+        vec![json!("ALICE"), json!(15), json!(7.3)],
+        vec![json!("CAROL"), json!(20.5), Value::Null],
+    ]);
+
+    assert_eq!(column(&db, "SELECT name, length(name) AS len ORDER BY len, name"), [json!("bob"), json!("Alice"), json!("Carol")]);
+    assert_eq!(column(&db, "SELECT age % 10 AS digit GROUP BY age ORDER BY digit"), [json!(0), json!(1), json!(5)]);
+}
+
+#[test]
+fn custom_functions() {
+    let mut db = people();
+    db.register_function("initial", |args: &[Value]| {
+        Ok(args[0].as_str().and_then(|name| name.chars().next()).map(|c| Value::from(c.to_string())).unwrap_or(Value::Null))
+    });
+    assert!(db.functions().contains("INITIAL"));
+    assert_eq!(column(&db, r#"SELECT initial(name) WHERE initial(name) != "b" ORDER BY name DESC"#), [json!("C"), json!("A")]);
+}
+
+#[test]
+fn substr_clamps_its_start() {
+    let db = people();
+    let substr = |args: &str| column(&db, &format!("SELECT substr(name, {}) WHERE name = 'Alice'", args));
+    assert_eq!(substr("2, 3"), [json!("lic")]);
+    assert_eq!(substr("0, 2"), [json!("A")]);
+    assert_eq!(substr("-1, 3"), [json!("A")]);
+    assert_eq!(substr("-9223372036854775808"), [json!("Alice")]);
+    assert_eq!(substr("-9223372036854775808, 9223372036854775807"), [json!("")]);
+    assert_eq!(substr("9223372036854775807"), [json!("")]);
+}
+
+#[test]
+fn expression_errors() {
+    let db = people();
+    let err = db.query("SELECT name WHERE lowr(name) = 'alice'").unwrap_err();
+    assert!(err.to_string().contains("did you mean 'lower'?"), "{}", err);
+
+    assert!(matches!(db.query("SELECT name WHERE name + 1 > 2"), Err(PngDbError::QueryError(_))));
+    assert!(matches!(db.query("SELECT age / 0"), Err(PngDbError::QueryError(_))));
+    assert!(matches!(db.query("SELECT lower(age)"), Err(PngDbError::QueryError(_))));
+    assert!(matches!(db.query("SELECT name WHERE round() = 1"), Err(PngDbError::QueryError(_))));
+
+    // Aggregates and ANY() cannot be used in arithmetic
+    for query in [
+        "SELECT name WHERE ANY(tags) + 1 = 2",
+        "SELECT COUNT(*) GROUP BY age HAVING COUNT(*) + 1 > 1",
+        "SELECT SUM(x) GROUP BY age HAVING SUM(x) - 1 > 0",
+        "SELECT AVG(age) GROUP BY name HAVING AVG(age) / 2 > 0",
+    ] {
+        assert!(matches!(db.query(query), Err(PngDbError::ParseError { .. })), "{}", query);
+    }
+}
+
+#[test]
+fn built_expressions_render_and_parse_back() {
+    let select = Select::new()
+        .expr_as(-(Expr::field("age") + Expr::literal(1)) * Expr::literal(2), "double")
+        .filter(Query::expr(Expr::call("lower", vec![Expr::field("name")])).eq("it's"))
+        .order_by_expr_desc(Expr::field("x") - Expr::field("y") - Expr::literal(3));
+    let text = select.to_string();
+    assert_eq!(text, r#"SELECT -(age + 1) * 2 AS double WHERE LOWER(name) = "it's" ORDER BY x - y - 3 DESC"#);
+    assert_eq!(parse_select(&text).unwrap(), select);
+
+    let nested = Select::new().expr(Expr::field("a") - (Expr::field("b") - Expr::literal("c'd")));
+    assert_eq!(nested.to_string(), r"SELECT a - (b - 'c\'d')");
+    assert_eq!(parse_select(&nested.to_string()).unwrap(), nested);
+
+    let db = people();
+    let unfiltered = Select { filter: None, ..select };
+    assert_eq!(db.select(&unfiltered).unwrap().rows, [vec![json!(-84)], vec![json!(-62)], vec![json!(-52)]]);
+}
@@ -0,0 +1,154 @@
+mod common;
+
+use common::{column, schema};
+use png_db::{
+    parse_field_path, parse_index_statement, CellPolicy, FieldPath, IndexKind, IndexStatement, Metadata, PngDatabase,
+    PngDbError,
+};
+use serde_json::{json, Value};
+use std::ops::Bound;
+
+fn staff() -> PngDatabase {
+    let mut db = PngDatabase::new(32, 32, schema(&[]));
+    let people = [
+        json!({"name": "Ada", "department": "Engineering", "age": 36, "address": {"city": "London"}}),
+        json!({"name": "Grace", "department": "Engineering", "age": 45, "address": {"city": "New York"}}),
+        json!({"name": "Linus", "department": "Operations", "age": 28.0}),
+        json!({"name": "Joan", "department": "Sales", "age": 52, "address": {"city": "London"}}),
+        json!({"name": "Ken"}),
+    ];
+    for (i, person) in people.into_iter().enumerate() {
+        db.insert(i as u32, 0, person).unwrap();
+    }
+    db
+}
+
+fn path(text: &str) -> FieldPath {
+    parse_field_path(text).unwrap()
+}
+
+const QUERIES: &[&str] = &[
+    "SELECT name WHERE department = 'Engineering'",
+    "SELECT name WHERE department IN ('Sales', 'Operations', 'Marketing')",
+    "SELECT name WHERE department >= 'F' AND age < 50",
+    "SELECT name WHERE age = 28",
+    "SELECT name WHERE age BETWEEN 30 AND 50 OR department = 'Sales'",
+    "SELECT name WHERE address.city = 'London' AND NOT age > 40",
+    "SELECT name WHERE age > 100",
+    "SELECT name WHERE department = 'Engineering' AND x >= 1",
+];
+
+#[test]
+fn indexes_agree_with_a_full_scan() {
+    let plain = staff();
+    for kind in [IndexKind::BTree, IndexKind::Hash] {
+        let mut db = staff();
+        for field in ["department", "age", "address.city"] {
+            db.create_index(path(field), kind).unwrap();
+        }
+        for query in QUERIES {
+            assert_eq!(column(&db, query), column(&plain, query), "{} ({})", query, kind);
+        }
+    }
+
+    let mut db = staff();
+    db.execute_index_statement("CREATE INDEX ON department").unwrap();
+    db.execute_index_statement("create index on age using hash").unwrap();
+    let department = &db.indexes()[0];
+    assert_eq!((department.kind(), department.len(), department.distinct()), (IndexKind::BTree, 4, 3));
+    assert_eq!(department.rows_equal(&json!("Engineering")), Some(vec![0, 1]));
+    assert_eq!(department.rows_in_range(Bound::Excluded(&json!("Engineering")), Bound::Unbounded), Some(vec![2, 3]));
+    assert_eq!(department.rows_in_range(Bound::Included(&json!("Z")), Bound::Excluded(&json!("A"))), Some(vec![]));
+
+    // A hash index finds 28.0 for 28, but cannot serve ranges
+    let age = &db.indexes()[1];
+    assert_eq!(age.rows_equal(&json!(28)), Some(vec![2]));
+    assert_eq!(age.rows_in_range(Bound::Included(&json!(30)), Bound::Unbounded), None);
+}
+
+#[test]
+fn indexes_follow_inserts_updates_and_deletes() {
+    let mut db = staff();
+    db.execute_index_statement("CREATE INDEX ON department").unwrap();
+    db.execute_index_statement("CREATE INDEX ON address.city USING HASH").unwrap();
+
+    db.insert(9, 9, json!({"name": "Barbara", "department": "Engineering", "address": {"city": "Boston"}})).unwrap();
+    assert_eq!(db.update("WHERE name = 'Grace'", json!({"department": "Research"})).unwrap(), 1);
+    assert_eq!(db.delete("WHERE name = 'Ada' OR name = 'Linus'").unwrap(), 2);
+    assert_eq!(column(&db, "SELECT name WHERE department = 'Engineering'"), [json!("Barbara")]);
+    assert_eq!(column(&db, "SELECT name WHERE department = 'Research'"), [json!("Grace")]);
+    assert_eq!(column(&db, "SELECT name WHERE address.city = 'London'"), [json!("Joan")]);
+
+    db.cell_policy = CellPolicy::Upsert;
+    db.insert(9, 9, json!({"name": "Barbara", "department": "Sales"})).unwrap();
+    assert_eq!(column(&db, "SELECT name WHERE department = 'Sales'"), [json!("Joan"), json!("Barbara")]);
+    assert_eq!(column(&db, "SELECT name WHERE address.city = 'Boston'"), Vec::<Value>::new());
+
+    db.drop_index(&path("address.city")).unwrap();
+    assert_eq!(db.indexes().len(), 1);
+
+    // Comparing a string with a number is still an error when the index is used
+    db.insert(10, 10, json!({"department": 7})).unwrap();
+    assert!(matches!(db.query("SELECT name WHERE department > 'A'"), Err(PngDbError::QueryError(_))));
+}
+
+#[test]
+fn indexes_are_stored_in_the_file() {
+    let mut db = staff();
+    db.execute_index_statement("CREATE INDEX ON age").unwrap();
+    db.execute_index_statement("CREATE INDEX ON address.city USING HASH").unwrap();
+    let mut bytes = Vec::new();
+    db.to_writer(&mut bytes).unwrap();
+
+    let loaded = PngDatabase::from_reader(bytes.as_slice()).unwrap();
+    let indexes: Vec<(String, IndexKind, usize)> = loaded.indexes().iter()
+        .map(|index| (index.field().to_string(), index.kind(), index.len()))
+        .collect();
+    assert_eq!(indexes, [("age".to_string(), IndexKind::BTree, 4), ("address.city".to_string(), IndexKind::Hash, 3)]);
+    assert_eq!(column(&loaded, "SELECT name WHERE age >= 45"), [json!("Grace"), json!("Joan")]);
+
+    // A writer that kept the chunk but changed the rows and the metadata: the
+    // definitions are kept and the indexes rebuilt
+    let reader = png::Decoder::new(bytes.as_slice()).read_info().unwrap();
+    let mut chunks: Vec<(String, String)> = reader.info().utf8_text.iter()
+        .map(|chunk| (chunk.keyword.clone(), chunk.get_text().unwrap()))
+        .collect();
+    let mut metadata: Metadata = serde_json::from_str(&chunks[0].1).unwrap();
+    metadata.modified_by = "png-db 0.0.9".to_string();
+    chunks[0].1 = serde_json::to_string(&metadata).unwrap();
+    let row = chunks.iter().position(|(keyword, _)| keyword == "row_4_0").unwrap();
+    chunks[row].1 = json!({"name": "Ken", "age": 61}).to_string();
+
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, 32, 32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    for (keyword, text) in chunks {
+        encoder.add_itxt_chunk(keyword, text).unwrap();
+    }
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&[0u8; 32 * 32 * 3]).unwrap();
+    writer.finish().unwrap();
+
+    let edited = PngDatabase::from_reader(bytes.as_slice()).unwrap();
+    assert_eq!(edited.indexes().len(), 2);
+    assert_eq!(column(&edited, "SELECT name WHERE age >= 45"), [json!("Grace"), json!("Joan"), json!("Ken")]);
+}
+
+#[test]
+fn index_statements() {
+    assert_eq!(
+        parse_index_statement("CREATE INDEX ON address.city USING hash").unwrap(),
+        IndexStatement::Create { field: path("address.city"), kind: IndexKind::Hash }
+    );
+    assert_eq!(parse_index_statement("DROP INDEX ON tags[0]").unwrap(), IndexStatement::Drop { field: path("tags[0]") });
+    for statement in ["CREATE INDEX department", "CREATE INDEX ON age USING bitmap", "DROP INDEX ON", "SELECT name"] {
+        assert!(matches!(parse_index_statement(statement), Err(PngDbError::ParseError { .. })), "{}", statement);
+    }
+
+    let mut db = staff();
+    db.execute_index_statement("CREATE INDEX ON department").unwrap();
+    for statement in ["CREATE INDEX ON department USING HASH", "CREATE INDEX ON x", "DROP INDEX ON age"] {
+        assert!(matches!(db.execute_index_statement(statement), Err(PngDbError::DatabaseError(_))), "{}", statement);
+    }
+}
@@ -0,0 +1,95 @@
+mod common;
+
+use common::{png_with_chunks, schema, TempPng};
+use png_db::format::format_timestamp;
+use png_db::{CellPolicy, Metadata, PngDatabase, FORMAT_VERSION};
+use serde_json::json;
+
+// Writes a PNG whose only text chunks are the given (keyword, text) iTXt pairs
+fn write_chunks(file: &TempPng, chunks: &[(&str, String)]) {
+    std::fs::write(file.path(), png_with_chunks(4, 4, chunks)).unwrap();
+}
+
+fn metadata_json(format_version: u32, features: &[&str]) -> String {
+    let mut metadata = Metadata::new();
+    metadata.format_version = format_version;
+    metadata.features = features.iter().map(|f| f.to_string()).collect();
+    serde_json::to_string(&metadata).unwrap()
+}
+
+#[test]
+fn new_files_record_current_format() {
+    let mut db = PngDatabase::new(8, 8, schema(&[]));
+    db.insert(1, 1, json!({"name": "a"})).unwrap();
+
+    let file = TempPng::new("format-current");
+    db.save_to_png(file.path()).unwrap();
+    let loaded = PngDatabase::load_from_png(file.path()).unwrap();
+
+    assert_eq!(loaded.metadata.format_version, FORMAT_VERSION);
+    assert!(loaded.metadata.is_current());
+    assert_eq!(loaded.metadata.created_by, format!("png-db {}", env!("CARGO_PKG_VERSION")));
+    assert_eq!(loaded.metadata.created_at, db.metadata.created_at);
+    assert!(loaded.metadata.modified_at >= loaded.metadata.created_at);
+    assert_eq!(loaded.metadata.features, ["itxt", "cell_policy"]);
+}
+
+#[test]
+fn files_without_metadata_load_as_format_1_and_migrate() {
+    let file = TempPng::new("format-legacy");
+    write_chunks(&file, &[
+        ("schema", r#"{"fields":{"name":"string"}}"#.to_string()),
+        ("row_2_3", r#"{"name":"a"}"#.to_string()),
+        ("row_2_3", r#"{"name":"b"}"#.to_string()),
+    ]);
+
+    let mut db = PngDatabase::load_from_png(file.path()).unwrap();
+    assert_eq!(db.metadata.format_version, 1);
+    assert!(!db.metadata.is_current());
+    assert_eq!(db.cell_policy, CellPolicy::Multi);
+
+    db.save_to_png(file.path()).unwrap();
+    let migrated = PngDatabase::load_from_png(file.path()).unwrap();
+    assert_eq!(migrated.metadata.format_version, FORMAT_VERSION);
+    assert_eq!(migrated.metadata.created_by, "unknown");
+    assert_eq!(migrated.cell_policy, CellPolicy::Multi);
+    assert_eq!(migrated.metadata.features, ["itxt", "cell_policy", "row_seq"]);
+    assert_eq!(migrated.rows.len(), 2);
+}
+
+#[test]
+fn current_files_without_config_use_default_cell_policy() {
+    let file = TempPng::new("format-no-config");
+    write_chunks(&file, &[
+        ("pngdb", metadata_json(FORMAT_VERSION, &[])),
+        ("schema", r#"{"fields":{}}"#.to_string()),
+    ]);
+
+    let db = PngDatabase::load_from_png(file.path()).unwrap();
+    assert_eq!(db.cell_policy, CellPolicy::Unique);
+}
+
+#[test]
+fn newer_formats_are_rejected() {
+    let file = TempPng::new("format-newer");
+    write_chunks(&file, &[("pngdb", metadata_json(FORMAT_VERSION + 1, &[]))]);
+
+    let err = PngDatabase::load_from_png(file.path()).err().unwrap();
+    assert!(err.to_string().contains(&format!("format v{}", FORMAT_VERSION + 1)), "{}", err);
+}
+
+#[test]
+fn unknown_features_are_rejected() {
+    let file = TempPng::new("format-feature");
+    write_chunks(&file, &[("pngdb", metadata_json(FORMAT_VERSION, &["itxt", "time_travel"]))]);
+
+    let err = PngDatabase::load_from_png(file.path()).err().unwrap();
+    assert!(err.to_string().contains("'time_travel'"), "{}", err);
+}
+
+#[test]
+fn timestamps_format_as_utc() {
+    assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+    assert_eq!(format_timestamp(951_827_696), "2000-02-29 12:34:56 UTC");
+    assert_eq!(format_timestamp(1_792_195_200), "2026-10-17 00:00:00 UTC");
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use common::{column, schema};
+use png_db::{parse_select, CellPolicy, DataRow, PngDatabase, Query, Select, Shape};
+use serde_json::{json, Value};
+
+fn tiles() -> PngDatabase {
+    let mut db = PngDatabase::new(512, 512, schema(&[]));
+    db.insert(120, 340, json!({"label": "tree", "kind": "plant"})).unwrap();
+    db.insert(125, 340, json!({"label": "rock", "kind": "stone"})).unwrap();
+    db.insert(120, 330, json!({"label": "bush", "kind": "plant"})).unwrap();
+    db.insert(100, 300, json!({"label": "pond", "kind": "water"})).unwrap();
+    db.insert(400, 400, json!({"label": "hill", "kind": "stone"})).unwrap();
+    db
+}
+
+fn labels(rows: &[&DataRow]) -> Vec<Value> {
+    rows.iter().map(|row| row.data["label"].clone()).collect()
+}
+
+#[test]
+fn nearest_with_and_without_filter() {
+    let db = tiles();
+    assert_eq!(labels(&db.nearest(121.0, 340.0, 3, None).unwrap()), [json!("tree"), json!("rock"), json!("bush")]);
+    assert_eq!(labels(&db.nearest(500.0, 500.0, 1, None).unwrap()), [json!("hill")]);
+
+    let stones = Query::field("kind").eq("stone");
+    assert_eq!(labels(&db.nearest(120.0, 340.0, 5, Some(&stones)).unwrap()), [json!("rock"), json!("hill")]);
+    assert!(db.nearest(120.0, 340.0, 0, None).unwrap().is_empty());
+}
+
+#[test]
+fn nearest_clause() {
+    let db = tiles();
+    assert_eq!(column(&db, "SELECT label WHERE kind = 'plant' NEAREST 5 TO (120, 340)"), [json!("tree"), json!("bush")]);
+    assert_eq!(column(&db, "SELECT label NEAREST 3 TO (110, 310) ORDER BY label"), [json!("bush"), json!("pond"), json!("tree")]);
+    assert_eq!(column(&db, "SELECT label NEAREST 4 TO (120, 340) LIMIT 2 OFFSET 1"), [json!("rock"), json!("bush")]);
+    assert_eq!(column(&db, "SELECT COUNT(*) NEAREST 4 TO (0, 0)"), [json!(4)]);
+
+    let select = Select::new().column("label").filter(Query::field("kind").ne("water")).nearest(2, 120.5, 340.0);
+    assert_eq!(select.to_string(), r#"SELECT label WHERE kind != "water" NEAREST 2 TO (120.5, 340)"#);
+    assert_eq!(parse_select(&select.to_string()).unwrap(), select);
+
+    assert!(db.query("SELECT label NEAREST 3 TO (1)").is_err());
+    assert!(db.query("SELECT label NEAREST -1 TO (1, 1)").is_err());
+}
+
+#[test]
+fn index_follows_inserts_and_deletes() {
+    let mut db = tiles();
+    assert_eq!(labels(&db.nearest(401.0, 401.0, 1, None).unwrap()), [json!("hill")]);
+    db.insert(401, 402, json!({"label": "cave"})).unwrap();
+    assert_eq!(labels(&db.nearest(401.0, 401.0, 1, None).unwrap()), [json!("cave")]);
+    db.delete("WHERE label = 'cave'").unwrap();
+    assert_eq!(labels(&db.nearest(401.0, 401.0, 1, None).unwrap()), [json!("hill")]);
+}
+
+#[test]
+fn index_agrees_with_a_full_scan() {
+    let mut db = PngDatabase::new(256, 256, schema(&[]));
+    db.cell_policy = CellPolicy::Multi;
+    let mut seed: u64 = 42;
+    let mut next = || {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (seed >> 33) as u32
+    };
+    for i in 0..500 {
+        db.insert(next() % 256, next() % 256, json!({ "i": i })).unwrap();
+    }
+
+    for &(x, y) in &[(0.0, 0.0), (128.0, 128.0), (255.0, 3.5), (-20.0, 300.0)] {
+        let mut expected: Vec<(f64, usize)> = db.rows.iter().enumerate()
+            .map(|(index, row)| ((row.x as f64 - x).hypot(row.y as f64 - y), index))
+            .collect();
+        expected.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+        let expected: Vec<usize> = expected.into_iter().take(25).map(|(_, index)| index).collect();
+        let found: Vec<usize> = db.spatial_index().nearest(x, y).take(25).collect();
+        assert_eq!(found, expected, "nearest to ({}, {})", x, y);
+    }
+
+    let shape = Shape::rect(40.0, 60.0, 90.0, 75.0);
+    let (min_x, min_y, max_x, max_y) = shape.bounds();
+    let mut found = db.spatial_index().within_bounds(min_x, min_y, max_x, max_y);
+    found.sort();
+    let expected: Vec<usize> = db.rows.iter().enumerate()
+        .filter(|(_, row)| shape.contains(row.x as f64, row.y as f64))
+        .map(|(index, _)| index)
+        .collect();
+    assert_eq!(found, expected);
+}
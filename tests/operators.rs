@@ -0,0 +1,149 @@
+mod common;
+
+use common::{column, schema};
+use png_db::{parse_query, PngDatabase, PngDbError, Query};
+use serde_json::{json, Value};
+
+fn people() -> PngDatabase {
+    let mut db = PngDatabase::new(64, 64, schema(&[]));
+    db.insert(10, 1, json!({"name": "Alice", "email": "alice@example.com", "manager": null})).unwrap();
+    db.insert(20, 2, json!({"name": "albert", "email": "al@example.org", "manager": "Alice"})).unwrap();
+    db.insert(30, 3, json!({"name": "Bob", "email": "bob@test.net"})).unwrap();
+    db.insert(40, 14, json!({"name": "Zoë 50%", "manager": "Bob"})).unwrap();
+    db
+}
+
+fn names(db: &PngDatabase, query: &str) -> Vec<Value> {
+    column(db, &format!("SELECT name {} ORDER BY x", query))
+}
+
+#[test]
+fn like_ilike_and_regex() {
+    let db = people();
+    assert_eq!(names(&db, "WHERE name LIKE 'Al%'"), [json!("Alice")]);
+    assert_eq!(names(&db, "WHERE name ILIKE 'al%'"), [json!("Alice"), json!("albert")]);
+    assert_eq!(names(&db, "WHERE name LIKE '_ob'"), [json!("Bob")]);
+    assert_eq!(names(&db, r#"WHERE name LIKE "%\%""#), [json!("Zoë 50%")]);
+    assert_eq!(names(&db, r#"WHERE email ~ "@example\.(com|org)$""#), [json!("Alice"), json!("albert")]);
+
+    assert!(matches!(db.query(r#"WHERE name ~ "(""#), Err(PngDbError::ParseError { position: 13, .. })));
+}
+
+#[test]
+fn in_and_between() {
+    let db = people();
+    assert_eq!(names(&db, r#"WHERE name IN ("Bob", 'Alice', 3)"#), [json!("Alice"), json!("Bob")]);
+    assert_eq!(names(&db, r#"WHERE name BETWEEN "B" AND "Zz""#), [json!("Bob"), json!("Zoë 50%")]);
+    assert_eq!(names(&db, r#"WHERE name BETWEEN "B" AND "Zz" AND x > 30"#), [json!("Zoë 50%")]);
+}
+
+#[test]
+fn null_and_missing_are_distinct() {
+    let db = people();
+    assert_eq!(names(&db, "WHERE manager IS NULL"), [json!("Alice")]);
+    assert_eq!(names(&db, "WHERE manager IS NOT NULL"), [json!("albert"), json!("Zoë 50%")]);
+    assert_eq!(names(&db, "WHERE manager IS MISSING"), [json!("Bob")]);
+    assert_eq!(names(&db, "WHERE manager IS NOT MISSING").len(), 3);
+}
+
+#[test]
+fn strings_order_lexicographically() {
+    let db = people();
+    assert_eq!(names(&db, r#"WHERE name < "a""#), [json!("Alice"), json!("Bob"), json!("Zoë 50%")]);
+    assert_eq!(names(&db, r#"WHERE name >= "a""#), [json!("albert")]);
+    assert_eq!(names(&db, r#"WHERE name > "Alice" AND name <= "Bob""#), [json!("Bob")]);
+}
+
+#[test]
+fn operators_apply_to_coordinates() {
+    let db = people();
+    assert_eq!(names(&db, "WHERE x IN (10, 30)"), [json!("Alice"), json!("Bob")]);
+    assert_eq!(names(&db, "WHERE x BETWEEN 15 AND 35"), [json!("albert"), json!("Bob")]);
+    assert_eq!(names(&db, "WHERE y LIKE '1%'"), [json!("Alice"), json!("Zoë 50%")]);
+    assert_eq!(names(&db, r#"WHERE y ~ "^[0-9]$""#).len(), 3);
+    assert_eq!(names(&db, "WHERE x IS NOT MISSING").len(), 4);
+}
+
+#[test]
+fn builder_renders_and_reparses() {
+    let query = Query::field("name").ilike("al%")
+        .and(Query::field("x").between(10, 20))
+        .and(Query::field("tag").is_in(["a", "b"]))
+        .and(Query::field("manager").is_not_null())
+        .and(Query::field("email").matches("^a"));
+    let text = query.to_where_clause();
+    assert_eq!(text, r#"WHERE name ILIKE "al%" AND x BETWEEN 10 AND 20 AND tag IN ("a", "b") AND manager IS NOT NULL AND email ~ "^a""#);
+    assert_eq!(parse_query(&text).unwrap(), query);
+}
+
+#[test]
+fn having_supports_the_new_operators() {
+    let db = people();
+    let results = db.query("SELECT manager, COUNT(*) AS n GROUP BY manager HAVING n BETWEEN 1 AND 1 AND manager IS NOT NULL ORDER BY manager").unwrap();
+    assert_eq!(results.rows, vec![vec![json!("Alice"), json!(1)], vec![json!("Bob"), json!(1)]]);
+
+    let results = db.query("SELECT COUNT(*) HAVING COUNT(*) IN (4)").unwrap();
+    assert_eq!(results.rows, vec![vec![json!(4)]]);
+}
+
+#[test]
+fn equality_of_numbers_agrees_across_operators() {
+    let mut db = PngDatabase::new(8, 8, schema(&[]));
+    db.insert(1, 1, json!({"name": "sum", "v": 0.1 + 0.2, "vs": [0.1 + 0.2], "at": {"v": 0.1 + 0.2}})).unwrap();
+    db.insert(2, 1, json!({"name": "other", "v": 0.4, "vs": [0.4], "at": {"v": 0.4}})).unwrap();
+    for query in [
+        "WHERE v = 0.3",
+        "WHERE v IN (0.3, 1)",
+        "WHERE vs CONTAINS 0.3",
+        "WHERE ANY(vs) IN (0.3)",
+        "WHERE vs = [0.3]",
+        r#"WHERE at = {"v": 0.3}"#,
+        r#"WHERE at @> '{"v": 0.3}'"#,
+    ] {
+        assert_eq!(names(&db, query), [json!("sum")], "{}", query);
+    }
+    assert_eq!(names(&db, "WHERE v != 0.3"), [json!("other")]);
+}
+
+#[test]
+fn numbers_take_signed_exponents() {
+    let mut db = PngDatabase::new(8, 8, schema(&[]));
+    db.insert(1, 1, json!({"name": "tiny", "score": 0.000001})).unwrap();
+    db.insert(2, 1, json!({"name": "large", "score": 2500})).unwrap();
+    assert_eq!(names(&db, "WHERE score < 1e-5"), [json!("tiny")]);
+    assert_eq!(names(&db, "WHERE score = 2.5E+3"), [json!("large")]);
+    assert_eq!(names(&db, "WHERE score >= 25e2"), [json!("large")]);
+
+    // Without an exponent, a sign after a number is still arithmetic
+    assert_eq!(names(&db, "WHERE score - 1e3 = 1500"), [json!("large")]);
+    assert_eq!(names(&db, "WHERE 3e3-score = 500"), [json!("large")]);
+}
+
+#[test]
+fn strings_take_every_json_escape() {
+    let mut db = PngDatabase::new(16, 1, schema(&[]));
+    let escapes = [
+        (r#"\"q\""#, "\"q\""),
+        (r"\'", "'"),
+        (r"a\\b", "a\\b"),
+        (r"a\/b", "a/b"),
+        (r"\b", "\u{8}"),
+        (r"\f", "\u{c}"),
+        (r"\n", "\n"),
+        (r"\r", "\r"),
+        (r"\t", "\t"),
+        (r"\u00e9", "é"),
+        (r"\uD83D\uDE00", "😀"),
+    ];
+    for (x, (_, text)) in escapes.iter().enumerate() {
+        db.insert(x as u32, 0, json!({ "name": text })).unwrap();
+    }
+    for (escaped, text) in escapes {
+        assert_eq!(names(&db, &format!(r#"WHERE name = "{}""#, escaped)), [json!(text)], "{}", escaped);
+    }
+
+    // A surrogate must be half of a pair
+    for lone in [r"\uD83D", r"\uD83Dx", r"\uDE00", r"\uD83DA"] {
+        assert!(matches!(db.query(&format!(r#"WHERE name = "{}""#, lone)), Err(PngDbError::ParseError { .. })), "{}", lone);
+    }
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use common::{column, schema};
+use png_db::{parse_field_path, FieldPath, PngDatabase};
+use serde_json::{json, Value};
+
+fn customers() -> PngDatabase {
+    let mut db = PngDatabase::new(16, 16, schema(&[]));
+    db.insert(1, 1, json!({
+        "name": "Ada",
+        "address": {"city": "London", "zip code": "N1"},
+        "tags": ["vip", "early"],
+        "orders": [{"total": 20}, {"total": 35}, {"total": 12}],
+        "meta": {"a.b": 1},
+        "first name": "Augusta",
+    })).unwrap();
+    db.insert(2, 1, json!({
+        "name": "Grace",
+        "address": {"city": "New York"},
+        "tags": ["early"],
+        "orders": [{"total": 50}],
+    })).unwrap();
+    db.insert(3, 1, json!({"name": "Ken", "address": "unknown", "tags": []})).unwrap();
+    db
+}
+
+#[test]
+fn nested_keys_and_array_indexes_in_where() {
+    let db = customers();
+    assert_eq!(column(&db, "SELECT name WHERE address.city = 'London'"), [json!("Ada")]);
+    assert_eq!(column(&db, "SELECT name WHERE tags[0] = 'early'"), [json!("Grace")]);
+    assert_eq!(column(&db, "SELECT name WHERE orders[2].total < 15"), [json!("Ada")]);
+    assert_eq!(column(&db, "SELECT name WHERE orders[0].total >= 20"), [json!("Ada"), json!("Grace")]);
+
+    // Paths that do not resolve, through a string, past the end of an array or
+    // into a missing key, never match
+    assert!(column(&db, "SELECT name WHERE address.city.name = 'London'").is_empty());
+    assert!(column(&db, "SELECT name WHERE tags[5] = 'vip' OR orders[1].missing = 1").is_empty());
+    assert_eq!(column(&db, "SELECT name WHERE NOT tags[1] = 'early'"), [json!("Grace"), json!("Ken")]);
+}
+
+#[test]
+fn quoted_keys() {
+    let db = customers();
+    assert_eq!(column(&db, r#"SELECT name WHERE "first name" = 'Augusta'"#), [json!("Ada")]);
+    assert_eq!(column(&db, r#"SELECT name WHERE address."zip code" = 'N1'"#), [json!("Ada")]);
+    assert_eq!(column(&db, r#"SELECT name WHERE meta["a.b"] = 1"#), [json!("Ada")]);
+    assert!(column(&db, "SELECT name WHERE meta.a.b = 1").is_empty());
+}
+
+#[test]
+fn paths_in_select_group_by_and_order_by() {
+    let db = customers();
+    let results = db.query(r#"SELECT name, address.city, orders[0].total AS first, "first name" ORDER BY orders[0].total DESC"#).unwrap();
+    assert_eq!(results.columns, ["name", "address.city", "first", "\"first name\""]);
+    assert_eq!(results.rows, [
+        vec![json!("Grace"), json!("New York"), json!(50), Value::Null],
+        vec![json!("Ada"), json!("London"), json!(20), json!("Augusta")],
+        vec![json!("Ken"), Value::Null, Value::Null, Value::Null],
+    ]);
+
+    let results = db.query("SELECT tags[0], COUNT(*) AS n GROUP BY tags[0] HAVING tags[0] IS NOT MISSING ORDER BY tags[0]").unwrap();
+    assert_eq!(results.rows, [vec![json!("early"), json!(1)], vec![json!("vip"), json!(1)]]);
+}
+
+#[test]
+fn paths_parse_and_render_back() {
+    let path = FieldPath::key("orders").index(2).child("total");
+    assert_eq!(parse_field_path("orders[2].total").unwrap(), path);
+    assert_eq!(path.to_string(), "orders[2].total");
+
+    let quoted = FieldPath::key("address").child("zip code").child("a.b");
+    assert_eq!(quoted.to_string(), r#"address."zip code"."a.b""#);
+    assert_eq!(parse_field_path(&quoted.to_string()).unwrap(), quoted);
+    assert_eq!(parse_field_path(r#"address["zip code"]["a.b"]"#).unwrap(), quoted);
+
+    for bad in ["orders[", "orders[-1]", "orders[x]", "address.", ".city", r#"address."zip"#] {
+        assert!(parse_field_path(bad).is_err(), "{}", bad);
+    }
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use common::schema;
+use png_db::{parse_field_path, parse_select, Access, ColumnStats, FieldPath, IndexKind, PngDatabase};
+use serde_json::json;
+
+// 100 rows on a 10 x 10 grid, spread over five departments
+fn office() -> PngDatabase {
+    let mut db = PngDatabase::new(10, 10, schema(&[]));
+    for i in 0..100u32 {
+        db.insert(i % 10, i / 10, json!({"department": format!("d{}", i % 5), "age": i, "name": format!("n{}", i)})).unwrap();
+    }
+    db.execute_index_statement("CREATE INDEX ON department").unwrap();
+    db.execute_index_statement("CREATE INDEX ON age").unwrap();
+    db
+}
+
+fn field(text: &str) -> FieldPath {
+    parse_field_path(text).unwrap()
+}
+
+#[test]
+fn chooses_the_cheapest_access_path() {
+    let db = office();
+    let plan = |query: &str| {
+        let plan = db.explain(query).unwrap();
+        (plan.access, plan.estimated_rows)
+    };
+
+    assert_eq!(plan("WHERE x = 3"), (Access::CoordinateIndex { bounds: (3.0, f64::NEG_INFINITY, 3.0, f64::INFINITY) }, 10));
+    assert_eq!(plan("SELECT name WHERE WITHIN RECT(0, 0, 1, 1)"), (Access::SpatialIndex { bounds: (0.0, 0.0, 1.0, 1.0) }, 4));
+    assert_eq!(plan("WHERE department = 'd1'"), (Access::FieldIndex { fields: vec![field("department")] }, 20));
+    assert_eq!(plan("WHERE age >= 90 AND department IN ('d1', 'd2')"), (Access::FieldIndex { fields: vec![field("age"), field("department")] }, 9));
+    assert_eq!(plan("WHERE department = 'd1' AND y BETWEEN 4 AND 4").0, Access::CoordinateIndex { bounds: (f64::NEG_INFINITY, 4.0, f64::INFINITY, 4.0) });
+    assert_eq!(plan("SELECT name NEAREST 3 TO (5, 5)"), (Access::Nearest { k: 3, x: 5.0, y: 5.0 }, 3));
+
+    // Nothing to gain from an index: a full scan
+    for query in ["WHERE name LIKE 'n1%'", "WHERE NOT department = 'd1'", "WHERE x >= 0", "WHERE department = 'd1' OR x = 3", "SELECT COUNT(*)"] {
+        assert_eq!(plan(query), (Access::FullScan, 100), "{}", query);
+    }
+}
+
+#[test]
+fn plans_give_the_same_rows_as_a_full_scan() {
+    let indexed = office();
+    let mut plain = PngDatabase::new(10, 10, schema(&[]));
+    plain.rows = indexed.rows.clone();
+
+    for query in [
+        "SELECT name WHERE department = 'd1' AND y BETWEEN 4 AND 5",
+        "SELECT name WHERE age >= 90 AND department IN ('d1', 'd2')",
+        "SELECT name WHERE (age < 3 OR age > 97) AND WITHIN RADIUS(0, 0, 2)",
+        "SELECT name WHERE age BETWEEN 10.5 AND 12",
+        "SELECT name WHERE department > 'd3' ORDER BY age DESC LIMIT 4",
+    ] {
+        assert_eq!(indexed.query(query).unwrap().rows, plain.query(query).unwrap().rows, "{}", query);
+    }
+
+    let mut updated = office();
+    assert_eq!(updated.update("WHERE department = 'd4' AND age > 90", json!({"department": "d9"})).unwrap(), 2);
+    assert_eq!(updated.delete("WHERE x = 0").unwrap(), 10);
+    assert_eq!(updated.query("SELECT name WHERE department = 'd9'").unwrap().rows, [vec![json!("n94")], vec![json!("n99")]]);
+}
+
+#[test]
+fn explain_output() {
+    let db = office();
+    let plan = db.explain("EXPLAIN SELECT name WHERE department = 'd1' AND x <= 3").unwrap();
+    assert_eq!(plan.to_string(), "field index on department: estimated 20 of 100 row(s)");
+    let considered: Vec<String> = plan.considered.iter().map(|(access, rows)| format!("{}: {}", access, rows)).collect();
+    assert_eq!(considered, ["full scan: 100", "coordinate index (x <= 3): 40", "field index on department: 20"]);
+    assert_eq!(db.plan(&parse_select("SELECT name WHERE x >= 2 AND x <= 4").unwrap()).access.to_string(), "coordinate index (2 <= x <= 4)");
+
+    assert!(db.explain("EXPLAIN DELETE").is_err());
+
+    assert_eq!(db.column_stats(&field("x")), Some(ColumnStats { rows: 100, distinct: None, min: Some(0.0), max: Some(9.0) }));
+    assert_eq!(db.column_stats(&field("age")), Some(ColumnStats { rows: 100, distinct: Some(100), min: Some(0.0), max: Some(99.0) }));
+    assert_eq!(db.column_stats(&field("name")), None);
+
+    let mut hashed = office();
+    hashed.drop_index(&field("age")).unwrap();
+    hashed.create_index(field("age"), IndexKind::Hash).unwrap();
+    assert_eq!(hashed.explain("WHERE age > 50").unwrap().access, Access::FullScan);
+    assert_eq!(hashed.explain("WHERE age = 50").unwrap().estimated_rows, 1);
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use common::people;
+use png_db::{Param, PngDbError, PreparedQuery};
+use serde_json::{json, Map, Value};
+
+fn named(value: Value) -> Map<String, Value> {
+    value.as_object().unwrap().clone()
+}
+
+#[test]
+fn positional_parameters_are_bound_in_order() {
+    let db = people();
+    let prepared = PreparedQuery::new("SELECT name WHERE team = ? AND age >= ? ORDER BY name").unwrap();
+    assert_eq!(prepared.params(), [&Param::Positional(0), &Param::Positional(1)]);
+
+    let results = db.execute(&prepared, &[json!("core"), json!(40)]).unwrap();
+    assert_eq!(results.rows, vec![vec![json!("Grace")]]);
+
+    let results = db.execute(&prepared, &[json!("core"), json!(0)]).unwrap();
+    assert_eq!(results.rows, vec![vec![json!("Ada")], vec![json!("Grace")]]);
+}
+
+#[test]
+fn bound_values_are_not_parsed_as_syntax() {
+    let db = people();
+    let prepared = PreparedQuery::new("SELECT x, y WHERE name = ?").unwrap();
+
+    let results = db.execute(&prepared, &[json!("O\"Brien")]).unwrap();
+    assert_eq!(results.rows, vec![vec![json!(9), json!(3)]]);
+
+    let results = db.execute(&prepared, &[json!("Ada\" OR name != \"")]).unwrap();
+    assert!(results.is_empty());
+}
+
+#[test]
+fn named_parameters_can_repeat() {
+    let db = people();
+    let prepared = PreparedQuery::new("WHERE age < :limit OR x = :limit").unwrap();
+    assert_eq!(prepared.params(), [&Param::Named("limit".to_string())]);
+    assert_eq!(prepared.to_string(), "SELECT * WHERE age < :limit OR x = :limit");
+
+    let results = db.execute_named(&prepared, &named(json!({"limit": 5}))).unwrap();
+    assert_eq!(results.len(), 1);
+
+    let results = db.execute_named(&prepared, &named(json!({"limit": 21}))).unwrap();
+    assert_eq!(results.len(), 1);
+    assert!(results.rows[0].contains(&json!("O\"Brien")));
+}
+
+#[test]
+fn coordinate_and_having_parameters() {
+    let db = people();
+    let prepared = PreparedQuery::new("SELECT team, COUNT(*) GROUP BY team HAVING COUNT(*) >= ?").unwrap();
+    let results = db.execute(&prepared, &[json!(2)]).unwrap();
+    assert_eq!(results.rows, vec![vec![json!("core"), json!(2)]]);
+
+    let prepared = PreparedQuery::new("SELECT name WHERE x > ?").unwrap();
+    let results = db.execute(&prepared, &[json!(4)]).unwrap();
+    assert_eq!(results.len(), 2);
+    assert!(matches!(db.execute(&prepared, &[json!("4")]), Err(PngDbError::QueryError(_))));
+}
+
+#[test]
+fn binding_errors() {
+    let db = people();
+    let positional = PreparedQuery::new("WHERE age > ?").unwrap();
+    let named_query = PreparedQuery::new("WHERE age > :min").unwrap();
+
+    assert!(matches!(db.execute(&positional, &[]), Err(PngDbError::QueryError(_))));
+    assert!(matches!(db.execute(&positional, &[json!(1), json!(2)]), Err(PngDbError::QueryError(_))));
+    assert!(matches!(db.execute_named(&positional, &named(json!({"min": 1}))), Err(PngDbError::QueryError(_))));
+    assert!(matches!(db.execute_named(&named_query, &Map::new()), Err(PngDbError::QueryError(_))));
+    assert!(matches!(db.execute_named(&named_query, &named(json!({"min": 1, "max": 2}))), Err(PngDbError::QueryError(_))));
+
+    assert!(matches!(PreparedQuery::new("WHERE a = ? AND b = :b"), Err(PngDbError::ParseError { .. })));
+    assert!(matches!(db.query("WHERE age > ?"), Err(PngDbError::ParseError { .. })));
+}
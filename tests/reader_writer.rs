@@ -0,0 +1,53 @@
+mod common;
+
+use common::{schema, TempPng};
+use png_db::PngDatabase;
+use serde_json::json;
+use std::io::Cursor;
+
+fn sample_db() -> PngDatabase {
+    let mut db = PngDatabase::new(32, 16, schema(&[("name", "string")]));
+    db.insert(1, 2, json!({"name": "Ada"})).unwrap();
+    db.insert(30, 15, json!({"name": "Grace"})).unwrap();
+    db
+}
+
+#[test]
+fn in_memory_buffers_round_trip() {
+    let mut db = sample_db();
+
+    let mut buf = Vec::new();
+    db.to_writer(&mut buf).unwrap();
+    let loaded = PngDatabase::from_reader(Cursor::new(&buf)).unwrap();
+
+    assert_eq!((loaded.width, loaded.height), (32, 16));
+    assert_eq!(loaded.schema.fields, db.schema.fields);
+    assert_eq!(loaded.rows.len(), 2);
+    assert_eq!(loaded.rows[1].data, json!({"name": "Grace"}));
+}
+
+#[test]
+fn files_and_buffers_are_interchangeable() {
+    let mut db = sample_db();
+    let file = TempPng::new("reader-writer");
+    let path_str = file.path();
+
+    db.save_to_png(path_str).unwrap();
+    let bytes = std::fs::read(path_str).unwrap();
+    let mut from_bytes = PngDatabase::from_reader(bytes.as_slice()).unwrap();
+    assert_eq!(from_bytes.rows.len(), 2);
+
+    let mut buf = Vec::new();
+    from_bytes.to_writer(&mut buf).unwrap();
+    std::fs::write(path_str, &buf).unwrap();
+    let from_file = PngDatabase::load_from_png(path_str).unwrap();
+    assert_eq!(from_file.rows[0].data, json!({"name": "Ada"}));
+}
+
+#[test]
+fn truncated_input_is_an_error() {
+    let mut buf = Vec::new();
+    sample_db().to_writer(&mut buf).unwrap();
+
+    assert!(PngDatabase::from_reader(&buf[..20]).is_err());
+}
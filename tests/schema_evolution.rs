@@ -0,0 +1,136 @@
+mod common;
+
+use common::{encode, schema};
+use png_db::{ConversionRule, PngDatabase, PngDbError};
+use serde_json::{json, Value};
+
+fn staff() -> PngDatabase {
+    let mut db = PngDatabase::new(8, 8, schema(&[("name", "string!"), ("age", "any")]));
+    db.insert(1, 1, json!({"name": "Ada", "age": "36"})).unwrap();
+    db.insert(2, 2, json!({"name": "Grace", "age": 85, "team": "core"})).unwrap();
+    db.insert(3, 3, json!({"name": "Linus", "age": "unknown"})).unwrap();
+    db.insert(4, 4, json!({"name": "Ken"})).unwrap();
+    db
+}
+
+fn rows(db: &PngDatabase) -> Vec<Value> {
+    db.rows.iter().map(|row| row.data.clone()).collect()
+}
+
+// Checks that a failed change left the rows, the schema and its version as they were
+fn assert_rolled_back(db: &PngDatabase, before: &PngDatabase) {
+    assert_eq!(rows(db), rows(before));
+    assert_eq!(db.schema.fields, before.schema.fields);
+    assert_eq!(db.schema.version, before.schema.version);
+}
+
+#[test]
+fn each_change_bumps_the_version() {
+    let mut db = staff();
+    assert_eq!(db.schema.version, 1);
+
+    assert_eq!(db.add_column("team", "string", json!("none")).unwrap(), 3);
+    assert_eq!(db.rename_column("name", "full_name").unwrap(), 4);
+    assert_eq!(db.drop_column("team").unwrap(), 4);
+    assert_eq!(db.change_column_type("age", "integer?", ConversionRule::SetNull).unwrap(), 2);
+    assert_eq!(db.schema.version, 5);
+
+    assert_eq!(rows(&db), [
+        json!({"full_name": "Ada", "age": 36}),
+        json!({"full_name": "Grace", "age": 85}),
+        json!({"full_name": "Linus", "age": null}),
+        json!({"full_name": "Ken"}),
+    ]);
+    assert_eq!(db.schema.fields.get("full_name").map(String::as_str), Some("string!"));
+    assert!(!db.schema.fields.contains_key("name"));
+
+    // The version is stored with the schema
+    let loaded = PngDatabase::from_reader(encode(&mut db).as_slice()).unwrap();
+    assert_eq!(loaded.schema.version, 5);
+    assert_eq!(loaded.schema.fields, db.schema.fields);
+}
+
+#[test]
+fn conversion_rules_handle_unconvertible_values() {
+    let convert = |rule: ConversionRule| {
+        let mut db = staff();
+        db.change_column_type("age", "integer", rule).map(|_| db.rows[2].data.clone())
+    };
+    assert_eq!(convert(ConversionRule::SetNull).ok(), None);
+    assert_eq!(convert(ConversionRule::Remove).unwrap(), json!({"name": "Linus"}));
+    assert_eq!(convert(ConversionRule::Default(json!(0))).unwrap(), json!({"name": "Linus", "age": 0}));
+
+    let mut db = staff();
+    assert_eq!(db.change_column_type("age", "integer?", ConversionRule::SetNull).unwrap(), 2);
+    assert_eq!(db.rows[2].data, json!({"name": "Linus", "age": null}));
+}
+
+#[test]
+fn failed_changes_are_rolled_back() {
+    let before = staff();
+
+    // The first row converts before the third fails; neither is kept
+    let mut db = staff();
+    let Err(PngDbError::SchemaViolation { row, violations }) = db.change_column_type("age", "integer", ConversionRule::Fail) else {
+        panic!("an unconvertible value was accepted");
+    };
+    assert_eq!((row, violations[0].field.as_str()), (Some((3, 3)), "age"));
+    assert_rolled_back(&db, &before);
+
+    // Values that convert to null are refused by a type that is not nullable
+    assert!(matches!(db.change_column_type("age", "integer", ConversionRule::SetNull), Err(PngDbError::SchemaViolation { .. })));
+    assert_rolled_back(&db, &before);
+
+    // A rename onto a field that some row already has
+    db.insert(5, 5, json!({"name": "Joan", "team": "sales"})).unwrap();
+    let before = PngDatabase::from_reader(encode(&mut db).as_slice()).unwrap();
+    assert!(matches!(db.rename_column("age", "team"), Err(PngDbError::SchemaViolation { row: Some((2, 2)), .. })));
+    assert_rolled_back(&db, &before);
+
+    // A default that the existing value does not match
+    db.insert(6, 6, json!({"name": "Barbara", "active": "yes"})).unwrap();
+    let before = PngDatabase::from_reader(encode(&mut db).as_slice()).unwrap();
+    assert!(matches!(db.add_column("active", "boolean", json!(true)), Err(PngDbError::SchemaViolation { row: Some((6, 6)), .. })));
+    assert_rolled_back(&db, &before);
+}
+
+#[test]
+fn rows_that_are_not_rewritten_must_still_fit() {
+    let before = staff();
+    let mut db = staff();
+
+    // Required columns need a default, or every row to have them already
+    assert!(matches!(db.add_column("team", "string!", Value::Null), Err(PngDbError::SchemaViolation { row: Some((1, 1)), .. })));
+    assert_rolled_back(&db, &before);
+    assert!(matches!(db.change_column_type("age", "any!", ConversionRule::Fail), Err(PngDbError::SchemaViolation { row: Some((4, 4)), .. })));
+    assert_rolled_back(&db, &before);
+
+    db.delete("WHERE name = 'Ken'").unwrap();
+    assert_eq!(db.change_column_type("age", "any!", ConversionRule::Fail).unwrap(), 0);
+    assert_eq!(db.schema.version, 2);
+}
+
+#[test]
+fn invalid_changes_are_schema_errors() {
+    let before = staff();
+    let mut db = staff();
+    assert!(matches!(db.add_column("name", "string", Value::Null), Err(PngDbError::SchemaError(_))));
+    assert!(matches!(db.add_column("level", "integer", json!("high")), Err(PngDbError::SchemaError(_))));
+    assert!(matches!(db.add_column("level", "level", Value::Null), Err(PngDbError::SchemaError(_))));
+    assert!(matches!(db.drop_column("team"), Err(PngDbError::SchemaError(_))));
+    assert!(matches!(db.rename_column("team", "group"), Err(PngDbError::SchemaError(_))));
+    assert!(matches!(db.rename_column("name", "age"), Err(PngDbError::SchemaError(_))));
+    assert!(matches!(db.change_column_type("level", "integer", ConversionRule::Fail), Err(PngDbError::SchemaError(_))));
+    assert_rolled_back(&db, &before);
+}
+
+#[test]
+fn rows_that_are_not_objects_must_still_fit() {
+    let mut db = PngDatabase::new(8, 8, schema(&[]));
+    db.insert(1, 1, json!({"name": "Ada"})).unwrap();
+    db.insert(2, 2, json!(5)).unwrap();
+    let before = PngDatabase::from_reader(encode(&mut db).as_slice()).unwrap();
+
+    assert!(matches!(db.add_column("name", "string", Value::Null), Err(PngDbError::SchemaError(_))));
+    assert_rolled_back(&db, &before);
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use common::schema;
+use png_db::{FieldKind, FieldType, PngDatabase, PngDbError, Violation};
+use serde_json::{json, Value};
+
+// The fields named in the violations an insert is refused with, or none
+fn violations(db: &mut PngDatabase, data: Value) -> Vec<String> {
+    match db.insert(0, 0, data) {
+        Ok(()) => {
+            db.delete("WHERE x = 0 AND y = 0").unwrap();
+            Vec::new()
+        }
+        Err(PngDbError::SchemaViolation { violations, .. }) => violations.into_iter().map(|v| v.field).collect(),
+        Err(other) => panic!("unexpected error: {:?}", other),
+    }
+}
+
+#[test]
+fn each_type_accepts_its_values() {
+    let samples = [json!("text"), json!(3), json!(-2.5), json!(true), Value::Null, json!([1]), json!({"a": 1})];
+    let accepted: [(&str, [bool; 7]); 9] = [
+        ("string", [true, false, false, false, false, false, false]),
+        ("integer", [false, true, false, false, false, false, false]),
+        ("float", [false, true, true, false, false, false, false]),
+        ("number", [false, true, true, false, false, false, false]),
+        ("boolean", [false, false, false, true, false, false, false]),
+        ("null", [false, false, false, false, true, false, false]),
+        ("array", [false, false, false, false, false, true, false]),
+        ("object", [false, false, false, false, false, false, true]),
+        ("any", [true; 7]),
+    ];
+    for (type_name, expected) in accepted {
+        let mut db = PngDatabase::new(4, 4, schema(&[("v", type_name)]));
+        for (sample, accepts) in samples.iter().zip(expected) {
+            let refused = violations(&mut db, json!({ "v": sample }));
+            assert_eq!(refused.is_empty(), accepts, "{} with {}", type_name, sample);
+        }
+    }
+}
+
+#[test]
+fn markers_make_fields_nullable_or_required() {
+    let mut db = PngDatabase::new(4, 4, schema(&[
+        ("plain", "integer"),
+        ("nullable", "integer?"),
+        ("required", "integer!"),
+        ("both", "integer?!"),
+    ]));
+    let valid = json!({"required": 1, "both": 2});
+
+    assert!(violations(&mut db, valid.clone()).is_empty());
+    assert!(violations(&mut db, json!({"required": 1, "both": null, "nullable": null})).is_empty());
+    assert_eq!(violations(&mut db, json!({"plain": null, "required": null, "both": 2})), ["plain", "required"]);
+    assert_eq!(violations(&mut db, json!({"nullable": 1})), ["both", "required"]);
+
+    // Fields outside the schema are allowed
+    let mut extra = valid;
+    extra["other"] = json!("anything");
+    assert!(violations(&mut db, extra).is_empty());
+}
+
+#[test]
+fn types_parse_with_aliases_and_markers_in_either_order() {
+    let parsed: FieldType = "Int!?".parse().unwrap();
+    assert_eq!(parsed, FieldType { kind: FieldKind::Integer, nullable: true, required: true });
+    assert_eq!(parsed.to_string(), "integer?!");
+    assert_eq!("str".parse::<FieldType>().unwrap().kind, FieldKind::String);
+    assert_eq!("bool?".parse::<FieldType>().unwrap().to_string(), "boolean?");
+    assert!("date".parse::<FieldType>().is_err());
+
+    let mut db = PngDatabase::new(4, 4, schema(&[("when", "date")]));
+    assert!(matches!(db.insert(0, 0, json!({"when": 1})), Err(PngDbError::SchemaError(_))));
+}
+
+#[test]
+fn updates_are_validated_like_inserts() {
+    let mut db = PngDatabase::new(4, 4, schema(&[("name", "string!"), ("age", "integer?")]));
+    db.insert(1, 1, json!({"name": "Ada", "age": 36})).unwrap();
+
+    db.update("WHERE name = 'Ada'", json!({"age": null})).unwrap();
+    assert_eq!(db.rows[0].data, json!({"name": "Ada"}));
+    let Err(PngDbError::SchemaViolation { row, violations }) = db.update("WHERE x = 1", json!({"name": null})) else {
+        panic!("removing a required field was allowed");
+    };
+    assert_eq!(row, Some((1, 1)));
+    assert_eq!(violations, [Violation::new("name", "is required but missing")]);
+    assert!(matches!(db.insert(2, 2, json!(["not", "an", "object"])), Err(PngDbError::SchemaError(_))));
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use common::{column, people, schema};
+use png_db::{PngDatabase, PngDbError};
+use serde_json::{json, Value};
+
+#[test]
+fn projection_keeps_the_column_order_and_fills_missing_fields() {
+    let mut db = people();
+    db.insert(2, 7, json!({"name": "Linus"})).unwrap();
+
+    let results = db.query("SELECT age, name FROM staff WHERE team = 'core' OR x = 2").unwrap();
+    assert_eq!(results.columns, ["age", "name"]);
+    assert_eq!(results.rows, [
+        vec![json!(36), json!("Ada")],
+        vec![json!(85), json!("Grace")],
+        vec![Value::Null, json!("Linus")],
+    ]);
+
+    let results = db.query("SELECT name, x, name AS again WHERE age < 30").unwrap();
+    assert_eq!(results.columns, ["name", "x", "again"]);
+    assert_eq!(results.rows, [vec![json!("O\"Brien"), json!(9), json!("O\"Brien")]]);
+}
+
+#[test]
+fn star_expands_to_coordinates_then_fields_by_name() {
+    let mut db = PngDatabase::new(8, 8, schema(&[("zone", "string?")]));
+    db.insert(1, 2, json!({"name": "Ada", "age": 36})).unwrap();
+
+    let results = db.query("SELECT *").unwrap();
+    assert_eq!(results.columns, ["x", "y", "age", "name", "zone"]);
+    assert_eq!(results.rows, [vec![json!(1), json!(2), json!(36), json!("Ada"), Value::Null]]);
+    let bare = db.query("WHERE age = 36").unwrap();
+    assert_eq!((bare.columns, bare.rows), (results.columns, results.rows));
+}
+
+#[test]
+fn order_by_sorts_on_each_key_in_turn() {
+    let db = people();
+    assert_eq!(column(&db, "SELECT name ORDER BY team DESC, age"), [json!("O\"Brien"), json!("Ada"), json!("Grace")]);
+    assert_eq!(column(&db, "SELECT name ORDER BY team, age DESC"), [json!("Grace"), json!("Ada"), json!("O\"Brien")]);
+    assert_eq!(column(&db, "SELECT name ORDER BY y DESC"), [json!("O\"Brien"), json!("Grace"), json!("Ada")]);
+
+    // Nulls, then booleans, numbers, strings, arrays and objects
+    let mut db = PngDatabase::new(8, 8, schema(&[]));
+    for (x, value) in [json!({"k": 1}), json!("b"), json!([1]), json!(2), json!(true), Value::Null, json!("a")].into_iter().enumerate() {
+        db.insert(x as u32, 0, json!({ "v": value })).unwrap();
+    }
+    db.insert(7, 0, json!({})).unwrap();
+    assert_eq!(column(&db, "SELECT x ORDER BY v, x"), [json!(5), json!(7), json!(4), json!(3), json!(6), json!(1), json!(2), json!(0)]);
+    assert_eq!(column(&db, "SELECT x ORDER BY v DESC, x LIMIT 2"), [json!(0), json!(2)]);
+}
+
+#[test]
+fn limit_and_offset_page_through_the_sorted_rows() {
+    let db = people();
+    assert_eq!(column(&db, "SELECT name ORDER BY age LIMIT 2"), [json!("O\"Brien"), json!("Ada")]);
+    assert_eq!(column(&db, "SELECT name ORDER BY age LIMIT 2 OFFSET 1"), [json!("Ada"), json!("Grace")]);
+    assert_eq!(column(&db, "SELECT name ORDER BY age OFFSET 2"), [json!("Grace")]);
+    assert!(column(&db, "SELECT name ORDER BY age LIMIT 5 OFFSET 3").is_empty());
+    assert!(column(&db, "SELECT name LIMIT 0").is_empty());
+}
+
+#[test]
+fn malformed_statements_are_parse_errors() {
+    let db = people();
+    for query in ["SELECT", "SELECT name,", "SELECT name ORDER age", "SELECT name LIMIT -1", "SELECT name LIMIT two", "SELECT name OFFSET"] {
+        assert!(matches!(db.query(query), Err(PngDbError::ParseError { .. })), "{}", query);
+    }
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use common::{column, schema};
+use png_db::{parse_query, PngDatabase, PngDbError, Query, Shape};
+use serde_json::json;
+
+fn grid() -> PngDatabase {
+    let mut db = PngDatabase::new(64, 64, schema(&[]));
+    for (x, y, name) in [(0, 0, "origin"), (5, 5, "centre"), (10, 0, "east"), (3, 4, "near"), (20, 20, "far")] {
+        db.insert(x, y, json!({ "name": name })).unwrap();
+    }
+    db
+}
+
+#[test]
+fn rect_radius_and_polygon() {
+    let db = grid();
+    assert_eq!(column(&db, "SELECT name WHERE WITHIN RECT(0, 0, 5, 5)"), [json!("origin"), json!("centre"), json!("near")]);
+    assert_eq!(column(&db, "SELECT name WHERE within rect(10, 5, 3, 0)"), [json!("centre"), json!("east"), json!("near")]);
+
+    // The boundary is inside: (3, 4) is exactly 5 from the origin
+    assert_eq!(column(&db, "SELECT name WHERE WITHIN RADIUS(0, 0, 5)"), [json!("origin"), json!("near")]);
+    assert_eq!(column(&db, "SELECT name WHERE WITHIN RADIUS(20, 20, 0.5)"), [json!("far")]);
+
+    // A triangle with (0, 0), (10, 0) and (5, 5) on its corners and (3, 4) outside
+    assert_eq!(
+        column(&db, "SELECT name WHERE WITHIN POLYGON(0, 0, 10, 0, 5, 5)"),
+        [json!("origin"), json!("centre"), json!("east")]
+    );
+    assert_eq!(column(&db, "SELECT name WHERE NOT WITHIN RECT(0, 0, 10, 10) OR name = 'east'"), [json!("east"), json!("far")]);
+}
+
+#[test]
+fn order_by_distance() {
+    let db = grid();
+    assert_eq!(
+        column(&db, "SELECT name WHERE WITHIN RADIUS(5, 5, 8) ORDER BY distance(x, y, 5, 5), name"),
+        [json!("centre"), json!("near"), json!("east"), json!("origin")]
+    );
+    let result = db.query("SELECT name, round(distance(x, y, 0, 0), 2) AS d ORDER BY d DESC LIMIT 1").unwrap();
+    assert_eq!(result.rows, [vec![json!("far"), json!(28.28)]]);
+}
+
+#[test]
+fn shapes_from_rust() {
+    let db = grid();
+    let query = Query::within(Shape::polygon(vec![(0.0, 0.0), (10.0, 0.0), (5.0, 5.0)])).and(Query::field("x").gt(0));
+    assert_eq!(query.to_where_clause(), "WHERE WITHIN POLYGON(0, 0, 10, 0, 5, 5) AND x > 0");
+    assert_eq!(parse_query(&query.to_where_clause()).unwrap(), query);
+
+    let shape: Shape = "RADIUS(0, 0, 5)".parse().unwrap();
+    assert_eq!(shape, Shape::radius(0.0, 0.0, 5.0));
+    assert_eq!(shape.bounds(), (-5.0, -5.0, 5.0, 5.0));
+    let cells: Vec<(u32, u32)> = db.within(&shape).iter().map(|row| (row.x, row.y)).collect();
+    assert_eq!(cells, [(0, 0), (3, 4)]);
+}
+
+#[test]
+fn invalid_shapes() {
+    for text in ["RECT(0, 0, 5)", "RADIUS(0, 0, -1)", "POLYGON(0, 0, 1, 1)", "POLYGON(0, 0, 1, 1, 2)", "CIRCLE(0, 0, 1)", "RECT(0, 0, 'a', 1)"] {
+        assert!(matches!(text.parse::<Shape>(), Err(PngDbError::ParseError { .. })), "{}", text);
+    }
+    assert!(matches!(grid().query("SELECT name WHERE WITHIN RECT(0, 0, 1, 1"), Err(PngDbError::ParseError { .. })));
+
+    // A field called `within` can still be compared
+    let mut db = PngDatabase::new(4, 4, schema(&[]));
+    db.insert(1, 1, json!({ "within": 3 })).unwrap();
+    assert_eq!(db.query("SELECT x WHERE within = 3").unwrap().len(), 1);
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use common::{column, encode, png_with_chunks, schema, text_chunks};
+use png_db::{Metadata, PngDatabase, Shape};
+use serde_json::json;
+
+// Re-encodes the chunks the way a writer that does not know the spatial index
+// might: same chunks, possibly edited, index chunk carried along untouched
+fn reencode(chunks: &[(String, String)]) -> Vec<u8> {
+    png_with_chunks(16, 16, chunks)
+}
+
+fn places() -> PngDatabase {
+    let mut db = PngDatabase::new(16, 16, schema(&[]));
+    db.persist_spatial_index = true;
+    for (x, y) in [(1, 1), (2, 9), (8, 8), (12, 3), (15, 15)] {
+        db.insert(x, y, json!({ "at": format!("{},{}", x, y) })).unwrap();
+    }
+    db
+}
+
+#[test]
+fn index_is_stored_only_when_asked() {
+    let mut db = places();
+    let keywords = |db: &mut PngDatabase| -> Vec<String> { text_chunks(&encode(db)).into_iter().map(|(keyword, _)| keyword).collect() };
+    assert!(keywords(&mut db).contains(&"spatial_index".to_string()));
+
+    let loaded = PngDatabase::from_reader(encode(&mut db).as_slice()).unwrap();
+    assert!(loaded.persist_spatial_index);
+    assert_eq!(loaded.spatial_index().len(), 5);
+    assert_eq!(column(&loaded, "SELECT at WHERE WITHIN RADIUS(8, 8, 7)"), [json!("2,9"), json!("8,8"), json!("12,3")]);
+    assert_eq!(column(&loaded, "SELECT at NEAREST 1 TO (14, 14)"), [json!("15,15")]);
+
+    db.persist_spatial_index = false;
+    assert!(!keywords(&mut db).contains(&"spatial_index".to_string()));
+}
+
+#[test]
+fn coordinate_conditions_use_the_index() {
+    let mut db = PngDatabase::from_reader(encode(&mut places()).as_slice()).unwrap();
+    assert_eq!(column(&db, "SELECT at WHERE x >= 8 AND y < 10"), [json!("8,8"), json!("12,3")]);
+    assert_eq!(column(&db, "SELECT at WHERE x BETWEEN 1 AND 2 OR WITHIN RECT(14, 14, 16, 16)"), [json!("1,1"), json!("2,9"), json!("15,15")]);
+    assert_eq!(column(&db, "SELECT at WHERE NOT x > 2"), [json!("1,1"), json!("2,9")]);
+    assert_eq!(db.within(&Shape::polygon(vec![(0.0, 0.0), (16.0, 0.0), (0.0, 16.0)])).len(), 4);
+
+    assert_eq!(db.update("WHERE y = 8", json!({ "centre": true })).unwrap(), 1);
+    assert_eq!(db.delete("WHERE WITHIN RECT(0, 0, 4, 16)").unwrap(), 2);
+    assert_eq!(column(&db, "SELECT at WHERE centre = true"), [json!("8,8")]);
+    assert_eq!(column(&db, "SELECT at NEAREST 1 TO (0, 0)"), [json!("8,8")]);
+}
+
+#[test]
+fn stale_index_is_rebuilt() {
+    let chunks = text_chunks(&encode(&mut places()));
+
+    // An older writer added a row and restamped the metadata but kept the index chunk
+    let mut edited = chunks.clone();
+    let mut metadata: Metadata = serde_json::from_str(&edited[0].1).unwrap();
+    metadata.modified_by = "png-db 0.0.9".to_string();
+    edited[0].1 = serde_json::to_string(&metadata).unwrap();
+    edited.push(("row_14_14".to_string(), json!({ "at": "14,14" }).to_string()));
+    let loaded = PngDatabase::from_reader(reencode(&edited).as_slice()).unwrap();
+    assert_eq!(loaded.spatial_index().len(), 6);
+    assert_eq!(column(&loaded, "SELECT at NEAREST 1 TO (14, 13)"), [json!("14,14")]);
+
+    // Same stamp, but a row was moved
+    let mut moved = chunks.clone();
+    let row = moved.iter().position(|(keyword, _)| keyword == "row_15_15").unwrap();
+    moved[row].0 = "row_0_15".to_string();
+    let loaded = PngDatabase::from_reader(reencode(&moved).as_slice()).unwrap();
+    assert_eq!(column(&loaded, "SELECT at WHERE WITHIN RECT(0, 12, 3, 15)"), [json!("15,15")]);
+
+    // A damaged index chunk is ignored rather than an error
+    let mut damaged = chunks;
+    let index = damaged.iter().position(|(keyword, _)| keyword == "spatial_index").unwrap();
+    damaged[index].1 = "{not json".to_string();
+    let loaded = PngDatabase::from_reader(reencode(&damaged).as_slice()).unwrap();
+    assert_eq!(column(&loaded, "SELECT at WHERE x = 8"), [json!("8,8")]);
+}
@@ -0,0 +1,153 @@
+mod common;
+
+use common::schema;
+use png_db::{PngDatabase, PngDbError};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct User {
+    name: String,
+    age: u32,
+    email: Option<String>,
+}
+
+fn users() -> PngDatabase {
+    PngDatabase::new(16, 16, schema(&[("name", "string!"), ("age", "integer!"), ("email", "string?")]))
+}
+
+fn ada() -> User {
+    User { name: "Ada".to_string(), age: 36, email: Some("ada@example.com".to_string()) }
+}
+
+fn grace() -> User {
+    User { name: "Grace".to_string(), age: 85, email: None }
+}
+
+#[test]
+fn structs_round_trip_through_rows() {
+    let mut db = users();
+    db.insert_as(1, 1, &ada()).unwrap();
+    db.insert_as(2, 2, &grace()).unwrap();
+
+    assert_eq!(db.rows[1].data, json!({"name": "Grace", "age": 85, "email": null}));
+    assert_eq!(db.rows_as::<User>().unwrap(), vec![ada(), grace()]);
+}
+
+#[test]
+fn query_results_deserialize_by_column_name() {
+    let mut db = users();
+    db.insert_as(1, 1, &ada()).unwrap();
+    db.insert_as(2, 2, &grace()).unwrap();
+
+    let older: Vec<User> = db.query_as("WHERE age > 50").unwrap();
+    assert_eq!(older, vec![grace()]);
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Located {
+        x: u32,
+        y: u32,
+        who: String,
+    }
+    let located: Vec<Located> = db.query_as("SELECT x, y, name AS who ORDER BY age DESC").unwrap();
+    assert_eq!(located[0], Located { x: 2, y: 2, who: "Grace".to_string() });
+}
+
+#[test]
+fn schema_rules_still_apply_to_typed_inserts() {
+    #[derive(Serialize)]
+    struct Anonymous {
+        age: u32,
+    }
+
+    let mut db = users();
+    assert!(matches!(db.insert_as(0, 0, &Anonymous { age: 3 }), Err(PngDbError::SchemaViolation { .. })));
+}
+
+#[test]
+fn mismatched_rows_report_where_they_are() {
+    let mut db = PngDatabase::new(8, 8, schema(&[]));
+    db.insert(3, 4, json!({"name": "Ada"})).unwrap();
+
+    match db.rows_as::<User>() {
+        Err(PngDbError::DeserializeError { row, type_name, .. }) => {
+            assert_eq!(row, Some((3, 4)));
+            assert!(type_name.ends_with("User"));
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[cfg(feature = "derive")]
+mod derive {
+    use png_db::{HasSchema, PngDatabase};
+    use serde::{Deserialize, Serialize};
+    use std::collections::{BTreeMap, HashMap};
+
+    #[derive(Serialize, Deserialize, HasSchema)]
+    #[serde(rename_all = "camelCase")]
+    struct Sensor {
+        device_name: String,
+        reading: f64,
+        count: u64,
+        active: bool,
+        tags: Vec<String>,
+        labels: BTreeMap<String, String>,
+        location: Option<(f64, f64)>,
+        #[serde(default)]
+        note: String,
+        #[serde(rename = "raw")]
+        payload: serde_json::Value,
+        #[serde(skip)]
+        cache: Vec<u8>,
+        #[png_db(type = "object")]
+        nested: Nested,
+    }
+
+    #[derive(Serialize, Deserialize, Default)]
+    struct Nested {
+        level: u8,
+    }
+
+    fn expected(fields: &[(&str, &str)]) -> HashMap<String, String> {
+        fields.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn derived_schema_maps_rust_types() {
+        assert_eq!(Sensor::schema().fields, expected(&[
+            ("deviceName", "string!"),
+            ("reading", "float!"),
+            ("count", "integer!"),
+            ("active", "boolean!"),
+            ("tags", "array!"),
+            ("labels", "object!"),
+            ("location", "array?"),
+            ("note", "string"),
+            ("raw", "any!"),
+            ("nested", "object"),
+        ]));
+    }
+
+    #[test]
+    fn derived_schema_accepts_its_own_rows() {
+        let mut db = PngDatabase::new(4, 4, Sensor::schema());
+        let sensor = Sensor {
+            device_name: "probe".to_string(),
+            reading: 21.5,
+            count: 3,
+            active: true,
+            tags: vec!["lab".to_string()],
+            labels: BTreeMap::new(),
+            location: None,
+            note: String::new(),
+            payload: serde_json::json!([1, 2]),
+            cache: vec![1],
+            nested: Nested::default(),
+        };
+        db.insert_as(0, 0, &sensor).unwrap();
+        let rows: Vec<Sensor> = db.rows_as().unwrap();
+        assert_eq!(rows[0].device_name, "probe");
+        assert!(rows[0].cache.is_empty());
+    }
+}
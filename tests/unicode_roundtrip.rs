@@ -0,0 +1,109 @@
+mod common;
+
+use common::{schema, TempPng};
+use png_db::{CellPolicy, PngDatabase};
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::BufWriter;
+
+fn round_trip(name: &str, db: &mut PngDatabase) -> PngDatabase {
+    let file = TempPng::new(name);
+    db.save_to_png(file.path()).unwrap();
+    PngDatabase::load_from_png(file.path()).unwrap()
+}
+
+#[test]
+fn japanese_and_emoji_rows_round_trip() {
+    let mut db = PngDatabase::new(64, 64, schema(&[("name", "string"), ("note", "string")]));
+    let rows = [
+        json!({"name": "山田太郎", "note": "こんにちは世界"}),
+        json!({"name": "Zoë 🦀", "note": "🎉🚀✨ emoji everywhere 👩‍👩‍👧"}),
+        json!({"name": "Ελληνικά", "note": "Привет, мир — العربية"}),
+    ];
+    for (i, data) in rows.iter().enumerate() {
+        db.insert(i as u32, i as u32, data.clone()).unwrap();
+    }
+
+    let loaded = round_trip("unicode-rows", &mut db);
+
+    assert_eq!(loaded.rows.len(), rows.len());
+    for (row, expected) in loaded.rows.iter().zip(&rows) {
+        assert_eq!(&row.data, expected);
+    }
+}
+
+#[test]
+fn non_ascii_keys_and_schema_round_trip() {
+    let mut db = PngDatabase::new(16, 16, schema(&[("名前", "string!"), ("年齢", "integer")]));
+    db.insert(1, 2, json!({"名前": "佐藤", "年齢": 41, "住所": {"都市": "東京"}})).unwrap();
+
+    let loaded = round_trip("unicode-schema", &mut db);
+
+    assert_eq!(loaded.schema.fields.get("名前").map(String::as_str), Some("string!"));
+    assert_eq!(loaded.schema.fields.get("年齢").map(String::as_str), Some("integer"));
+    assert_eq!(loaded.rows[0].data["住所"]["都市"], json!("東京"));
+}
+
+#[test]
+fn non_ascii_values_are_queryable_after_reload() {
+    let mut db = PngDatabase::new(16, 16, schema(&[("名前", "string")]));
+    db.insert(3, 4, json!({"名前": "山田太郎"})).unwrap();
+    db.insert(5, 6, json!({"名前": "🦀"})).unwrap();
+
+    let loaded = round_trip("unicode-query", &mut db);
+
+    let results = loaded.query(r#"SELECT x, y WHERE 名前 = "山田太郎""#).unwrap();
+    assert_eq!(results.rows, vec![vec![json!(3), json!(4)]]);
+
+    let results = loaded.query(r#"WHERE 名前 = "🦀""#).unwrap();
+    assert_eq!(results.len(), 1);
+}
+
+#[test]
+fn rows_are_written_as_compressed_itxt_chunks() {
+    let mut db = PngDatabase::new(8, 8, schema(&[("name", "string")]));
+    db.insert(0, 0, json!({"name": "絵文字 😀"})).unwrap();
+
+    let file = TempPng::new("unicode-itxt");
+    db.save_to_png(file.path()).unwrap();
+
+    let decoder = png::Decoder::new(File::open(file.path()).unwrap());
+    let reader = decoder.read_info().unwrap();
+    let info = reader.info();
+
+    assert!(info.compressed_latin1_text.is_empty());
+    let keywords: Vec<&str> = info.utf8_text.iter().map(|chunk| chunk.keyword.as_str()).collect();
+    assert_eq!(keywords, ["pngdb", "schema", "config", "row_0_0"]);
+    // All but the metadata, which appends rewrite in place
+    assert!(!info.utf8_text[0].compressed);
+    assert!(info.utf8_text[1..].iter().all(|chunk| chunk.compressed));
+}
+
+#[test]
+fn legacy_ztxt_files_still_load() {
+    let file = TempPng::new("legacy-ztxt");
+    {
+        let writer = BufWriter::new(File::create(file.path()).unwrap());
+        let mut encoder = png::Encoder::new(writer, 4, 4);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.add_ztxt_chunk("schema".to_string(), r#"{"fields":{"name":"string"}}"#.to_string()).unwrap();
+        encoder.add_ztxt_chunk("row_1_1".to_string(), r#"{"name":"Zoë"}"#.to_string()).unwrap();
+        encoder.add_ztxt_chunk("row_1_1".to_string(), r#"{"name":"Noël"}"#.to_string()).unwrap();
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[0u8; 4 * 4 * 3]).unwrap();
+        writer.finish().unwrap();
+    }
+
+    let mut db = PngDatabase::load_from_png(file.path()).unwrap();
+
+    assert_eq!(db.schema.version, 1);
+    assert_eq!(db.cell_policy, CellPolicy::Multi);
+    let names: Vec<&Value> = db.rows.iter().map(|row| &row.data["name"]).collect();
+    assert_eq!(names, [&json!("Zoë"), &json!("Noël")]);
+
+    // Re-saving upgrades the file to iTXt without losing the Latin-1 text
+    let upgraded = round_trip("legacy-ztxt-upgraded", &mut db);
+    assert_eq!(upgraded.rows[1].data, json!({"name": "Noël"}));
+    assert_eq!(upgraded.rows[1].seq, 1);
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use common::{column, encode, people, schema};
+use png_db::{PngDatabase, PngDbError};
+use serde_json::json;
+
+#[test]
+fn updates_merge_the_patch_into_matching_rows() {
+    let mut db = PngDatabase::new(8, 8, schema(&[]));
+    db.insert(1, 1, json!({"name": "Ada", "address": {"city": "London", "zip": "N1"}, "tags": ["a", "b"], "active": true})).unwrap();
+    db.insert(2, 2, json!({"name": "Grace", "active": true})).unwrap();
+
+    let patch = json!({"address": {"city": "Paris", "zip": null, "country": "FR"}, "tags": ["c"], "active": null, "age": 36});
+    assert_eq!(db.update("WHERE name = 'Ada'", patch).unwrap(), 1);
+
+    // Objects merge key by key, null removes a key, anything else replaces it
+    assert_eq!(db.rows[0].data, json!({"name": "Ada", "address": {"city": "Paris", "country": "FR"}, "tags": ["c"], "age": 36}));
+    assert_eq!((db.rows[0].x, db.rows[0].y), (1, 1));
+    assert_eq!(db.rows[1].data, json!({"name": "Grace", "active": true}));
+
+    // A patch that adds an object where there was a scalar replaces it
+    assert_eq!(db.update("WHERE x = 2", json!({"active": {"since": 2020}})).unwrap(), 1);
+    assert_eq!(db.rows[1].data["active"], json!({"since": 2020}));
+}
+
+#[test]
+fn updates_report_how_many_rows_changed() {
+    let mut db = people();
+    assert_eq!(db.update("WHERE team = 'core'", json!({"team": "platform"})).unwrap(), 2);
+    assert_eq!(db.update("WHERE team = 'core'", json!({"team": "none"})).unwrap(), 0);
+    assert_eq!(column(&db, "SELECT team ORDER BY x"), [json!("platform"), json!("platform"), json!("web")]);
+}
+
+#[test]
+fn updates_are_all_or_nothing() {
+    let mut db = PngDatabase::new(8, 8, schema(&[("age", "integer")]));
+    db.insert(1, 1, json!({"name": "Ada", "age": 36})).unwrap();
+    db.insert(2, 2, json!({"name": "Grace", "age": 85})).unwrap();
+    let before: Vec<_> = db.rows.iter().map(|row| row.data.clone()).collect();
+
+    assert!(matches!(db.update("WHERE age > 0", json!({"age": "old"})), Err(PngDbError::SchemaViolation { row: Some((1, 1)), .. })));
+    assert!(matches!(db.update("WHERE age >", json!({"age": 1})), Err(PngDbError::ParseError { .. })));
+    assert_eq!(db.rows.iter().map(|row| row.data.clone()).collect::<Vec<_>>(), before);
+}
+
+#[test]
+fn deletes_remove_matching_rows() {
+    let mut db = people();
+    assert_eq!(db.delete("WHERE age > 30 AND team = 'core' AND NOT name = 'Ada'").unwrap(), 1);
+    assert_eq!(db.delete("WHERE name = 'Nobody'").unwrap(), 0);
+    assert!(db.delete("WHERE name =").is_err());
+    assert_eq!(column(&db, "SELECT name"), [json!("Ada"), json!("O\"Brien")]);
+
+    // The freed cell takes a new row, and the deletion survives a save
+    db.insert(5, 2, json!({"name": "Linus"})).unwrap();
+    let loaded = PngDatabase::from_reader(encode(&mut db).as_slice()).unwrap();
+    assert_eq!(column(&loaded, "SELECT name ORDER BY x"), [json!("Ada"), json!("Linus"), json!("O\"Brien")]);
+
+    assert_eq!(db.delete("WHERE x >= 0").unwrap(), 3);
+    assert!(db.rows.is_empty());
+}
@@ -193,24 +193,29 @@ window.queryData = async function() {
 
         const whereClause = document.getElementById('queryWhere').value.trim();
         if (!whereClause) {
-            throw new Error('WHERE clause is required');
+            throw new Error('Query is required');
         }
 
         const resultsJson = currentDatabase.query(whereClause);
         const results = JSON.parse(resultsJson);
 
-        if (results.length === 0) {
+        if (results.rows.length === 0) {
             showResults('No results found');
         } else {
-            let html = `<h3>Found ${results.length} result${results.length === 1 ? '' : 's'}:</h3>`;
-            results.forEach(row => {
-                html += `
-                    <div class="result-item">
-                        <strong>Position (${row.x}, ${row.y}):</strong>
-                        <pre>${JSON.stringify(row.data, null, 2)}</pre>
-                    </div>
-                `;
+            let html = `<h3>Found ${results.rows.length} result${results.rows.length === 1 ? '' : 's'}:</h3>`;
+            html += '<table class="result-table"><thead><tr>';
+            results.columns.forEach(column => {
+                html += `<th>${column}</th>`;
+            });
+            html += '</tr></thead><tbody>';
+            results.rows.forEach(row => {
+                html += '<tr>';
+                row.forEach(value => {
+                    html += `<td><code>${JSON.stringify(value)}</code></td>`;
+                });
+                html += '</tr>';
             });
+            html += '</tbody></table>';
             showResults(html);
         }
 